@@ -28,6 +28,7 @@ just call it
 
 Acceleration structure to use with BVH.
 */
+#[allow(clippy::upper_case_acronyms)]
 pub struct AABB {
   pub min: Point3d,
   pub max: Point3d,
@@ -52,20 +53,22 @@ impl AABB {
   }
 
   pub fn from_point_cloud(points: &[Point3d]) -> AABB {
-    if points.len() < 1 {
+    if points.is_empty() {
       panic!("Cannot create AABB from point cloud of 0 length")
     }
-    let mut min = points[0].clone();
-    let mut max = points[0].clone();
+    let mut min = points[0];
+    let mut max = points[0];
 
     for p in points {
-      min = point_min(&min, &p);
-      max = point_max(&max, &p);
+      min = point_min(&min, p);
+      max = point_max(&max, p);
     }
 
     AABB { min, max }
   }
 
+  #[allow(dead_code)]
+  /** BVH uses its own version, that reuses values precomputed for the ray */
   pub fn check_intersection(&self, r: &Ray, t_min_: f32, t_max_: f32) -> bool {
     let mut t_min = t_min_;
     let mut t_max = t_max_;
@@ -98,7 +101,7 @@ impl AABB {
     true
   }
 
-  pub fn to_points(&self) -> [Point3d; 8] {
+  pub fn to_points(self) -> [Point3d; 8] {
     let min = self.min;
     let max = self.max;
     [
@@ -117,4 +120,14 @@ impl AABB {
   pub fn dims(&self) -> Vec3 {
     self.max - self.min
   }
+
+  pub fn centroid(&self) -> Point3d {
+    (self.min + self.max) * 0.5
+  }
+
+  /** Used by the Surface Area Heuristic, as probability of a ray hitting the box is proportional to it */
+  pub fn surface_area(&self) -> f32 {
+    let d = self.max - self.min;
+    2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+  }
 }
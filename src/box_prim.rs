@@ -9,7 +9,7 @@ use crate::rectangle::Rectangle;
 use crate::traceable::{RayHit, Traceable};
 use crate::transform::Transform;
 use crate::vec3::{Point3d, Vec3};
-use crate::world::World;
+use crate::world::{World, WorldObjectsList};
 
 #[derive(Clone, Debug)]
 /**
@@ -99,4 +99,8 @@ impl Traceable for BoxPrim {
   fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
     self.side_planes.check_intersection(r, t_min, t_max)
   }
+
+  fn lights(&self) -> WorldObjectsList {
+    self.side_planes.lights()
+  }
 }
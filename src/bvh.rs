@@ -1,134 +1,561 @@
-use rand::Rng;
+use log::info;
 use std::sync::Arc;
 
 use crate::aabb::AABB;
 use crate::ray::Ray;
 use crate::traceable::{RayHit, Traceable};
-use crate::vec3::Vec3;
-use crate::world::{World, WorldObjectsList};
+use crate::vec3::Point3d;
+use crate::world::{collect_lights, World, WorldObjectsList};
+
+/** Number of buckets the centroids are sorted into when looking for the best split */
+const SAH_BINS: usize = 12;
+/** Nodes with this many objects or fewer can become leaves */
+const MAX_LEAF_SIZE: usize = 4;
+/** Cost of visiting a node, relative to the cost of intersecting a single object */
+const TRAVERSAL_COST: f32 = 1.0;
+
+/** Max depth of the tree, so that traversal can use fixed-size stack */
+const MAX_DEPTH: usize = 64;
 
 /**
-Bounding Volume Hierarchy node. Used to split World space into groups, to easier discard
+Bounding Volume Hierarchy. Used to split World space into groups, to easier discard
 a lot of objects at once. Form of acceleration structure.
+
+Built using binned Surface Area Heuristic (SAH). The split is chosen so that
+the expected cost of tracing a ray through the node is smallest. The probability
+of hitting a child is proportional to its surface area. The build does not use
+any randomness, so the same objects always produce the same tree.
+
+Nodes are stored in a single array in depth-first order. First child of
+the inner node is always right after it, so only the second child's index is stored.
+Objects are reordered so that each leaf references a continuous range.
+Objects that do not have a bounding box are kept in a separate list.
+
+https://www.pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies
 */
-pub struct BVHNode {
-  aabb: AABB,
-  child_left: Arc<dyn Traceable>,
-  child_right: Arc<dyn Traceable>,
+#[allow(clippy::upper_case_acronyms)]
+pub struct BVH {
+  /** Empty if there are no bounded objects */
+  nodes: Vec<LinearBVHNode>,
+  objects: WorldObjectsList,
+  /** Objects without bounding box e.g. infinite planes. Tested for every ray */
+  unbounded: WorldObjectsList,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+/** 32 bytes, so that 2 nodes fit in a cache line */
+struct LinearBVHNode {
+  /** AABB as [min, max] */
+  bounds: [[f32; 3]; 2],
+  /** Leaf: index of the first object. Inner node: index of the second child */
+  offset: u32,
+  /** 0 for inner nodes */
+  object_count: u16,
+  /** Axis the inner node was split on */
+  axis: u8,
+  _pad: u8,
 }
 
-impl BVHNode {
-  pub fn build(world: &World) -> BVHNode {
-    BVHNode::build_impl(&world.objects, 0, world.objects.len())
+impl LinearBVHNode {
+  /** Slab test, with values precomputed once per ray */
+  #[inline]
+  fn intersects(&self, ray: &RayData, t_min: f32, t_max: f32) -> bool {
+    let mut t_min = t_min;
+    let mut t_max = t_max;
+    for axis in 0..3 {
+      let t0 = (self.bounds[ray.dir_is_neg[axis]][axis] - ray.origin[axis]) * ray.inv_dir[axis];
+      let t1 = (self.bounds[1 - ray.dir_is_neg[axis]][axis] - ray.origin[axis]) * ray.inv_dir[axis];
+      t_min = t0.max(t_min);
+      t_max = t1.min(t_max);
+      if t_max <= t_min {
+        return false;
+      }
+    }
+    true
   }
+}
 
-  fn build_impl(world_objects: &WorldObjectsList, start_idx: usize, end_idx: usize) -> BVHNode {
-    let mut node = BVHNode::subdivide_into_bvh(world_objects, start_idx, end_idx);
+/** Values that are the same for every node the ray visits */
+struct RayData {
+  origin: [f32; 3],
+  inv_dir: [f32; 3],
+  /** 1 if direction on axis is negative, used to select near/far plane */
+  dir_is_neg: [usize; 3],
+}
 
-    // recalc aabb
-    match (
-      node.child_left.bounding_box(),
-      node.child_right.bounding_box(),
-    ) {
-      (Some(bb_left), Some(bb_right)) => {
-        node.aabb = AABB::merge(&bb_left, &bb_right);
-      }
-      _ => {
-        panic!("Tried to create BVH, but some objects do not have bounding box");
-      }
-    };
+impl RayData {
+  fn new(r: &Ray) -> RayData {
+    let inv_dir = [1.0 / r.dir[0], 1.0 / r.dir[1], 1.0 / r.dir[2]];
+    RayData {
+      origin: r.origin.e,
+      inv_dir,
+      dir_is_neg: [
+        (inv_dir[0] < 0.0) as usize,
+        (inv_dir[1] < 0.0) as usize,
+        (inv_dir[2] < 0.0) as usize,
+      ],
+    }
+  }
+}
+
+/** Object with precomputed values used during build */
+#[derive(Clone)]
+struct BuildItem {
+  object: Arc<dyn Traceable>,
+  aabb: AABB,
+  centroid: Point3d,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+  aabb: Option<AABB>,
+  count: usize,
+}
 
-    node
+impl BVH {
+  pub fn build(world: &World) -> BVH {
+    let bvh = BVH::from_objects(&world.objects);
+    let (nodes, leaves, depth) = bvh.stats();
+    info!(
+      "BVH: {} objects ({} unbounded), {} nodes, {} leaves, depth {}",
+      world.objects.len(),
+      bvh.unbounded.len(),
+      nodes,
+      leaves,
+      depth
+    );
+    bvh
   }
 
-  /**
-  Given object list, subdivide into recursive BVH nodes.
-  This fn returns left/right children, please recalc AABB after!
-  */
-  fn subdivide_into_bvh(
-    world_objects: &WorldObjectsList,
-    start_idx: usize,
-    end_idx: usize,
-  ) -> BVHNode {
-    let objects_count = end_idx - start_idx;
-    let mock_aabb = AABB {
-      min: Vec3::zero(),
-      max: Vec3::one(),
+  pub fn from_objects(objects: &WorldObjectsList) -> BVH {
+    let mut items: Vec<BuildItem> = Vec::with_capacity(objects.len());
+    let mut unbounded: WorldObjectsList = Vec::new();
+    for object in objects {
+      match object.bounding_box() {
+        Some(aabb) => items.push(BuildItem {
+          object: object.clone(),
+          aabb,
+          centroid: aabb.centroid(),
+        }),
+        None => unbounded.push(object.clone()),
+      }
+    }
+
+    let mut bvh = BVH {
+      nodes: Vec::with_capacity(2 * items.len()),
+      objects: Vec::with_capacity(items.len()),
+      unbounded,
     };
+    if !items.is_empty() {
+      bvh.build_impl(&mut items, 0);
+    }
+    bvh
+  }
+
+  /** Append node (and all its children) for the items. Returns index of the node */
+  fn build_impl(&mut self, items: &mut [BuildItem], depth: usize) -> usize {
+    let aabb = merge_all(items.iter().map(|item| item.aabb));
+    let node_idx = self.nodes.len();
+    self.nodes.push(LinearBVHNode {
+      bounds: [aabb.min.e, aabb.max.e],
+      offset: 0,
+      object_count: 0,
+      axis: 0,
+      _pad: 0,
+    });
+
+    match BVH::split(items, &aabb, depth) {
+      None => {
+        let node = &mut self.nodes[node_idx];
+        node.offset = self.objects.len() as u32;
+        node.object_count = items.len() as u16;
+        self
+          .objects
+          .extend(items.iter().map(|item| item.object.clone()));
+      }
+      Some((axis, mid)) => {
+        let (left, right) = items.split_at_mut(mid);
+        self.build_impl(left, depth + 1);
+        let second_child = self.build_impl(right, depth + 1);
+        let node = &mut self.nodes[node_idx];
+        node.offset = second_child as u32;
+        node.axis = axis as u8;
+      }
+    }
+    node_idx
+  }
 
-    if objects_count < 1 {
-      panic!("Tried to create BVHNode from empty objects list");
-    } else if objects_count == 1 {
-      // single object node. A bit of waste actually..
-      return BVHNode {
-        aabb: mock_aabb,
-        child_left: world_objects[start_idx].clone(),
-        child_right: world_objects[start_idx].clone(),
-      };
-    } else if objects_count == 2 {
-      // 2 objects - assign as left/right children
-      return BVHNode {
-        aabb: mock_aabb,
-        child_left: world_objects[start_idx].clone(),
-        child_right: world_objects[start_idx + 1].clone(),
-      };
+  /** Reorder items and return (axis, index of the first item in right child), or `None` for leaf */
+  fn split(items: &mut [BuildItem], aabb: &AABB, depth: usize) -> Option<(usize, usize)> {
+    if items.len() == 1 {
+      return None;
+    }
+    if depth + 1 >= MAX_DEPTH {
+      // leaf holds more objects than usual, does not happen for any reasonable input
+      assert!(items.len() <= u16::MAX as usize, "BVH is too deep");
+      return None;
     }
 
-    // many objects - pick X/Y/Z axis at random and split in half objects wrt. to that axis
-    let mut objects_copy = world_objects[start_idx..end_idx].to_vec();
-    let axis_to_sort_by: u32 = rand::thread_rng().gen_range(0..3); // represents x/y/z axis
-    sort_by_axis_distance(&mut objects_copy, axis_to_sort_by as usize);
+    let centroid_bounds = AABB::from_point_cloud(
+      &items
+        .iter()
+        .map(|item| item.centroid)
+        .collect::<Vec<Point3d>>(),
+    );
+    match find_best_split(items, aabb, &centroid_bounds) {
+      Some((axis, split_bin, cost)) => {
+        let leaf_cost = items.len() as f32;
+        if items.len() <= MAX_LEAF_SIZE && leaf_cost <= cost {
+          return None;
+        }
+        let min = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - min;
+        let mid = partition(items, |item| {
+          bin_index(item.centroid[axis], min, extent) <= split_bin
+        });
+        Some((axis, mid))
+      }
+      // all centroids are in the same place, no split would separate them
+      None if items.len() <= MAX_LEAF_SIZE => None,
+      None => Some((0, items.len() / 2)),
+    }
+  }
 
-    let mid = objects_copy.len() / 2;
-    BVHNode {
-      aabb: mock_aabb,
-      child_left: Arc::new(BVHNode::build_impl(&objects_copy, 0, mid)), // first half
-      child_right: Arc::new(BVHNode::build_impl(&objects_copy, mid, objects_copy.len())), // second half
+  /** Returns (nodes, leaves, depth) */
+  fn stats(&self) -> (usize, usize, usize) {
+    if self.nodes.is_empty() {
+      return (0, 0, 0);
+    }
+    let leaves = self.nodes.iter().filter(|n| n.object_count > 0).count();
+    let mut depth = 0;
+    let mut stack: Vec<(usize, usize)> = vec![(0, 1)];
+    while let Some((idx, d)) = stack.pop() {
+      depth = depth.max(d);
+      let node = &self.nodes[idx];
+      if node.object_count == 0 {
+        stack.push((idx + 1, d + 1));
+        stack.push((node.offset as usize, d + 1));
+      }
     }
+    (self.nodes.len(), leaves, depth)
   }
 }
 
-/** Sort objects on a selected axis */
-fn sort_by_axis_distance(objects: &mut WorldObjectsList, axis_to_sort_by: usize) {
-  objects.sort_by(|a, b| match (a.bounding_box(), b.bounding_box()) {
-    (Some(bb_a), Some(bb_b)) => {
-      let val_a = bb_a.min[axis_to_sort_by];
-      let val_b = bb_b.min[axis_to_sort_by];
-      val_a.partial_cmp(&val_b).unwrap()
+fn merge_all(mut aabbs: impl Iterator<Item = AABB>) -> AABB {
+  let first = aabbs.next().unwrap();
+  aabbs.fold(first, |acc, bb| AABB::merge(&acc, &bb))
+}
+
+/**
+Bin the centroids on each axis and evaluate SAH cost of splitting between each bin.
+Returns (axis, last bin of the left child, cost) of the cheapest split. Cost is relative
+to the cost of intersecting single object. `None` if centroids cannot be separated.
+*/
+fn find_best_split(
+  items: &[BuildItem],
+  aabb: &AABB,
+  centroid_bounds: &AABB,
+) -> Option<(usize, usize, f32)> {
+  let mut best: Option<(usize, usize, f32)> = None;
+  let parent_area = aabb.surface_area();
+
+  for axis in 0..3 {
+    let min = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - min;
+    if extent <= 0.0 {
+      continue;
     }
-    _ => {
-      panic!("Tried to create BVH, but some objects do not have bounding box");
+
+    let mut bins = [Bin {
+      aabb: None,
+      count: 0,
+    }; SAH_BINS];
+    for item in items {
+      let bin = &mut bins[bin_index(item.centroid[axis], min, extent)];
+      bin.count += 1;
+      bin.aabb = Some(match bin.aabb {
+        Some(bb) => AABB::merge(&bb, &item.aabb),
+        None => item.aabb,
+      });
     }
-  });
+
+    // cost of splitting after bin `i`, sweep from both sides to get the areas
+    let mut cost_below = [0.0f32; SAH_BINS - 1];
+    let mut acc = Bin {
+      aabb: None,
+      count: 0,
+    };
+    for i in 0..(SAH_BINS - 1) {
+      acc = merge_bins(acc, bins[i]);
+      cost_below[i] = area_of(&acc) * acc.count as f32;
+    }
+    let mut acc = Bin {
+      aabb: None,
+      count: 0,
+    };
+    for i in (0..(SAH_BINS - 1)).rev() {
+      acc = merge_bins(acc, bins[i + 1]);
+      let cost_above = area_of(&acc) * acc.count as f32;
+      let cost = TRAVERSAL_COST + (cost_below[i] + cost_above) / parent_area;
+      // strict comparison, so that ties always resolve the same way
+      if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+        best = Some((axis, i, cost));
+      }
+    }
+  }
+
+  best
 }
 
-impl Traceable for BVHNode {
-  fn bounding_box(&self) -> Option<AABB> {
-    Some(self.aabb)
+fn bin_index(value: f32, min: f32, extent: f32) -> usize {
+  let idx = ((value - min) / extent * SAH_BINS as f32) as usize;
+  idx.min(SAH_BINS - 1)
+}
+
+fn merge_bins(a: Bin, b: Bin) -> Bin {
+  let aabb = match (a.aabb, b.aabb) {
+    (Some(x), Some(y)) => Some(AABB::merge(&x, &y)),
+    (x, None) => x,
+    (None, y) => y,
+  };
+  Bin {
+    aabb,
+    count: a.count + b.count,
   }
+}
 
-  /** Check ray agains BVH. This is the fn that makes use of AABBs */
-  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-    if !self.aabb.check_intersection(r, t_min, t_max) {
-      // this node was not hit by the ray, skip entirely
+fn area_of(bin: &Bin) -> f32 {
+  bin.aabb.map_or(0.0, |bb| bb.surface_area())
+}
+
+/**
+Reorder items so that the ones matching predicate come first. Returns the number of
+matching items. Stable, so that the resulting tree does not depend on the sort algorithm.
+Both sides are non-empty, as the first and last bin always have some centroids.
+*/
+fn partition(items: &mut [BuildItem], pred: impl Fn(&BuildItem) -> bool) -> usize {
+  let (mut below, above): (Vec<BuildItem>, Vec<BuildItem>) =
+    items.iter().cloned().partition(|item| pred(item));
+  let mid = below.len();
+  below.extend(above);
+  items.clone_from_slice(&below);
+  mid
+}
+
+impl Traceable for BVH {
+  fn bounding_box(&self) -> Option<AABB> {
+    if !self.unbounded.is_empty() || self.nodes.is_empty() {
       return None;
     }
+    let [min, max] = self.nodes[0].bounds;
+    Some(AABB {
+      min: Point3d::new(min[0], min[1], min[2]),
+      max: Point3d::new(max[0], max[1], max[2]),
+    })
+  }
 
-    match self.child_left.check_intersection(r, t_min, t_max) {
-      None => {
-        // left missed, return right that maybe hit
-        return self.child_right.check_intersection(r, t_min, t_max);
+  /**
+  Check ray agains BVH. This is the fn that makes use of AABBs. Children
+  are visited front to back, so that the far child can often be skipped
+  once something closer was hit.
+  */
+  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    let mut result: Option<RayHit> = None;
+    let mut closest_so_far = t_max;
+    for object in &self.unbounded {
+      if let Some(hit) = object.check_intersection(r, t_min, closest_so_far) {
+        closest_so_far = hit.t;
+        result = Some(hit);
       }
-      Some(left_hit_data) => {
-        // check if right hit closer than left
-        let hit_right = self
-          .child_right
-          .check_intersection(r, t_min, left_hit_data.t); // NOTE: we changed max to `left_hit_data.t`
-        match hit_right {
-          None => return Some(left_hit_data), // left hit, right missed (or was farther)
-          Some(right_hit_data) => return Some(right_hit_data), // both hit, but right was closer
+    }
+    if self.nodes.is_empty() {
+      return result;
+    }
+
+    let ray = RayData::new(r);
+
+    let mut to_visit = [0usize; MAX_DEPTH];
+    let mut to_visit_count = 0;
+    let mut node_idx = 0;
+    loop {
+      let node = &self.nodes[node_idx];
+      if node.intersects(&ray, t_min, closest_so_far) {
+        if node.object_count > 0 {
+          let first = node.offset as usize;
+          for object in &self.objects[first..first + node.object_count as usize] {
+            if let Some(hit) = object.check_intersection(r, t_min, closest_so_far) {
+              closest_so_far = hit.t;
+              result = Some(hit);
+            }
+          }
+        } else {
+          // visit near child first, remember the far one for later
+          let (near, far) = if ray.dir_is_neg[node.axis as usize] == 1 {
+            (node.offset as usize, node_idx + 1)
+          } else {
+            (node_idx + 1, node.offset as usize)
+          };
+          to_visit[to_visit_count] = far;
+          to_visit_count += 1;
+          node_idx = near;
+          continue;
         }
       }
+
+      if to_visit_count == 0 {
+        break;
+      }
+      to_visit_count -= 1;
+      node_idx = to_visit[to_visit_count];
+    }
+
+    result
+  }
+
+  fn lights(&self) -> WorldObjectsList {
+    let mut result = collect_lights(&self.objects);
+    result.extend(collect_lights(&self.unbounded));
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::SolidColor;
+  use crate::plane::Plane;
+  use crate::sphere::Sphere;
+  use crate::texture::SolidColorTex;
+  use crate::vec3::{Color, Vec3};
+  use crate::volumetric::Volumetric;
+
+  fn spheres(count: usize, radius: f32) -> WorldObjectsList {
+    let material = Arc::new(SolidColor {
+      color: Color::one(),
+    });
+    (0..count)
+      .map(|i| {
+        let p = Point3d::new((i % 10) as f32, (i / 10) as f32, 0.0);
+        Arc::new(Sphere::new(p, radius, material.clone())) as Arc<dyn Traceable>
+      })
+      .collect()
+  }
+
+  #[test]
+  fn finds_closest_hit() {
+    let bvh = BVH::from_objects(&spheres(100, 0.25));
+    for i in 0..100 {
+      let p = Point3d::new((i % 10) as f32, (i / 10) as f32, 5.0);
+      let r = Ray::new(p, Vec3::new(0.0, 0.0, -1.0));
+      let hit = bvh.check_intersection(&r, 0.001, f32::INFINITY).unwrap();
+      assert!((hit.t - 4.75).abs() < 0.0001);
+      assert!((hit.p.x() - p.x()).abs() < 0.0001);
+    }
+  }
+
+  #[test]
+  fn is_deterministic() {
+    let objects = spheres(100, 0.25);
+    let a = BVH::from_objects(&objects);
+    let b = BVH::from_objects(&objects);
+    assert_eq!(a.stats(), b.stats());
+  }
+
+  #[test]
+  fn leaves_hold_many_objects() {
+    // overlapping objects are cheaper to test together than to split
+    let (nodes, leaves, _) = BVH::from_objects(&spheres(100, 0.6)).stats();
+    assert!(leaves < 100);
+    assert_eq!(nodes, 2 * leaves - 1);
+  }
+
+  #[test]
+  fn unbounded_objects() {
+    let material = Arc::new(SolidColor {
+      color: Color::one(),
+    });
+    let mut objects = spheres(4, 0.25);
+    objects.push(Arc::new(Plane::new(
+      Point3d::new(0.0, 0.0, -1.0),
+      Vec3::new(0.0, 0.0, 1.0),
+      material,
+    )));
+    let bvh = BVH::from_objects(&objects);
+    assert!(bvh.bounding_box().is_none());
+
+    let hit_sphere = Ray::new(Point3d::new(1.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = bvh
+      .check_intersection(&hit_sphere, 0.001, f32::INFINITY)
+      .unwrap();
+    assert!((hit.t - 4.75).abs() < 0.0001);
+    let hit_plane = Ray::new(Point3d::new(1.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = bvh
+      .check_intersection(&hit_plane, 0.001, f32::INFINITY)
+      .unwrap();
+    assert!((hit.t - 6.0).abs() < 0.0001);
+  }
+
+  /** Fog without boundary scatters rays no matter where they go, but not behind objects */
+  #[test]
+  fn global_fog() {
+    let fog = Volumetric::everywhere(10.0, Arc::new(SolidColorTex::new(1.0, 1.0, 1.0)));
+    let mut objects = spheres(4, 0.25);
+    objects.push(Arc::new(fog));
+    let bvh = BVH::from_objects(&objects);
+    assert!(bvh.bounding_box().is_none());
+
+    for _ in 0..100 {
+      let r = Ray::new(Point3d::new(1.0, 0.0, 5.0), Vec3::rand_unit());
+      let hit = bvh.check_intersection(&r, 0.001, f32::INFINITY).unwrap();
+      assert!(hit.t <= 5.0);
+      let hit_sphere = Ray::new(Point3d::new(1.0, 0.0, 0.5), Vec3::new(0.0, 0.0, -1.0));
+      let hit = bvh.check_intersection(&hit_sphere, 0.001, f32::INFINITY).unwrap();
+      assert!(hit.t <= 0.25 + 0.0001);
+    }
+  }
+
+  #[test]
+  fn empty() {
+    let bvh = BVH::from_objects(&Vec::new());
+    let r = Ray::new(Point3d::zero(), Vec3::new(0.0, 0.0, -1.0));
+    assert!(bvh.bounding_box().is_none());
+    assert!(bvh.check_intersection(&r, 0.001, f32::INFINITY).is_none());
+  }
+
+  #[test]
+  fn node_size() {
+    assert_eq!(std::mem::size_of::<LinearBVHNode>(), 32);
+  }
+
+  #[test]
+  fn rays_from_any_direction() {
+    let bvh = BVH::from_objects(&spheres(100, 0.25));
+    let target = Point3d::new(4.0, 4.0, 0.0);
+    for dir in &[
+      Vec3::new(1.0, 0.0, 0.0),
+      Vec3::new(-1.0, 0.0, 0.0),
+      Vec3::new(0.0, 1.0, 0.0),
+      Vec3::new(0.0, -1.0, 0.0),
+      Vec3::new(1.0, 1.0, 1.0),
+      Vec3::new(-1.0, -1.0, -1.0),
+    ] {
+      let r = Ray::new(target - *dir * 0.5, *dir);
+      let hit = bvh.check_intersection(&r, 0.001, f32::INFINITY).unwrap();
+      assert!(((hit.p - target).length() - 0.25).abs() < 0.0001);
     }
   }
+
+  #[test]
+  fn same_centroids() {
+    let material = Arc::new(SolidColor {
+      color: Color::one(),
+    });
+    let objects: WorldObjectsList = (0..20)
+      .map(|_| Arc::new(Sphere::new(Point3d::zero(), 1.0, material.clone())) as Arc<dyn Traceable>)
+      .collect();
+    let bvh = BVH::from_objects(&objects);
+    let r = Ray::new(Point3d::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(bvh.check_intersection(&r, 0.001, f32::INFINITY).is_some());
+  }
 }
@@ -0,0 +1,154 @@
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+use crate::scenes::scene_settings::SceneSettings;
+use crate::vec3::Color;
+
+#[derive(Parser, Debug)]
+#[clap(about, version)]
+/** Command line arguments. Everything optional, defaults render scene7 like before */
+pub struct CliArgs {
+  /** Name of the scene to render e.g. 'scene7' */
+  #[clap(short, long, default_value = "scene7")]
+  pub scene: String,
+
+  /** Render all built-in scenes. Scene name is appended to the output file name */
+  #[clap(long, conflicts_with = "scene")]
+  pub all: bool,
+
+  /** Render scene from a TOML scene file or glTF file (.gltf/.glb) instead of a built-in scene */
+  #[clap(long, conflicts_with_all = &["scene", "all"])]
+  pub scene_file: Option<PathBuf>,
+
+  /** Print names and descriptions of all built-in scenes and exit */
+  #[clap(long)]
+  pub list_scenes: bool,
+
+  /** Output image width in pixels */
+  #[clap(short, long, default_value_t = 960, parse(try_from_str = parse_image_dimension))]
+  pub width: u32,
+
+  /** Output image height in pixels. Calculated from width and aspect ratio if not provided */
+  #[clap(long, parse(try_from_str = parse_image_dimension))]
+  pub height: Option<u32>,
+
+  /** Aspect ratio as either 'W:H' or a number. Ignored if both width and height are provided */
+  #[clap(short, long, default_value = "16:9", parse(try_from_str = parse_aspect_ratio))]
+  pub aspect_ratio: f32,
+
+  /** Path of the output image. Format is deduced from the extension */
+  #[clap(short, long, default_value = "output.png")]
+  pub output: PathBuf,
+
+  /** Override scene's samples per pixel */
+  #[clap(long)]
+  pub samples: Option<usize>,
+
+  /** Override scene's max bounces */
+  #[clap(long)]
+  pub max_bounces: Option<i32>,
+
+  /** Override scene's vertical field of view (in degrees) */
+  #[clap(long)]
+  pub fov: Option<f32>,
+
+  /** Override scene's camera aperture */
+  #[clap(long)]
+  pub aperture: Option<f32>,
+
+  /** Override scene's background color as 'r,g,b' e.g. '0.5,0.7,1.0' */
+  #[clap(long, parse(try_from_str = parse_color))]
+  pub background: Option<Color>,
+}
+
+impl CliArgs {
+  /** Overwrite values in scene's settings with the ones provided from command line */
+  pub fn apply_overrides(&self, cfg: &mut SceneSettings) {
+    if let Some(v) = self.samples {
+      cfg.samples_per_pixel = v;
+    }
+    if let Some(v) = self.max_bounces {
+      cfg.max_bounces = v;
+    }
+    if let Some(v) = self.fov {
+      cfg.camera_fov = v;
+    }
+    if let Some(v) = self.aperture {
+      cfg.camera_aperture = v;
+    }
+    if let Some(v) = self.background {
+      cfg.background = v;
+    }
+  }
+
+  /** Output path when rendering many scenes at once e.g. 'output.png' -> 'output_scene1.png' */
+  pub fn output_for_scene(&self, scene_name: &str) -> PathBuf {
+    let stem = self
+      .output
+      .file_stem()
+      .and_then(|s| s.to_str())
+      .unwrap_or("output");
+    let file_name = match self.output.extension().and_then(|s| s.to_str()) {
+      Some(ext) => format!("{}_{}.{}", stem, scene_name, ext),
+      None => format!("{}_{}.png", stem, scene_name),
+    };
+    self
+      .output
+      .parent()
+      .unwrap_or_else(|| Path::new(""))
+      .join(file_name)
+  }
+
+  /** Returns (width, height, aspect_ratio) of the output image */
+  pub fn image_size(&self) -> (u32, u32, f32) {
+    match self.height {
+      Some(h) => (self.width, h, self.width as f32 / h as f32),
+      None => {
+        let h = ((self.width as f32 / self.aspect_ratio) as u32).max(1);
+        (self.width, h, self.aspect_ratio)
+      }
+    }
+  }
+}
+
+fn parse_image_dimension(s: &str) -> Result<u32, String> {
+  let v = s
+    .trim()
+    .parse::<u32>()
+    .map_err(|e| format!("Invalid image size '{}': {}", s, e))?;
+  if v == 0 {
+    return Err(format!("Image size '{}' has to be at least 1 pixel", s));
+  }
+  Ok(v)
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+  let parse_f32 = |v: &str| {
+    v.trim()
+      .parse::<f32>()
+      .map_err(|e| format!("Invalid aspect ratio '{}': {}", s, e))
+  };
+
+  let ratio = match s.split_once(':') {
+    Some((w, h)) => parse_f32(w)? / parse_f32(h)?,
+    None => parse_f32(s)?,
+  };
+  if !ratio.is_finite() || ratio <= 0.0 {
+    return Err(format!("Aspect ratio '{}' has to be positive", s));
+  }
+  Ok(ratio)
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+  let values = s
+    .split(',')
+    .map(|v| v.trim().parse::<f32>())
+    .collect::<Result<Vec<f32>, _>>()
+    .map_err(|e| format!("Invalid color '{}': {}", s, e))?;
+
+  match values[..] {
+    [v] => Ok(Color::uni(v)),
+    [r, g, b] => Ok(Color::new(r, g, b)),
+    _ => Err(format!("Color '{}' should be either 'v' or 'r,g,b'", s)),
+  }
+}
@@ -0,0 +1,413 @@
+use image::codecs::hdr::HdrDecoder;
+use image::error::{DecodingError, ImageError, ImageFormatHint, UnsupportedError};
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::utils::luminance;
+use crate::vec3::{Color, Vec3};
+
+// Light that comes from very far away, stored as equirectangular (latitude-longitude)
+// image. Top row of the image is +y, center of the image is -z (`Vec3::forward`).
+//
+// https://pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Sampling_Light_Sources#InfiniteAreaLights
+
+/** Environment light from equirectangular HDR image. Used instead of constant background color */
+pub struct EnvironmentMap {
+  width: usize,
+  height: usize,
+  /** Row by row, starting from the top */
+  pixels: Vec<Color>,
+  /** Around y axis, in radians */
+  rotation: f32,
+  intensity: f32,
+  /**
+  Pixel weights are luminance multiplied by the solid angle of the pixel,
+  so that brighter areas are sampled more often.
+  */
+  distribution: Distribution2D,
+}
+
+impl fmt::Debug for EnvironmentMap {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("EnvironmentMap")
+      .field("width", &self.width)
+      .field("height", &self.height)
+      .field("rotation", &self.rotation)
+      .field("intensity", &self.intensity)
+      .finish()
+  }
+}
+
+impl EnvironmentMap {
+  /** `rotation` is around y axis, in radians. Panics if `pixels` has wrong size */
+  pub fn new(
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    rotation: f32,
+    intensity: f32,
+  ) -> Self {
+    assert_eq!(pixels.len(), width * height, "Invalid environment map size");
+
+    // pixels near the poles cover smaller solid angle
+    let weights: Vec<f32> = pixels
+      .iter()
+      .enumerate()
+      .map(|(idx, color)| {
+        let row = idx / width;
+        let sin_theta = (PI * (row as f32 + 0.5) / height as f32).sin();
+        luminance(*color).max(0.0) * sin_theta
+      })
+      .collect();
+    let distribution = Distribution2D::new(&weights, width);
+
+    Self {
+      width,
+      height,
+      pixels,
+      rotation,
+      intensity,
+      distribution,
+    }
+  }
+
+  /** Evaluate `radiance` in the center of each pixel, e.g. to bake analytic sky */
+  pub fn from_fn(width: usize, height: usize, radiance: impl Fn(Vec3) -> Color) -> Self {
+    let pixels = (0..width * height)
+      .map(|idx| {
+        let u = ((idx % width) as f32 + 0.5) / width as f32;
+        let v = ((idx / width) as f32 + 0.5) / height as f32;
+        radiance(uv_to_direction(u, v, 0.0))
+      })
+      .collect();
+    Self::new(width, height, pixels, 0.0, 1.0)
+  }
+
+  /** Load Radiance `.hdr` or OpenEXR `.exr` file */
+  pub fn load(path: &Path, rotation: f32, intensity: f32) -> Result<Self, ImageError> {
+    let ext = path
+      .extension()
+      .and_then(|s| s.to_str())
+      .map(|ext| ext.to_ascii_lowercase());
+    let (width, height, pixels) = match ext.as_deref() {
+      Some("hdr") => load_hdr(path)?,
+      Some("exr") => load_exr(path)?,
+      _ => {
+        let hint = ImageFormatHint::from(path);
+        return Err(ImageError::Unsupported(UnsupportedError::from(hint)));
+      }
+    };
+    Ok(Self::new(width, height, pixels, rotation, intensity))
+  }
+
+  /** Light arriving from direction `dir` */
+  pub fn radiance(&self, dir: Vec3) -> Color {
+    let (u, v) = self.direction_to_uv(dir);
+    self.pixels[self.pixel_index(u, v)] * self.intensity
+  }
+
+  /**
+  Pick direction with probability proportional to the brightness.
+  Returns direction, light arriving from it and its probability density.
+  */
+  pub fn sample(&self) -> Option<(Vec3, Color, f32)> {
+    let (idx, (u, v), image_pdf) = self
+      .distribution
+      .sample(rand::random::<f32>(), rand::random::<f32>())?;
+    let pdf = image_to_solid_angle_pdf(image_pdf, v);
+    if pdf <= 0.0 {
+      return None;
+    }
+    let dir = uv_to_direction(u, v, self.rotation);
+    Some((dir, self.pixels[idx] * self.intensity, pdf))
+  }
+
+  /** Probability density that `sample` returns `dir`, wrt. solid angle */
+  pub fn pdf(&self, dir: Vec3) -> f32 {
+    let (u, v) = self.direction_to_uv(dir);
+    image_to_solid_angle_pdf(self.distribution.pdf(u, v), v)
+  }
+
+  fn direction_to_uv(&self, dir: Vec3) -> (f32, f32) {
+    let d = dir.unit_vector();
+    let phi = d.x().atan2(-d.z()) - self.rotation;
+    let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+    let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+  }
+
+  fn pixel_index(&self, u: f32, v: f32) -> usize {
+    let x = ((u * self.width as f32) as usize).min(self.width - 1);
+    let y = ((v * self.height as f32) as usize).min(self.height - 1);
+    y * self.width + x
+  }
+}
+
+/** Returns (width, height, pixels) */
+fn load_hdr(path: &Path) -> Result<(usize, usize, Vec<Color>), ImageError> {
+  let file = File::open(path).map_err(ImageError::IoError)?;
+  let decoder = HdrDecoder::new(BufReader::new(file))?;
+  let meta = decoder.metadata();
+  let pixels = decoder
+    .read_image_hdr()?
+    .iter()
+    .map(|p| Color::new(p[0], p[1], p[2]))
+    .collect();
+  Ok((meta.width as usize, meta.height as usize, pixels))
+}
+
+/** Returns (width, height, pixels). First layer with RGB channels is used, alpha is ignored */
+fn load_exr(path: &Path) -> Result<(usize, usize, Vec<Color>), ImageError> {
+  let image = exr::prelude::read_first_rgba_layer_from_file(
+    path,
+    |size, _| (size.width(), vec![Color::zero(); size.area()]),
+    |(width, pixels), pos, (r, g, b, _): (f32, f32, f32, f32)| {
+      pixels[pos.y() * *width + pos.x()] = Color::new(r, g, b);
+    },
+  )
+  .map_err(|e| ImageError::Decoding(DecodingError::new(ImageFormatHint::from(path), e)))?;
+  let size = image.layer_data.size;
+  let (_, pixels) = image.layer_data.channel_data.pixels;
+  Ok((size.width(), size.height(), pixels))
+}
+
+/** Mapping from the image onto the sphere stretches `2π * π * sin(θ)` times */
+fn image_to_solid_angle_pdf(image_pdf: f32, v: f32) -> f32 {
+  let sin_theta = (v * PI).sin();
+  if sin_theta <= 0.0 {
+    return 0.0;
+  }
+  image_pdf / (2.0 * PI * PI * sin_theta)
+}
+
+fn uv_to_direction(u: f32, v: f32, rotation: f32) -> Vec3 {
+  let phi = (u - 0.5) * 2.0 * PI + rotation;
+  let theta = v * PI;
+  Vec3::new(
+    theta.sin() * phi.sin(),
+    theta.cos(),
+    -theta.sin() * phi.cos(),
+  )
+}
+
+///////////////////////
+// Sampling
+
+/**
+Piecewise-constant distribution over `[0, 1)`, made of equally wide buckets.
+Bucket is picked proportionally to its weight, then point inside it uniformly.
+*/
+#[derive(Clone, Debug)]
+struct Distribution1D {
+  weights: Vec<f32>,
+  /** Normalized running sum of weights, starts with 0 and ends with 1 */
+  cdf: Vec<f32>,
+  total: f32,
+}
+
+impl Distribution1D {
+  fn new(weights: &[f32]) -> Self {
+    // summed in f64, large images have millions of pixels
+    let mut sums = Vec::with_capacity(weights.len() + 1);
+    let mut total = 0.0f64;
+    sums.push(0.0);
+    for w in weights {
+      total += *w as f64;
+      sums.push(total);
+    }
+    let cdf = if total > 0.0 {
+      sums.iter().map(|s| (s / total) as f32).collect()
+    } else {
+      vec![0.0; weights.len() + 1]
+    };
+    Self {
+      weights: weights.to_vec(),
+      cdf,
+      total: total as f32,
+    }
+  }
+
+  /** Returns bucket and position inside it, `rnd` is in `[0, 1)` */
+  fn sample(&self, rnd: f32) -> Option<(usize, f32)> {
+    if self.total <= 0.0 {
+      return None;
+    }
+    // last bucket with running sum before `rnd`, so never an empty one
+    let idx = (self.cdf.partition_point(|&c| c <= rnd) - 1).min(self.weights.len() - 1);
+    let width = self.cdf[idx + 1] - self.cdf[idx];
+    let offset = if width > 0.0 {
+      ((rnd - self.cdf[idx]) / width).clamp(0.0, 0.99999)
+    } else {
+      0.5
+    };
+    Some((idx, offset))
+  }
+
+  /** Density in `[0, 1)` of points inside `idx` bucket */
+  fn pdf(&self, idx: usize) -> f32 {
+    if self.total <= 0.0 {
+      return 0.0;
+    }
+    self.weights[idx] / self.total * self.weights.len() as f32
+  }
+}
+
+/**
+Piecewise-constant distribution over an image. Row is picked first (marginal
+distribution of row sums), then pixel in that row (conditional distribution).
+https://pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations#Piecewise-Constant2DDistributions
+*/
+#[derive(Clone, Debug)]
+struct Distribution2D {
+  rows: Vec<Distribution1D>,
+  marginal: Distribution1D,
+}
+
+impl Distribution2D {
+  /** `weights` are row by row, starting from the top */
+  fn new(weights: &[f32], width: usize) -> Self {
+    let rows: Vec<Distribution1D> = weights.chunks(width).map(Distribution1D::new).collect();
+    let row_totals: Vec<f32> = rows.iter().map(|r| r.total).collect();
+    Self {
+      marginal: Distribution1D::new(&row_totals),
+      rows,
+    }
+  }
+
+  /** Returns pixel index, (u, v) inside that pixel and density over the image */
+  fn sample(&self, rnd_u: f32, rnd_v: f32) -> Option<(usize, (f32, f32), f32)> {
+    let (y, offset_y) = self.marginal.sample(rnd_v)?;
+    let row = &self.rows[y];
+    let (x, offset_x) = row.sample(rnd_u)?;
+    let width = row.weights.len();
+    let u = (x as f32 + offset_x) / width as f32;
+    let v = (y as f32 + offset_y) / self.rows.len() as f32;
+    Some((y * width + x, (u, v), self.marginal.pdf(y) * row.pdf(x)))
+  }
+
+  fn pdf(&self, u: f32, v: f32) -> f32 {
+    let y = ((v * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+    let row = &self.rows[y];
+    let x = ((u * row.weights.len() as f32) as usize).min(row.weights.len() - 1);
+    self.marginal.pdf(y) * row.pdf(x)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn gradient_map(rotation: f32) -> EnvironmentMap {
+    let (w, h) = (16, 8);
+    let pixels = (0..w * h)
+      .map(|idx| Color::new((idx % w) as f32 / w as f32, 1.0, (idx / w) as f32))
+      .collect();
+    EnvironmentMap::new(w, h, pixels, rotation, 1.0)
+  }
+
+  #[test]
+  fn uv_round_trip() {
+    let env = gradient_map(0.7);
+    for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+      let dir = uv_to_direction(u, v, env.rotation);
+      let (u2, v2) = env.direction_to_uv(dir);
+      assert_approx_eq!(u, u2, 1e-4);
+      assert_approx_eq!(v, v2, 1e-4);
+    }
+  }
+
+  #[test]
+  fn center_is_forward() {
+    let env = gradient_map(0.0);
+    let (u, v) = env.direction_to_uv(Vec3::forward());
+    assert_approx_eq!(u, 0.5);
+    assert_approx_eq!(v, 0.5);
+    let (_, v) = env.direction_to_uv(Vec3::up());
+    assert_approx_eq!(v, 0.0);
+  }
+
+  /**
+  Expected value of `1/pdf` is the solid angle of the whole sphere. Samples right on
+  the pixel border can round into the neighbour pixel, so few mismatches are fine.
+  */
+  #[test]
+  fn pdf_matches_sampling() {
+    let env = gradient_map(1.3);
+    let n = 20000;
+    let mut sum = 0.0;
+    let mut mismatched = 0;
+    for _ in 0..n {
+      let (dir, radiance, pdf) = env.sample().unwrap();
+      let same_pixel = (radiance - env.radiance(dir)).length() < 1e-3;
+      if !same_pixel || (pdf - env.pdf(dir)).abs() > pdf * 0.01 {
+        mismatched += 1;
+      }
+      sum += 1.0 / pdf;
+    }
+    assert!(mismatched < n / 100, "{} mismatched samples", mismatched);
+    assert_approx_eq!(sum / n as f32, 4.0 * PI, 4.0 * PI * 0.03);
+  }
+
+  #[test]
+  fn samples_bright_pixel() {
+    let (w, h) = (8, 4);
+    let mut pixels = vec![Color::uni(0.01); w * h];
+    pixels[w + 2] = Color::uni(1000.0);
+    let env = EnvironmentMap::new(w, h, pixels, 0.0, 1.0);
+    let bright = (0..100)
+      .filter(|_| env.sample().unwrap().1.x() > 1.0)
+      .count();
+    assert!(bright > 90);
+  }
+
+  /** Pixels are not lost in the running sum of a big map, even the dark ones */
+  #[test]
+  fn large_map_distribution() {
+    let (w, h) = (2048, 1024);
+    let mut weights = vec![1.0; w * h];
+    weights[w * h - 1] = 0.5;
+    let distribution = Distribution2D::new(&weights, w);
+    let pdf_at = |x: usize, y: usize| {
+      let u = (x as f32 + 0.5) / w as f32;
+      let v = (y as f32 + 0.5) / h as f32;
+      distribution.pdf(u, v)
+    };
+    assert_approx_eq!(pdf_at(5, h / 2), 1.0, 1e-4);
+    assert_approx_eq!(pdf_at(w - 2, h - 1), 1.0, 1e-4);
+    assert_approx_eq!(pdf_at(w - 1, h - 1), 0.5, 1e-4);
+
+    let (idx, (u, v), pdf) = distribution.sample(0.9999, 0.9999).unwrap();
+    assert_eq!(idx, w * h - 1);
+    assert!(u > 0.999 && v > 0.999);
+    assert_approx_eq!(pdf, pdf_at(w - 1, h - 1), 1e-4);
+  }
+
+  #[test]
+  fn load_exr() {
+    let path = std::env::temp_dir().join(format!("env_test_{}.exr", std::process::id()));
+    let (w, h) = (8, 4);
+    exr::prelude::write_rgba_file(&path, w, h, |x, y| {
+      (x as f32, y as f32 * 10.0, 100.0f32, 1.0f32)
+    })
+    .unwrap();
+    let env = EnvironmentMap::load(&path, 0.0, 2.0).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!((env.width, env.height), (w, h));
+    // values above 1 survive, unlike in LDR images
+    let c = env.pixels[w + 3];
+    assert_approx_eq!(c.x(), 3.0);
+    assert_approx_eq!(c.y(), 10.0);
+    assert_approx_eq!(c.z(), 100.0);
+    assert_approx_eq!(env.radiance(Vec3::up()).z(), 200.0);
+  }
+
+  #[test]
+  fn unsupported_format() {
+    assert!(EnvironmentMap::load(Path::new("studio.png"), 0.0, 1.0).is_err());
+  }
+}
@@ -0,0 +1,432 @@
+use glam::f32::Mat4;
+use gltf::khr_lights_punctual::Kind as LightKind;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::light::{DirectionalLight, PointLight, PunctualLight, SpotLight};
+use crate::material::Material;
+use crate::normal_map::{ModifiedNormal, NormalMap};
+use crate::principled::PrincipledMaterial;
+use crate::scenes::scene_settings::SceneSettings;
+use crate::texture::{ChannelTex, ImageTex, ScaledTex, SolidColorTex, Texture};
+use crate::traceable::Traceable;
+use crate::transform::Transform;
+use crate::triangle_mesh::{MeshData, TriangleMesh};
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::WorldObjectsList;
+
+// glTF 2.0 (.gltf/.glb) importer. Node hierarchy is flattened, so that
+// each mesh instance is wrapped in a single `Transform` with the combined matrix.
+// Meshes referenced by many nodes are shared between the instances.
+//
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+
+#[derive(Debug)]
+pub enum GltfError {
+  Import(gltf::Error),
+  Unsupported(String),
+}
+
+impl fmt::Display for GltfError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GltfError::Import(e) => write!(f, "{}", e),
+      GltfError::Unsupported(msg) => write!(f, "Unsupported glTF feature: {}", msg),
+    }
+  }
+}
+
+impl std::error::Error for GltfError {}
+
+#[derive(Clone, Copy, Debug)]
+/** First perspective camera found in the scene */
+pub struct GltfCamera {
+  pub position: Point3d,
+  pub target: Point3d,
+  /** Vertical, in degrees */
+  pub fov: f32,
+}
+
+/** Objects from the glTF's default scene */
+pub struct GltfScene {
+  pub objects: WorldObjectsList,
+  pub camera: Option<GltfCamera>,
+  /** From `KHR_lights_punctual` extension, already in world space */
+  pub lights: Vec<Arc<dyn PunctualLight>>,
+}
+
+impl GltfScene {
+  /** Overwrite camera in scene settings, if glTF had one */
+  pub fn apply_camera(&self, cfg: &mut SceneSettings) {
+    if let Some(cam) = self.camera {
+      cfg.camera_position = cam.position;
+      cfg.camera_target = cam.target;
+      cfg.camera_fov = cam.fov;
+    }
+  }
+}
+
+pub fn load_gltf(path: &Path) -> Result<GltfScene, GltfError> {
+  let (document, buffers, images) = gltf::import(path).map_err(GltfError::Import)?;
+  let mut loader = GltfLoader {
+    buffers,
+    images,
+    textures: HashMap::new(),
+    materials: HashMap::new(),
+    meshes: HashMap::new(),
+    result: GltfScene {
+      objects: Vec::new(),
+      camera: None,
+      lights: Vec::new(),
+    },
+  };
+
+  let scene = document
+    .default_scene()
+    .or_else(|| document.scenes().next())
+    .ok_or_else(|| GltfError::Unsupported(String::from("File does not contain any scene")))?;
+  for node in scene.nodes() {
+    loader.load_node(&node, Mat4::IDENTITY)?;
+  }
+
+  info!(
+    "Loaded '{}': {} objects, {} unique meshes, {} lights",
+    path.display(),
+    loader.result.objects.len(),
+    loader.meshes.len(),
+    loader.result.lights.len()
+  );
+  Ok(loader.result)
+}
+
+struct GltfLoader {
+  buffers: Vec<gltf::buffer::Data>,
+  images: Vec<gltf::image::Data>,
+  /** By image index */
+  textures: HashMap<usize, Arc<dyn Texture>>,
+  /** By material index, `None` is the default material */
+  materials: HashMap<Option<usize>, Arc<dyn Material>>,
+  /** By mesh index, each primitive is a separate object */
+  meshes: HashMap<usize, Vec<Arc<dyn Traceable>>>,
+  result: GltfScene,
+}
+
+impl GltfLoader {
+  fn load_node(&mut self, node: &gltf::Node, parent_transform: Mat4) -> Result<(), GltfError> {
+    let local = Mat4::from_cols_array_2d(&node.transform().matrix());
+    let transform = parent_transform * local;
+
+    if let Some(mesh) = node.mesh() {
+      for primitive in self.load_mesh(&mesh)? {
+        self.add_instance(primitive, transform);
+      }
+    }
+
+    if let Some(camera) = node.camera() {
+      self.load_camera(&camera, transform);
+    }
+
+    if let Some(light) = node.light() {
+      self.load_light(&light, transform);
+    }
+
+    for child in node.children() {
+      self.load_node(&child, transform)?;
+    }
+    Ok(())
+  }
+
+  fn add_instance(&mut self, object: Arc<dyn Traceable>, transform: Mat4) {
+    let object: Arc<dyn Traceable> = if transform == Mat4::IDENTITY {
+      object
+    } else {
+      Arc::new(Transform::new(transform, object))
+    };
+    self.result.objects.push(object);
+  }
+
+  fn load_mesh(&mut self, mesh: &gltf::Mesh) -> Result<Vec<Arc<dyn Traceable>>, GltfError> {
+    if let Some(primitives) = self.meshes.get(&mesh.index()) {
+      return Ok(primitives.clone());
+    }
+
+    let mut primitives: Vec<Arc<dyn Traceable>> = Vec::new();
+    for primitive in mesh.primitives() {
+      if let Some(mesh_data) = self.load_primitive(&primitive)? {
+        let material = self.load_material(&primitive.material())?;
+        primitives.push(Arc::new(TriangleMesh::new(mesh_data, material)));
+      }
+    }
+
+    self.meshes.insert(mesh.index(), primitives.clone());
+    Ok(primitives)
+  }
+
+  fn load_primitive(&self, primitive: &gltf::Primitive) -> Result<Option<MeshData>, GltfError> {
+    use gltf::mesh::Mode;
+
+    let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+    let positions: Vec<Point3d> = match reader.read_positions() {
+      Some(p) => p.map(|p| Point3d::new(p[0], p[1], p[2])).collect(),
+      None => return Ok(None),
+    };
+    let normals: Vec<Vec3> = reader
+      .read_normals()
+      .map(|n| n.map(|n| Vec3::new(n[0], n[1], n[2])).collect())
+      .unwrap_or_default();
+    let uvs: Vec<(f32, f32)> = reader
+      .read_tex_coords(0)
+      .map(|t| t.into_f32().map(|uv| (uv[0], uv[1])).collect())
+      .unwrap_or_default();
+    let colors: Vec<Color> = reader
+      .read_colors(0)
+      .map(|c| {
+        c.into_rgb_f32()
+          .map(|c| Color::new(c[0], c[1], c[2]))
+          .collect()
+      })
+      .unwrap_or_default();
+    let indices: Vec<u32> = match reader.read_indices() {
+      Some(i) => i.into_u32().collect(),
+      None => (0..positions.len() as u32).collect(),
+    };
+
+    let triangles: Vec<[u32; 3]> = match primitive.mode() {
+      Mode::Triangles => indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect(),
+      Mode::TriangleStrip => (2..indices.len())
+        .map(|i| {
+          // every other triangle has reversed winding
+          if i % 2 == 0 {
+            [indices[i - 2], indices[i - 1], indices[i]]
+          } else {
+            [indices[i - 1], indices[i - 2], indices[i]]
+          }
+        })
+        .collect(),
+      Mode::TriangleFan => (2..indices.len())
+        .map(|i| [indices[0], indices[i - 1], indices[i]])
+        .collect(),
+      mode => {
+        warn!("Skipping glTF primitive with unsupported mode {:?}", mode);
+        return Ok(None);
+      }
+    };
+
+    let mesh = MeshData {
+      positions,
+      normals,
+      uvs,
+      colors,
+      indices: triangles,
+    };
+    mesh.validate().map_err(GltfError::Unsupported)?;
+    Ok(Some(mesh))
+  }
+
+  fn load_material(&mut self, material: &gltf::Material) -> Result<Arc<dyn Material>, GltfError> {
+    if let Some(m) = self.materials.get(&material.index()) {
+      return Ok(m.clone());
+    }
+
+    // glTF metallic-roughness is a subset of principled BSDF. Its dielectric F0 of 4%
+    // is the default `specular`
+    let pbr = material.pbr_metallic_roughness();
+    let mut result = PrincipledMaterial::new(Color::one());
+    let base_color = pbr.base_color_factor();
+    let base_color = Color::new(base_color[0], base_color[1], base_color[2]);
+    result.base_color = match pbr.base_color_texture() {
+      Some(info) => scaled(self.load_texture(&info.texture(), info.tex_coord())?, base_color),
+      None => Arc::new(SolidColorTex::from_color(base_color)),
+    };
+    // roughness in green channel, metalness in blue channel
+    let metallic_roughness = match pbr.metallic_roughness_texture() {
+      Some(info) => Some(self.load_texture(&info.texture(), info.tex_coord())?),
+      None => None,
+    };
+    let channel = |channel: usize, factor: f32| -> Arc<dyn Texture> {
+      match &metallic_roughness {
+        Some(tex) => scaled(
+          Arc::new(ChannelTex {
+            tex: tex.clone(),
+            channel,
+          }),
+          Color::uni(factor),
+        ),
+        None => Arc::new(SolidColorTex::from_color(Color::uni(factor))),
+      }
+    };
+    result.roughness = channel(1, pbr.roughness_factor());
+    result.metallic = channel(2, pbr.metallic_factor());
+
+    let emissive = material.emissive_factor();
+    let emissive = Color::new(emissive[0], emissive[1], emissive[2]);
+    if !emissive.is_zero() {
+      result.emission = Some(match material.emissive_texture() {
+        Some(info) => scaled(self.load_texture(&info.texture(), info.tex_coord())?, emissive),
+        None => Arc::new(SolidColorTex::from_color(emissive)),
+      });
+    }
+
+    let mut result: Arc<dyn Material> = Arc::new(result);
+    if let Some(info) = material.normal_texture() {
+      let normal_map = NormalMap {
+        map: self.load_texture(&info.texture(), info.tex_coord())?,
+        strength: info.scale(),
+        green_up: true,
+      };
+      result = Arc::new(ModifiedNormal::new(result, Arc::new(normal_map)));
+    }
+    self.materials.insert(material.index(), result.clone());
+    Ok(result)
+  }
+
+  fn load_texture(
+    &mut self,
+    texture: &gltf::Texture,
+    tex_coord: u32,
+  ) -> Result<Arc<dyn Texture>, GltfError> {
+    if tex_coord != 0 {
+      warn!(
+        "glTF texture uses TEXCOORD_{}, only TEXCOORD_0 is supported",
+        tex_coord
+      );
+    }
+
+    let image_idx = texture.source().index();
+    if let Some(t) = self.textures.get(&image_idx) {
+      return Ok(t.clone());
+    }
+
+    let image = image_to_rgba(&self.images[image_idx])?;
+    let tex: Arc<dyn Texture> = Arc::new(ImageTex::from_image(image));
+    self.textures.insert(image_idx, tex.clone());
+    Ok(tex)
+  }
+
+  fn load_camera(&mut self, camera: &gltf::Camera, transform: Mat4) {
+    if self.result.camera.is_some() {
+      return; // only first camera is used
+    }
+    match camera.projection() {
+      gltf::camera::Projection::Perspective(p) => {
+        // glTF cameras look towards -z
+        let position = Point3d::zero().transform_mat4(transform);
+        let target = Vec3::forward().transform_mat4(transform);
+        self.result.camera = Some(GltfCamera {
+          position,
+          target,
+          fov: p.yfov().to_degrees(),
+        });
+      }
+      gltf::camera::Projection::Orthographic(_) => {
+        warn!("Orthographic glTF cameras are not supported");
+      }
+    }
+  }
+
+  /** glTF lights point along local -z (`Vec3::forward`), point and spot light intensity is in candela */
+  fn load_light(&mut self, light: &gltf::khr_lights_punctual::Light, transform: Mat4) {
+    let c = light.color();
+    let color = Color::new(c[0], c[1], c[2]);
+    let intensity = light.intensity();
+    let local: Arc<dyn PunctualLight> = match light.kind() {
+      LightKind::Point => Arc::new(PointLight::new(Point3d::zero(), color, intensity)),
+      LightKind::Spot {
+        inner_cone_angle,
+        outer_cone_angle,
+      } => Arc::new(SpotLight::new(
+        Point3d::zero(),
+        Vec3::forward(),
+        color,
+        intensity,
+        inner_cone_angle,
+        outer_cone_angle,
+      )),
+      LightKind::Directional => Arc::new(DirectionalLight::new(
+        Vec3::forward(),
+        color,
+        intensity,
+        0.0,
+      )),
+    };
+    self.result.lights.push(local.transformed(transform));
+  }
+}
+
+/** Convert glTF's decoded image into format used by `ImageTex` */
+/** glTF values are `factor * texture` */
+fn scaled(tex: Arc<dyn Texture>, factor: Color) -> Arc<dyn Texture> {
+  Arc::new(ScaledTex { tex, scale: factor })
+}
+
+fn image_to_rgba(data: &gltf::image::Data) -> Result<image::RgbaImage, GltfError> {
+  use gltf::image::Format;
+
+  let channels: usize = match data.format {
+    Format::R8 => 1,
+    Format::R8G8 => 2,
+    Format::R8G8B8 => 3,
+    Format::R8G8B8A8 => 4,
+    format => return Err(GltfError::Unsupported(format!("Image format {:?}", format))),
+  };
+
+  let pixels: Vec<u8> = data
+    .pixels
+    .chunks_exact(channels)
+    .flat_map(|p| match channels {
+      1 => [p[0], p[0], p[0], 255],
+      2 => [p[0], p[0], p[0], p[1]],
+      3 => [p[0], p[1], p[2], 255],
+      _ => [p[0], p[1], p[2], p[3]],
+    })
+    .collect();
+  image::RgbaImage::from_raw(data.width, data.height, pixels)
+    .ok_or_else(|| GltfError::Unsupported(String::from("Image has invalid size")))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ray::Ray;
+  use crate::world::World;
+  use assert_approx_eq::assert_approx_eq;
+
+  #[test]
+  fn load_test_scene() {
+    let scene = load_gltf(Path::new("assets/models/gltf_test.gltf")).unwrap();
+    // 2 quad instances
+    assert_eq!(scene.objects.len(), 2);
+    assert_eq!(scene.lights.len(), 1);
+
+    let cam = scene.camera.unwrap();
+    assert_approx_eq!(cam.position.z(), 4.0);
+    assert_approx_eq!(cam.target.z(), 3.0);
+    assert_approx_eq!(cam.fov, 45.0, 0.01);
+
+    // second instance is moved by +2 on x and scaled 2x
+    let r = Ray::new(Point3d::new(2.9, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let world = World {
+      objects: scene.objects,
+    };
+    let hit = world.check_intersection(&r, 0.001, f32::INFINITY).unwrap();
+    assert_approx_eq!(hit.t, 5.0);
+    assert_approx_eq!(hit.normal.z(), 1.0);
+
+    // red plastic
+    let n = hit.normal;
+    let c = hit.material.eval(&hit, n, n);
+    assert!(c.x() > 2.0 * c.y());
+    assert!(!hit.material.is_emissive());
+  }
+
+  #[test]
+  fn missing_file() {
+    assert!(load_gltf(Path::new("does/not/exist.gltf")).is_err());
+  }
+}
@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
-use crate::material::{BSDFResult, Material};
-use crate::ray::Ray;
+use crate::material::{BSDFSample, Material};
 use crate::texture::{SolidColorTex, Texture};
 use crate::traceable::RayHit;
 use crate::vec3::{Color, Vec3};
@@ -31,11 +30,22 @@ impl IsotropicMat {
 }
 
 impl Material for IsotropicMat {
-  fn bsdf(&self, _r_in: &Ray, hit: &RayHit) -> BSDFResult {
-    BSDFResult {
-      diffuse: self.albedo.sample(hit),
-      bounce: Some(Ray::new(hit.p, Vec3::rand_unit())),
-      ..Default::default()
-    }
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    let wi = Vec3::rand_unit();
+    Some(BSDFSample {
+      wi,
+      weight: self.albedo.sample(hit),
+      pdf: self.pdf(hit, wo, wi),
+      delta: false,
+    })
+  }
+
+  /** Phase function is the same in every direction, and there is no cosine term in volumes */
+  fn eval(&self, hit: &RayHit, _wo: Vec3, _wi: Vec3) -> Color {
+    self.albedo.sample(hit) / (4.0 * std::f32::consts::PI)
+  }
+
+  fn pdf(&self, _hit: &RayHit, _wo: Vec3, _wi: Vec3) -> f32 {
+    1.0 / (4.0 * std::f32::consts::PI)
   }
 }
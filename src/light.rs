@@ -1,10 +1,16 @@
+use glam::f32::Mat4;
+use rand::Rng;
+use std::fmt;
 use std::sync::Arc;
 
-use crate::material::{BSDFResult, Material};
+use crate::environment::EnvironmentMap;
+use crate::material::{BSDFSample, Material};
 use crate::ray::Ray;
 use crate::texture::{SolidColorTex, Texture};
-use crate::traceable::RayHit;
-use crate::vec3::Color;
+use crate::traceable::{RayHit, Traceable};
+use crate::utils::random_in_cone;
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::{World, WorldObjectsList};
 
 #[derive(Clone, Debug)]
 pub struct DiffuseLight {
@@ -31,13 +37,463 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-  fn bsdf(&self, _r_in: &Ray, hit: &RayHit) -> BSDFResult {
-    let color = self.albedo.sample(hit);
+  /** Lights do not bounce light, they are the end of ray lifetime */
+  fn sample(&self, _hit: &RayHit, _wo: Vec3) -> Option<BSDFSample> {
+    None
+  }
+
+  /** Emits from both sides */
+  fn emitted(&self, _r_in: &Ray, hit: &RayHit) -> Color {
+    self.albedo.sample(hit) * self.strength
+  }
+
+  fn is_emissive(&self) -> bool {
+    true
+  }
+}
+
+///////////////////////
+// Punctual lights
 
-    BSDFResult {
-      diffuse: color,
-      emissive: color * self.strength,
-      bounce: None, // important
+/** Light arriving at a point from `PunctualLight` */
+pub struct LightSample {
+  /** Direction towards the light, normalized */
+  pub wi: Vec3,
+  /** Distance to the light. Infinite for directional lights */
+  pub dist: f32,
+  /** Light arriving from `wi`, already divided by the probability density of picking `wi` */
+  pub radiance: Color,
+}
+
+/**
+Light without any geometry e.g. point or spot light. Rays can't hit it,
+so it can only be found by direct light sampling.
+*/
+pub trait PunctualLight: fmt::Debug + Send + Sync {
+  /** Light arriving at `origin`. `None` if this light does not shine there */
+  fn sample(&self, origin: Point3d) -> Option<LightSample>;
+
+  /** Same light, moved from object into world space by `tfx` */
+  fn transformed(&self, tfx: Mat4) -> Arc<dyn PunctualLight>;
+}
+
+#[derive(Clone, Debug)]
+/** Emits the same amount of light in every direction. Light falls off with distance squared */
+pub struct PointLight {
+  pub position: Point3d,
+  pub color: Color,
+  /** Radiant intensity, power per unit solid angle */
+  pub intensity: f32,
+}
+
+impl PointLight {
+  pub fn new(position: Point3d, color: Color, intensity: f32) -> Self {
+    Self {
+      position,
+      color,
+      intensity,
     }
   }
 }
+
+impl PunctualLight for PointLight {
+  fn sample(&self, origin: Point3d) -> Option<LightSample> {
+    let to_light = self.position - origin;
+    let dist_sq = to_light.length_squared();
+    if dist_sq <= 0.0 {
+      return None;
+    }
+    Some(LightSample {
+      wi: to_light.unit_vector(),
+      dist: dist_sq.sqrt(),
+      radiance: self.color * (self.intensity / dist_sq),
+    })
+  }
+
+  fn transformed(&self, tfx: Mat4) -> Arc<dyn PunctualLight> {
+    Arc::new(Self {
+      position: self.position.transform_mat4(tfx),
+      ..self.clone()
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+/**
+Point light that shines only inside a cone. Full intensity up to `inner_angle`
+from the cone's axis, then smoothly fades out until `outer_angle`.
+Angles are in radians, measured from the axis (so half of cone's opening).
+*/
+pub struct SpotLight {
+  pub position: Point3d,
+  /** Where the light shines. Unit vector */
+  pub direction: Vec3,
+  pub color: Color,
+  /** Radiant intensity along the axis, power per unit solid angle */
+  pub intensity: f32,
+  pub inner_angle: f32,
+  pub outer_angle: f32,
+}
+
+impl SpotLight {
+  pub fn new(
+    position: Point3d,
+    direction: Vec3,
+    color: Color,
+    intensity: f32,
+    inner_angle: f32,
+    outer_angle: f32,
+  ) -> Self {
+    Self {
+      position,
+      direction: direction.unit_vector(),
+      color,
+      intensity,
+      inner_angle: inner_angle.min(outer_angle),
+      outer_angle,
+    }
+  }
+
+  /**
+  Same falloff as glTF's `KHR_lights_punctual` recommends.
+
+  https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_lights_punctual#inner-and-outer-cone-angles
+  */
+  fn falloff(&self, cos_angle: f32) -> f32 {
+    let cos_outer = self.outer_angle.cos();
+    let cos_inner = self.inner_angle.cos();
+    let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-4)).clamp(0.0, 1.0);
+    t * t
+  }
+}
+
+impl PunctualLight for SpotLight {
+  fn sample(&self, origin: Point3d) -> Option<LightSample> {
+    let to_light = self.position - origin;
+    let dist_sq = to_light.length_squared();
+    if dist_sq <= 0.0 {
+      return None;
+    }
+    let wi = to_light.unit_vector();
+    let falloff = self.falloff((!wi).dot(self.direction));
+    if falloff <= 0.0 {
+      return None;
+    }
+    Some(LightSample {
+      wi,
+      dist: dist_sq.sqrt(),
+      radiance: self.color * (self.intensity * falloff / dist_sq),
+    })
+  }
+
+  fn transformed(&self, tfx: Mat4) -> Arc<dyn PunctualLight> {
+    let rot = glam::f32::Mat3::from_mat4(tfx);
+    Arc::new(Self {
+      position: self.position.transform_mat4(tfx),
+      direction: self.direction.transform_mat3(rot).unit_vector(),
+      ..self.clone()
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+/**
+Very distant light e.g. the sun, all rays arrive from the same direction. With
+`angular_radius > 0` the light comes from a small disk instead, which gives soft shadows.
+*/
+pub struct DirectionalLight {
+  /** Where the light travels. Unit vector */
+  pub direction: Vec3,
+  pub color: Color,
+  /** Irradiance on a surface that faces the light, power per unit area */
+  pub intensity: f32,
+  /** In radians. Sun is about 0.0047 */
+  pub angular_radius: f32,
+}
+
+impl DirectionalLight {
+  pub fn new(direction: Vec3, color: Color, intensity: f32, angular_radius: f32) -> Self {
+    Self {
+      direction: direction.unit_vector(),
+      color,
+      intensity,
+      angular_radius,
+    }
+  }
+}
+
+impl PunctualLight for DirectionalLight {
+  /**
+  Directions on the disk are picked uniformly. The disk is small, so its radiance
+  is about `intensity / solid_angle`, which cancels out with the pdf.
+  */
+  fn sample(&self, _origin: Point3d) -> Option<LightSample> {
+    let to_light = !self.direction;
+    let wi = if self.angular_radius > 0.0 {
+      random_in_cone(to_light, self.angular_radius.cos())
+    } else {
+      to_light
+    };
+    Some(LightSample {
+      wi,
+      dist: f32::INFINITY,
+      radiance: self.color * self.intensity,
+    })
+  }
+
+  fn transformed(&self, tfx: Mat4) -> Arc<dyn PunctualLight> {
+    let rot = glam::f32::Mat3::from_mat4(tfx);
+    Arc::new(Self {
+      direction: self.direction.transform_mat3(rot).unit_vector(),
+      ..self.clone()
+    })
+  }
+}
+
+///////////////////////
+// Light list
+
+/** Result of `LightList::sample` */
+pub enum LightListSample {
+  /**
+  Point on an emissive object, see `Traceable::sample_direction`. Its light is found
+  by shadow ray hitting the object. `pdf` includes the probability of picking the object.
+  */
+  Object { to_point: Vec3, pdf: f32 },
+  /** Light from punctual light, already divided by the probability of picking it */
+  Punctual(LightSample),
+  /**
+  Direction towards the environment, shadow ray must not hit anything.
+  `pdf` includes the probability of picking the environment.
+  */
+  Environment { wi: Vec3, radiance: Color, pdf: f32 },
+}
+
+/** All lights in the scene, used for direct light sampling (next-event estimation) */
+pub struct LightList {
+  lights: WorldObjectsList,
+  punctual: Vec<Arc<dyn PunctualLight>>,
+  environment: Option<Arc<EnvironmentMap>>,
+}
+
+impl LightList {
+  pub fn new(
+    world: &World,
+    punctual: &[Arc<dyn PunctualLight>],
+    environment: Option<Arc<EnvironmentMap>>,
+  ) -> Self {
+    Self {
+      lights: world.lights(),
+      punctual: punctual.to_vec(),
+      environment,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.lights.len() + self.punctual.len() + self.environment.iter().count()
+  }
+
+  pub fn environment(&self) -> Option<&EnvironmentMap> {
+    self.environment.as_deref()
+  }
+
+  #[allow(dead_code)]
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /** Pick random light (all have the same probability) and a point on it */
+  pub fn sample(&self, origin: Point3d) -> Option<LightListSample> {
+    let count = self.len();
+    if count == 0 {
+      return None;
+    }
+    let idx = rand::thread_rng().gen_range(0..count);
+    if let Some(light) = self.lights.get(idx) {
+      let (to_point, pdf) = light.sample_direction(origin)?;
+      return Some(LightListSample::Object {
+        to_point,
+        pdf: pdf / count as f32,
+      });
+    }
+    if let Some(light) = self.punctual.get(idx - self.lights.len()) {
+      let mut sample = light.sample(origin)?;
+      sample.radiance = sample.radiance * count as f32;
+      return Some(LightListSample::Punctual(sample));
+    }
+    let (wi, radiance, pdf) = self.environment.as_ref()?.sample()?;
+    Some(LightListSample::Environment {
+      wi,
+      radiance,
+      pdf: pdf / count as f32,
+    })
+  }
+
+  /**
+  Probability density that `sample(r.origin)` picks the point that `r` hits
+  between `t_min` and `t_max`. 0 if that point is not on any of the lights.
+  */
+  pub fn pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    if self.lights.is_empty() {
+      return 0.0;
+    }
+    let sum: f32 = self
+      .lights
+      .iter()
+      .map(|light| light.direction_pdf(r, t_min, t_max))
+      .sum();
+    sum / self.len() as f32
+  }
+
+  /** Probability density that `sample` picks direction `dir` towards the environment */
+  pub fn environment_pdf(&self, dir: Vec3) -> f32 {
+    match &self.environment {
+      Some(env) => env.pdf(dir) / self.len() as f32,
+      None => 0.0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bvh::BVH;
+  use crate::material::Lambert;
+  use crate::rectangle::Rectangle;
+  use crate::sphere::Sphere;
+  use crate::transform::Transform;
+  use glam::f32::Mat4;
+
+  fn light() -> Arc<DiffuseLight> {
+    Arc::new(DiffuseLight::color(Color::one(), 1.0))
+  }
+
+  /** Expected value of `1/pdf` is the solid angle of the sampled object */
+  fn estimate_solid_angle(object: &dyn Traceable, origin: Point3d) -> f32 {
+    let n = 20000;
+    let sum: f32 = (0..n)
+      .map(|_| 1.0 / object.sample_direction(origin).unwrap().1)
+      .sum();
+    sum / n as f32
+  }
+
+  #[test]
+  fn finds_nested_lights() {
+    let mut world = World::new();
+    let lamp = Arc::new(Sphere::new(Point3d::zero(), 1.0, light()));
+    let other = Arc::new(Sphere::new(
+      Point3d::one(),
+      1.0,
+      Arc::new(Lambert::color(1.0, 1.0, 1.0)),
+    ));
+    let bvh = BVH::from_objects(&vec![lamp.clone(), other.clone()]);
+    world.add(Arc::new(Transform::new(
+      Mat4::from_translation(glam::f32::Vec3::X),
+      Arc::new(bvh),
+    )));
+    world.add(lamp);
+    world.add(other);
+    assert_eq!(LightList::new(&world, &[], None).len(), 2);
+  }
+
+  #[test]
+  fn sphere_pdf() {
+    let sphere = Sphere::new(Point3d::new(0.0, 0.0, -5.0), 1.0, light());
+    let (dir, pdf) = sphere.sample_direction(Point3d::zero()).unwrap();
+    // sampled point is on the sphere surface
+    assert!(((dir - sphere.center).length() - 1.0).abs() < 0.001);
+    let cos_theta_max = (1.0f32 - 1.0 / 25.0).sqrt();
+    let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_theta_max);
+    assert!((1.0 / pdf - solid_angle).abs() < 0.001);
+  }
+
+  #[test]
+  fn scaled_sphere_pdf() {
+    let sphere = Arc::new(Sphere::new(Point3d::zero(), 0.5, light()));
+    let tfx = Mat4::from_translation(glam::f32::Vec3::new(0.0, 0.0, -5.0))
+      * Mat4::from_scale(glam::f32::Vec3::new(2.0, 2.0, 2.0));
+    let object = Transform::new(tfx, sphere);
+    let cos_theta_max = (1.0f32 - 1.0 / 25.0).sqrt();
+    let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_theta_max);
+    let estimate = estimate_solid_angle(&object, Point3d::zero());
+    assert!((estimate - solid_angle).abs() < 0.001);
+  }
+
+  #[test]
+  fn rectangle_pdf() {
+    // 2x2 square, 1 unit away
+    let rect = Rectangle::new((-1.0, -1.0), (1.0, 1.0), 0.0, light());
+    let origin = Point3d::new(0.0, 0.0, 1.0);
+    let solid_angle = 4.0 * (4.0f32 / (8.0f32 * 8.0).sqrt()).asin();
+    let estimate = estimate_solid_angle(&rect, origin);
+    assert!((estimate - solid_angle).abs() / solid_angle < 0.02);
+  }
+
+  #[test]
+  fn direction_pdf_matches_sampling() {
+    let rect: Arc<dyn Traceable> = Arc::new(Rectangle::new((-1.0, -1.0), (1.0, 1.0), 0.0, light()));
+    let tfx = Mat4::from_translation(glam::f32::Vec3::new(0.5, 0.0, -4.0))
+      * Mat4::from_rotation_y(0.7)
+      * Mat4::from_scale(glam::f32::Vec3::new(1.0, 3.0, 0.5));
+    let objects: Vec<Arc<dyn Traceable>> = vec![
+      rect.clone(),
+      Arc::new(Transform::new(tfx, rect)),
+      Arc::new(Transform::new(
+        tfx,
+        Arc::new(Sphere::new(Point3d::zero(), 1.0, light())),
+      )),
+    ];
+    let origin = Point3d::new(0.2, 0.3, 2.0);
+
+    for object in objects {
+      for _ in 0..100 {
+        let (to_point, pdf) = object.sample_direction(origin).unwrap();
+        let r = Ray::new(origin, to_point);
+        let dist = to_point.length();
+        let pdf2 = object.direction_pdf(&r, dist * 0.999, dist * 1.001);
+        assert!((pdf - pdf2).abs() / pdf < 0.01, "{} vs {}", pdf, pdf2);
+      }
+    }
+  }
+
+  #[test]
+  fn point_light_falls_off_with_distance() {
+    let light = PointLight::new(Point3d::new(0.0, 2.0, 0.0), Color::one(), 8.0);
+    let sample = light.sample(Point3d::zero()).unwrap();
+    assert!((sample.wi.y() - 1.0).abs() < 0.001);
+    assert!((sample.dist - 2.0).abs() < 0.001);
+    assert!((sample.radiance.x() - 2.0).abs() < 0.001);
+  }
+
+  #[test]
+  fn spot_light_cone() {
+    let rad = |d: f32| d.to_radians();
+    let light = SpotLight::new(
+      Point3d::new(0.0, 1.0, 0.0),
+      Vec3::new(0.0, -1.0, 0.0),
+      Color::one(),
+      1.0,
+      rad(20.0),
+      rad(40.0),
+    );
+    let at_angle = |deg: f32| {
+      let origin = Point3d::new(rad(deg).tan(), 0.0, 0.0);
+      let dist_sq = (light.position - origin).length_squared();
+      light.sample(origin).map(|s| s.radiance.x() * dist_sq)
+    };
+    assert!((at_angle(10.0).unwrap() - 1.0).abs() < 0.001);
+    let half = at_angle(30.0).unwrap();
+    assert!(half > 0.0 && half < 1.0);
+    assert!(at_angle(50.0).is_none());
+  }
+
+  #[test]
+  fn transformed_directional_light() {
+    let light = DirectionalLight::new(Vec3::forward(), Color::one(), 1.0, 0.0);
+    let moved = light.transformed(Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2));
+    // -z rotated 90dgr around x points up, so the light comes from below
+    let sample = moved.sample(Point3d::zero()).unwrap();
+    assert!((sample.wi.y() + 1.0).abs() < 0.001);
+    assert!(sample.dist.is_infinite());
+  }
+}
@@ -1,6 +1,8 @@
-use log::info;
+use clap::Parser;
+use log::{error, info};
 use rand::Rng;
 use rayon::prelude::*;
+use std::path::Path;
 
 // TODO Stratified Sampling
 // TODO opensubdiv
@@ -14,88 +16,230 @@ mod aabb;
 mod box_prim; // box is reserved Rust keyword
 mod bvh;
 mod camera;
+mod cli;
+mod environment;
+mod gltf_loader;
 mod isotropic_mat;
 mod light;
 mod material;
+mod microfacet;
+mod normal_map;
+mod obj_loader;
+mod plane;
+mod ply_loader;
+mod principled;
 mod ray;
 mod rectangle;
+mod scene_file;
 mod scenes;
+mod sky;
 mod sphere;
 mod texture;
 mod traceable;
 mod transform;
+mod triangle;
+mod triangle_mesh;
 mod utils;
 mod vec3;
 mod volumetric;
 mod world;
 
-use crate::bvh::BVHNode;
+use crate::bvh::BVH;
 use crate::camera::Camera;
+use crate::cli::CliArgs;
+use crate::gltf_loader::load_gltf;
+use crate::light::{LightList, LightListSample};
 use crate::ray::Ray;
-use crate::traceable::Traceable;
-use crate::utils::{color_f32_to_u8, gamma_correct};
+use crate::scene_file::SceneFile;
+use crate::scenes::scene_registry::{find_scene, scene_names, SceneDef, SCENES};
+use crate::scenes::scene_settings::SceneSettings;
+use crate::traceable::{RayHit, Traceable};
+use crate::utils::{color_f32_to_u8, gamma_correct, power_heuristic};
 use crate::vec3::{Color, Vec3};
 use crate::world::World;
 
 const ACNE_CORRECTION: f32 = 0.001;
+/** Relative tolerance when checking if shadow ray reached the sampled point on the light */
+const SHADOW_RAY_EPSILON: f32 = 0.001;
+/** Paths always survive this many bounces, then Russian roulette may terminate them */
+const RUSSIAN_ROULETTE_MIN_DEPTH: i32 = 3;
 
-fn trace_ray(r: &Ray, world: &dyn Traceable, depth: i32, background: &Color) -> Color {
-  if depth <= 0 {
-    return Color::zero();
-  }
+/**
+Light arriving at the hit point straight from a randomly picked light (next-event estimation).
+Shadow ray has to reach the sampled point on the light without hitting anything else.
+Emissive objects are weighted against the chance that the BSDF bounce would find the same light.
+*/
+fn sample_direct_light(r: &Ray, hit: &RayHit, world: &dyn Traceable, lights: &LightList) -> Color {
+  let wo = !r.dir.unit_vector();
+  match lights.sample(hit.p) {
+    Some(LightListSample::Object {
+      to_point,
+      pdf: light_pdf,
+    }) => {
+      let wi = to_point.unit_vector();
+      let bsdf = hit.material.eval(hit, wo, wi);
+      if bsdf.is_zero() || light_pdf <= 0.0 {
+        return Color::zero();
+      }
 
-  let result = world.check_intersection(r, ACNE_CORRECTION, f32::INFINITY);
-  match result {
-    Some(hit) => {
-      // we hit something!
-      let bsdf_result = hit.material.bsdf(r, &hit);
-      match bsdf_result.bounce {
-        Some(r) => {
-          // do more bounces
-          let bounce_result = trace_ray(&r, world, depth - 1, background);
-          return bsdf_result.emissive + bsdf_result.diffuse * bounce_result;
+      let shadow_ray = hit.spawn_ray(to_point);
+      // measured from the offset origin, so the light itself is still within the bound
+      let dist = (hit.p + to_point - shadow_ray.origin).length();
+      let max_dist = dist * (1.0 + SHADOW_RAY_EPSILON);
+      match world.check_intersection(&shadow_ray, ACNE_CORRECTION, max_dist) {
+        Some(light_hit) if light_hit.t >= dist * (1.0 - SHADOW_RAY_EPSILON) => {
+          let bsdf_pdf = hit.material.pdf(hit, wo, wi);
+          let weight = power_heuristic(light_pdf, bsdf_pdf);
+          bsdf * light_hit.material.emitted(&shadow_ray, &light_hit) * (weight / light_pdf)
         }
-        _ => {
-          // e.g. light do not bounce light, but are the end of ray lifetime.
-          // If the `bsdf_result.emissive` is (0,0,0) this may effectively discard the ray.
-          return bsdf_result.emissive;
+        _ => Color::zero(), // in shadow
+      }
+    }
+    Some(LightListSample::Punctual(sample)) => {
+      // BSDF bounce can never hit punctual light, so no weighting here
+      let bsdf = hit.material.eval(hit, wo, sample.wi);
+      if bsdf.is_zero() {
+        return Color::zero();
+      }
+      let shadow_ray = hit.spawn_ray(sample.wi);
+      let max_dist = sample.dist * (1.0 - SHADOW_RAY_EPSILON);
+      match world.check_intersection(&shadow_ray, ACNE_CORRECTION, max_dist) {
+        Some(_) => Color::zero(), // in shadow
+        None => bsdf * sample.radiance,
+      }
+    }
+    Some(LightListSample::Environment {
+      wi,
+      radiance,
+      pdf: light_pdf,
+    }) => {
+      let bsdf = hit.material.eval(hit, wo, wi);
+      if bsdf.is_zero() {
+        return Color::zero();
+      }
+      let shadow_ray = hit.spawn_ray(wi);
+      match world.check_intersection(&shadow_ray, ACNE_CORRECTION, f32::INFINITY) {
+        Some(_) => Color::zero(), // in shadow
+        None => {
+          let weight = power_heuristic(light_pdf, hit.material.pdf(hit, wo, wi));
+          bsdf * radiance * (weight / light_pdf)
         }
       }
     }
-    _ => {
-      // TBH this is like an ambient light factor. If you set this to black,
-      // only emmisive materials make things visible
-      // let unit_direction = r.dir.unit_vector();
-      // let t = to_0_1(unit_direction.y());
-      // return lerp_vec3(Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0), t);
-      return *background;
+    None => Color::zero(),
+  }
+}
+
+/**
+Weight of light that the ray found by bouncing off a surface. `bsdf_pdf` is the
+density of that bounce, or `None` if direct light sampling could not have found this
+light (camera rays, mirrors etc.). Light sampling covers the rest of the weight.
+*/
+fn emitted_weight(r: &Ray, hit: &RayHit, lights: &LightList, bsdf_pdf: Option<f32>) -> f32 {
+  match bsdf_pdf {
+    Some(bsdf_pdf) => {
+      let t_min = hit.t * (1.0 - SHADOW_RAY_EPSILON);
+      let t_max = hit.t * (1.0 + SHADOW_RAY_EPSILON);
+      let light_pdf = lights.pdf(r, t_min, t_max);
+      power_heuristic(bsdf_pdf, light_pdf)
     }
-  };
+    None => 1.0,
+  }
 }
 
-fn main() {
-  // simple_logger::init().unwrap(); // .filter_level(log::LevelFilter::Debug).init();
-  simple_logger::SimpleLogger::new().init().unwrap();
-  log::set_max_level(log::LevelFilter::Trace);
+/**
+Follow the ray as it bounces around the scene. `throughput` is the product of BSDF
+weights along the path, so how much the light found at the current bounce adds to
+the pixel. After a few bounces, dim paths are randomly terminated (Russian roulette).
+Survivors are boosted by the same factor, so on average the result does not change.
+*/
+fn trace_ray(
+  r: &Ray,
+  world: &dyn Traceable,
+  lights: &LightList,
+  max_bounces: i32,
+  background: &Color,
+) -> Color {
+  let mut color = Color::zero();
+  let mut throughput = Color::one();
+  let mut ray = *r;
+  // density of the bounce that created current ray, `None` for camera ray and delta
+  // bounces. Used to weight light hit by this ray against direct light sampling.
+  let mut bsdf_pdf: Option<f32> = None;
 
-  info!("-- START! --");
-  let start_time = std::time::Instant::now();
+  for depth in 0..max_bounces {
+    let hit = match world.check_intersection(&ray, ACNE_CORRECTION, f32::INFINITY) {
+      Some(hit) => hit,
+      None => {
+        let sky = match lights.environment() {
+          Some(env) => {
+            let weight = match bsdf_pdf {
+              Some(bsdf_pdf) => power_heuristic(bsdf_pdf, lights.environment_pdf(ray.dir)),
+              None => 1.0,
+            };
+            env.radiance(ray.dir) * weight
+          }
+          // TBH this is like an ambient light factor. If you set this to black,
+          // only emmisive materials make things visible. For outdoor scenes use `Sky`
+          // instead, it is baked into the environment map.
+          None => *background,
+        };
+        color = color + throughput * sky;
+        break;
+      }
+    };
 
-  ///////////////////////
-  // World
-  let mut world = World::new();
-  scenes::scene7::load_scene(&mut world);
-  let cfg = scenes::scene7::settings();
-  info!("{:?}", cfg);
+    // we hit something!
+    let emitted = hit.material.emitted(&ray, &hit);
+    if !emitted.is_zero() {
+      let weight = emitted_weight(&ray, &hit, lights, bsdf_pdf);
+      color = color + throughput * emitted * weight;
+    }
+
+    // e.g. light do not bounce light, but are the end of ray lifetime
+    let sample = match hit.material.sample(&hit, !ray.dir.unit_vector()) {
+      Some(sample) => sample,
+      None => break,
+    };
+    // Always, even if this bounce picked a delta lobe. Materials like `CoatedMaterial`
+    // mix both kinds, direct light covers the rest of them. For pure mirrors and glass
+    // `eval` is zero, so nothing is added.
+    let direct = sample_direct_light(&ray, &hit, world, lights);
+    color = color + throughput * direct;
+    bsdf_pdf = if sample.delta {
+      None
+    } else {
+      Some(sample.pdf)
+    };
+
+    throughput = throughput * sample.weight;
+    if depth >= RUSSIAN_ROULETTE_MIN_DEPTH {
+      let survive_prob = throughput.max_component().min(1.0);
+      if rand::random::<f32>() >= survive_prob {
+        break;
+      }
+      throughput = throughput / survive_prob;
+    }
+
+    // do more bounces
+    ray = hit.spawn_ray(sample.wi);
+  }
+
+  color
+}
 
+/** Render the world from the point of view of camera described in scene settings */
+fn render(world: &World, cfg: &SceneSettings, args: &CliArgs) -> image::RgbImage {
   ///////////////////////
   // BVH
   info!("-- Building BVH --");
-  let bvh = BVHNode::build(&world);
+  let bvh = BVH::build(world);
+  let lights = LightList::new(world, &cfg.lights, cfg.environment.clone());
+  info!("Lights: {}", lights.len());
 
   ///////////////////////
   // Camera
-  let aspect_ratio = 16.0 / 9.0;
+  let (image_width, image_height, aspect_ratio) = args.image_size();
   let dist_to_focus = (cfg.camera_position - cfg.camera_target).length();
   let aperture = cfg.camera_aperture;
   let cam_fov = cfg.camera_fov;
@@ -112,9 +256,8 @@ fn main() {
   ///////////////////////
   // Render
   info!("-- Tracing rays --");
-  let image_width: u32 = 960;
-  let image_height: u32 = (image_width as f32 / aspect_ratio) as u32;
-  let mut img = image::RgbImage::new(image_width as u32, image_height as u32);
+  info!("Output size: {}x{}", image_width, image_height);
+  let mut img = image::RgbImage::new(image_width, image_height);
 
   let data: Vec<(u32, u32, Color)> = (0..(image_width * image_height))
     .into_par_iter()
@@ -124,10 +267,11 @@ fn main() {
       let mut pixel_color = Color::zero();
 
       for _ in 0..cfg.samples_per_pixel {
-        let u = (x as f32 + rng.gen::<f32>()) / (image_width as f32 - 1.0);
-        let v = (y as f32 + rng.gen::<f32>()) / (image_height as f32 - 1.0);
+        // pixel covers [x, x + 1), so the whole image is [0, 1) even when it's 1px wide
+        let u = (x as f32 + rng.gen::<f32>()) / image_width as f32;
+        let v = (y as f32 + rng.gen::<f32>()) / image_height as f32;
         let r = camera.get_ray(u, v);
-        pixel_color = pixel_color + trace_ray(&r, &bvh, cfg.max_bounces, &cfg.background);
+        pixel_color = pixel_color + trace_ray(&r, &bvh, &lights, cfg.max_bounces, &cfg.background);
       }
       pixel_color = pixel_color / (cfg.samples_per_pixel as f32); // average sample color
       pixel_color = gamma_correct(pixel_color, 2.2);
@@ -139,16 +283,169 @@ fn main() {
   info!("-- Collecting output --");
   for (x, y, pixel_color) in data {
     img.put_pixel(
-      x as u32,
-      (image_height - y - 1) as u32,
+      x,
+      image_height - y - 1,
       image::Rgb(color_f32_to_u8(pixel_color)),
     );
   }
 
+  img
+}
+
+/** Load, render and save a single built-in scene */
+fn render_scene(scene: &SceneDef, args: &CliArgs, output: &Path) {
+  info!("-- Scene '{}': {} --", scene.name, scene.description);
+  let (world, cfg) = scene.load();
+  render_and_save(&world, cfg, args, output);
+}
+
+/** Load, render and save scene described in a file */
+fn render_scene_file(path: &Path, args: &CliArgs, output: &Path) {
+  info!("-- Scene file '{}' --", path.display());
+  let is_gltf = matches!(
+    path.extension().and_then(|s| s.to_str()),
+    Some("gltf") | Some("glb")
+  );
+  let loaded = if is_gltf {
+    load_gltf(path)
+      .map(|scene| {
+        let mut cfg = SceneSettings::default();
+        scene.apply_camera(&mut cfg);
+        cfg.lights = scene.lights;
+        (
+          World {
+            objects: scene.objects,
+          },
+          cfg,
+        )
+      })
+      .map_err(|e| e.to_string())
+  } else {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    SceneFile::load(path)
+      .and_then(|scene| scene.build(base_dir))
+      .map_err(|e| e.to_string())
+  };
+  match loaded {
+    Ok((world, cfg)) => render_and_save(&world, cfg, args, output),
+    Err(e) => {
+      error!("Could not load '{}': {}", path.display(), e);
+      std::process::exit(1);
+    }
+  }
+}
+
+fn render_and_save(world: &World, mut cfg: SceneSettings, args: &CliArgs, output: &Path) {
+  let start_time = std::time::Instant::now();
+  args.apply_overrides(&mut cfg);
+  info!("{:?}", cfg);
+
+  let img = render(world, &cfg, args);
+
   ///////////////////////
   // Save output
-  img.save("output.png").unwrap();
+  info!("Saving to '{}'", output.display());
+  if let Err(e) = img.save(output) {
+    error!("Could not save '{}': {}", output.display(), e);
+    std::process::exit(1);
+  }
+
+  let s = start_time.elapsed().as_secs();
+  info!(
+    "-- '{}' DONE in {}min {}s --",
+    output.display(),
+    s / 60,
+    s % 60
+  );
+}
+
+fn main() {
+  let args = CliArgs::parse();
+
+  if args.list_scenes {
+    for scene in SCENES.iter() {
+      println!("{:<10} {}", scene.name, scene.description);
+    }
+    return;
+  }
+
+  // simple_logger::init().unwrap(); // .filter_level(log::LevelFilter::Debug).init();
+  simple_logger::SimpleLogger::new().init().unwrap();
+  log::set_max_level(log::LevelFilter::Trace);
+
+  info!("-- START! --");
+  let start_time = std::time::Instant::now();
+
+  if let Some(path) = &args.scene_file {
+    render_scene_file(path, &args, &args.output);
+  } else if args.all {
+    for scene in SCENES.iter() {
+      render_scene(scene, &args, &args.output_for_scene(scene.name));
+    }
+  } else {
+    match find_scene(&args.scene) {
+      Some(scene) => render_scene(scene, &args, &args.output),
+      None => {
+        error!(
+          "Unknown scene '{}'. Available scenes: {}",
+          args.scene,
+          scene_names().join(", ")
+        );
+        std::process::exit(1);
+      }
+    }
+  }
 
   let s = start_time.elapsed().as_secs();
   info!("-- DONE in {}min {}s --", s / 60, s % 60);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::light::DiffuseLight;
+  use crate::material::{CoatedMaterial, Lambert};
+  use crate::microfacet::fresnel_dielectric;
+  use crate::plane::Plane;
+  use crate::sphere::Sphere;
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+  use std::f32::consts::PI;
+  use std::sync::Arc;
+
+  /**
+  Lambert under a mirror-like coat, lit by a small sphere light right above.
+  At grazing angle the coat mostly picks its delta lobe, the rest is found
+  by direct light sampling.
+  */
+  #[test]
+  fn coated_diffuse_under_small_light() {
+    let (albedo, ior, strength, radius) = (0.5, 1.5, 100.0, 0.05);
+    let floor = CoatedMaterial::new(Arc::new(Lambert::color(albedo, albedo, albedo)), ior, 0.0);
+    let world = World {
+      objects: vec![
+        Arc::new(Plane::new(Point3d::zero(), Vec3::up(), Arc::new(floor))),
+        Arc::new(Sphere::new(
+          Point3d::new(0.0, 1.0, 0.0),
+          radius,
+          Arc::new(DiffuseLight::color(Color::one(), strength)),
+        )),
+      ],
+    };
+    let lights = LightList::new(&world, &[], None);
+
+    let cos_o = 10.0f32.to_radians().sin(); // 80 degrees from the normal
+    let wo = Vec3::new((1.0 - cos_o * cos_o).sqrt(), cos_o, 0.0);
+    let r = Ray::new(wo * 2.0, !wo);
+    let n = 4000;
+    let sum: f32 = (0..n)
+      .map(|_| trace_ray(&r, &world, &lights, 3, &Color::zero()).x())
+      .sum();
+
+    // irradiance from a small sphere is π * L * (radius / distance)²
+    let irradiance = PI * strength * radius * radius;
+    let through_coat = (1.0 - fresnel_dielectric(cos_o, ior)) * (1.0 - fresnel_dielectric(1.0, ior));
+    let expected = albedo / PI * through_coat * irradiance;
+    assert_approx_eq!(sum / n as f32, expected, expected * 0.03);
+  }
+}
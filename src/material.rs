@@ -1,6 +1,11 @@
+use std::f32::consts::PI;
 use std::fmt;
 use std::sync::Arc;
 
+use crate::microfacet::{
+  dielectric_eval, dielectric_pdf, dielectric_sample, fresnel_conductor, fresnel_dielectric,
+  fresnel_schlick, fresnel_thin_film, reflection_eval, reflection_pdf, reflection_sample, GGX,
+};
 use crate::ray::Ray;
 use crate::texture::{SolidColorTex, Texture};
 use crate::traceable::RayHit;
@@ -9,31 +14,19 @@ use crate::vec3::{Color, Vec3};
 
 const IOR_AIR: f32 = 1.0; // blah, blah, vacuum, blah, blah
 
-/**
-Bidirectional Scattering Distribution Function result. e.g. light bounce direction,
-output color, emmisive for lights etc.
-*/
-pub struct BSDFResult {
-  pub diffuse: Color,
-  pub emissive: Color,
-  pub bounce: Option<Ray>,
-}
-
-impl BSDFResult {
-  pub fn with_normal_bounce(&mut self, hit: &RayHit) -> &mut Self {
-    self.bounce = Some(Ray::new(hit.p, hit.normal));
-    self
-  }
-}
-
-impl Default for BSDFResult {
-  fn default() -> Self {
-    BSDFResult {
-      diffuse: Color::zero(),
-      emissive: Color::zero(),
-      bounce: None,
-    }
-  }
+/** Bounce picked by `Material::sample` */
+pub struct BSDFSample {
+  /** Direction of the bounced ray, away from the surface. Normalized */
+  pub wi: Vec3,
+  /** BSDF * cos / pdf. Light coming back along `wi` is multiplied by this */
+  pub weight: Color,
+  /** Probability density of picking `wi`, wrt. solid angle. Meaningless if `delta` */
+  pub pdf: f32,
+  /**
+  Direction was picked from a delta distribution e.g. mirror or glass. `eval` and `pdf`
+  never return anything for it, so direct light sampling can't find such lights.
+  */
+  pub delta: bool,
 }
 
 ///////////////////////
@@ -41,10 +34,41 @@ impl Default for BSDFResult {
 
 /**
 Describes color of light after it hits the surface. And some other properties
-e.g. when light penetrates the surface
+e.g. when light penetrates the surface.
+
+Directions are `wo` - towards where the ray came from (so `-r_in.dir`) and `wi` -
+where the light arrives from. Both point away from the surface and are normalized.
+https://raytracing.github.io/books/RayTracingTheRestOfYourLife.html
  */
 pub trait Material: fmt::Debug + Send + Sync {
-  fn bsdf(&self, r_in: &Ray, hit: &RayHit) -> BSDFResult;
+  /** Pick direction of the bounce. `None` if the ray is absorbed */
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample>;
+
+  /**
+  BSDF multiplied by cosine term, for light coming from direction `wi`.
+  Used for direct light sampling. Delta distributions return 0.
+  */
+  fn eval(&self, _hit: &RayHit, _wo: Vec3, _wi: Vec3) -> Color {
+    Color::zero()
+  }
+
+  /**
+  Probability density that `sample` picks `wi`. Has to match `BSDFSample::pdf`.
+  Delta distributions return 0.
+  */
+  fn pdf(&self, _hit: &RayHit, _wo: Vec3, _wi: Vec3) -> f32 {
+    0.0
+  }
+
+  /** Light emitted from the surface */
+  fn emitted(&self, _r_in: &Ray, _hit: &RayHit) -> Color {
+    Color::zero()
+  }
+
+  /** If true, objects with this material are used for direct light sampling */
+  fn is_emissive(&self) -> bool {
+    false
+  }
 }
 
 ///////////////////////
@@ -55,13 +79,14 @@ pub struct SolidColor {
 }
 
 impl Material for SolidColor {
-  fn bsdf(&self, _r_in: &Ray, hit: &RayHit) -> BSDFResult {
-    let mut result = BSDFResult {
-      diffuse: self.color,
-      ..Default::default()
-    };
-    result.with_normal_bounce(hit);
-    result
+  /** Always bounces along the normal */
+  fn sample(&self, hit: &RayHit, _wo: Vec3) -> Option<BSDFSample> {
+    Some(BSDFSample {
+      wi: hit.normal,
+      weight: self.color,
+      pdf: 1.0,
+      delta: true,
+    })
   }
 }
 
@@ -91,42 +116,301 @@ impl Lambert {
 }
 
 impl Material for Lambert {
-  fn bsdf(&self, _r_in: &Ray, hit: &RayHit) -> BSDFResult {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
     let mut scatter_direction = hit.normal + Vec3::rand_unit();
     if scatter_direction.near_zero() {
       scatter_direction = hit.normal;
     }
+    let wi = scatter_direction.unit_vector();
+
+    // BSDF * cos / pdf = (albedo * cos / π) / (cos / π)
+    Some(BSDFSample {
+      wi,
+      weight: self.albedo.sample(hit),
+      pdf: self.pdf(hit, wo, wi),
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, _wo: Vec3, wi: Vec3) -> Color {
+    let cos = hit.normal.dot(wi).max(0.0);
+    self.albedo.sample(hit) * (cos / PI)
+  }
+
+  /** `normal + random unit vector` is cosine-weighted */
+  fn pdf(&self, hit: &RayHit, _wo: Vec3, wi: Vec3) -> f32 {
+    hit.normal.dot(wi).max(0.0) / PI
+  }
+}
 
-    BSDFResult {
-      diffuse: self.albedo.sample(hit),
-      bounce: Some(Ray::new(hit.p, scatter_direction)),
-      ..Default::default()
+///////////////////////
+// Oren–Nayar
+
+const FON_C1: f32 = 0.5 - 2.0 / (3.0 * PI);
+const FON_C2: f32 = 2.0 / 3.0 - 28.0 / (15.0 * PI);
+
+#[derive(Clone, Debug)]
+/**
+Rough diffuse surface e.g. clay, concrete or cloth. Appears flatter than `Lambert`
+and reflects more light back towards the source.
+
+Energy-preserving Oren–Nayar, "EON: A practical energy-preserving rough diffuse BRDF"
+(Portsmouth et al. 2024). Fujii's single-scattering term plus multiple-scattering
+compensation, so white surface reflects all light for any roughness.
+https://arxiv.org/abs/2410.18026
+*/
+pub struct OrenNayar {
+  albedo: Arc<dyn Texture>,
+  /** 0 is Lambert, 1 is very rough */
+  pub roughness: f32,
+}
+
+impl OrenNayar {
+  #[allow(dead_code)]
+  pub fn from_color(c: Color, roughness: f32) -> Self {
+    Self::texture(Arc::new(SolidColorTex::from_color(c)), roughness)
+  }
+
+  pub fn texture(t: Arc<dyn Texture>, roughness: f32) -> Self {
+    Self {
+      albedo: t,
+      roughness: roughness.clamp(0.0, 1.0),
     }
   }
+
+  /** Directional albedo of the single-scattering term for white surface */
+  fn single_scatter_albedo(&self, cos: f32) -> f32 {
+    let cos = cos.clamp(1e-4, 1.0);
+    let a = 1.0 / (1.0 + FON_C1 * self.roughness);
+    let b = self.roughness * a;
+    let sin = (1.0 - cos * cos).sqrt();
+    let g =
+      sin * (cos.acos() - sin * cos) + 2.0 / 3.0 * ((sin / cos) * (1.0 - sin * sin * sin) - sin);
+    a + b / PI * g
+  }
+}
+
+impl Material for OrenNayar {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    let mut scatter_direction = hit.normal + Vec3::rand_unit();
+    if scatter_direction.near_zero() {
+      scatter_direction = hit.normal;
+    }
+    let wi = scatter_direction.unit_vector();
+    let pdf = self.pdf(hit, wo, wi);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BSDFSample {
+      wi,
+      weight: self.eval(hit, wo, wi) / pdf,
+      pdf,
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    let cos_i = hit.normal.dot(wi);
+    let cos_o = hit.normal.dot(wo);
+    if cos_i <= 0.0 || cos_o <= 0.0 {
+      return Color::zero();
+    }
+    let albedo = self.albedo.sample(hit);
+    let sigma = self.roughness;
+
+    // single scattering (Fujii), s/t grows towards retro-reflection
+    let s = wi.dot(wo) - cos_i * cos_o;
+    let s_over_t = if s > 0.0 { s / cos_i.max(cos_o) } else { s };
+    let single = albedo * ((1.0 + sigma * s_over_t) / (1.0 + FON_C1 * sigma) / PI);
+
+    // light lost by single scattering comes back after more bounces
+    let e_avg = (1.0 + FON_C2 * sigma) / (1.0 + FON_C1 * sigma);
+    let ms = |a: f32| a * a * e_avg / (1.0 - a * (1.0 - e_avg));
+    let albedo_ms = Color::new(ms(albedo.x()), ms(albedo.y()), ms(albedo.z()));
+    let lost =
+      (1.0 - self.single_scatter_albedo(cos_i)) * (1.0 - self.single_scatter_albedo(cos_o));
+    let multiple = albedo_ms * (lost / (1.0 - e_avg).max(1e-6) / PI);
+
+    (single + multiple) * cos_i
+  }
+
+  /** Cosine-weighted, same as `Lambert` */
+  fn pdf(&self, hit: &RayHit, _wo: Vec3, wi: Vec3) -> f32 {
+    hit.normal.dot(wi).max(0.0) / PI
+  }
+}
+
+///////////////////////
+// Thin film
+
+#[derive(Clone, Debug)]
+/**
+Transparent film over `Metal`, `Conductor` or `Dielectric` e.g. soap bubble, oil slick
+or anodized metal. Replaces their Fresnel with `fresnel_thin_film`, so the reflections
+get rainbow colors that change with the thickness and the viewing angle.
+*/
+pub struct ThinFilm {
+  pub ior: f32,
+  /** In nanometres, visible colors appear around 100-1000 */
+  pub thickness: f32,
+  /** Optional, red channel multiplies `thickness` */
+  pub thickness_map: Option<Arc<dyn Texture>>,
+}
+
+impl ThinFilm {
+  pub fn new(ior: f32, thickness: f32) -> Self {
+    Self {
+      ior,
+      thickness,
+      thickness_map: None,
+    }
+  }
+
+  /** Reflectance of the film over material with complex IOR `eta + i*k` */
+  fn reflectance(&self, hit: &RayHit, cos: f32, outside: f32, (eta, k): (Color, Color)) -> Color {
+    let thickness = match &self.thickness_map {
+      Some(map) => self.thickness * map.sample(hit).x().max(0.0),
+      None => self.thickness,
+    };
+    fresnel_thin_film(cos, outside, self.ior, thickness, eta, k)
+  }
 }
 
 ///////////////////////
 // Metal
+
 #[derive(Clone, Debug)]
+/**
+Artist-friendly metal, `albedo` is the color at normal incidence. Towards grazing
+angles it turns white (Schlick's Fresnel). GGX microfacets, roughness 0 is a mirror.
+*/
 pub struct Metal {
   pub albedo: Color,
   pub roughness: f32,
+  pub film: Option<ThinFilm>,
+}
+
+impl Metal {
+  fn ggx(&self) -> GGX {
+    GGX::from_roughness(self.roughness, self.roughness)
+  }
+
+  fn fresnel(&self, hit: &RayHit, cos: f32) -> Color {
+    match &self.film {
+      // film needs IOR, pretend it's a dielectric that reflects `albedo` at normal incidence
+      Some(film) => {
+        let eta = |f0: f32| {
+          let r = f0.clamp(0.0, 0.99).sqrt();
+          (1.0 + r) / (1.0 - r)
+        };
+        let albedo = self.albedo;
+        let eta = Color::new(eta(albedo.x()), eta(albedo.y()), eta(albedo.z()));
+        film.reflectance(hit, cos, IOR_AIR, (eta, Color::zero()))
+      }
+      None => fresnel_schlick(self.albedo, cos),
+    }
+  }
 }
 
 impl Material for Metal {
-  fn bsdf(&self, r_in: &Ray, hit: &RayHit) -> BSDFResult {
-    let reflected = reflect(r_in.dir, hit.normal);
-    let roughness_scatter = Vec3::rand_unit() * self.roughness.clamp(0.0, 1.0);
-    let scattered = Ray::new(hit.p, reflected + roughness_scatter);
-
-    let mut result = BSDFResult {
-      diffuse: self.albedo,
-      ..Default::default()
-    };
-    if scattered.dir.dot(hit.normal) > 0.0 {
-      result.bounce = Some(scattered)
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    reflection_sample(&self.ggx(), hit, wo, |cos| self.fresnel(hit, cos))
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    reflection_eval(&self.ggx(), hit, wo, wi, |cos| self.fresnel(hit, cos))
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    reflection_pdf(&self.ggx(), hit, wo, wi)
+  }
+}
+
+///////////////////////
+// Conductor
+
+/** Complex IOR (eta, k) of common metals for red, green and blue light */
+pub const GOLD_IOR: (Color, Color) = (
+  Color::new(0.143, 0.374, 1.442),
+  Color::new(3.983, 2.385, 1.603),
+);
+pub const COPPER_IOR: (Color, Color) = (
+  Color::new(0.200, 0.924, 1.102),
+  Color::new(3.912, 2.452, 2.142),
+);
+pub const ALUMINIUM_IOR: (Color, Color) = (
+  Color::new(1.657, 0.880, 0.521),
+  Color::new(9.224, 6.270, 4.837),
+);
+pub const SILVER_IOR: (Color, Color) = (
+  Color::new(0.155, 0.117, 0.138),
+  Color::new(4.828, 3.122, 2.147),
+);
+
+#[derive(Clone, Debug)]
+/**
+Physically based metal described by its complex index of refraction `eta + i*k`,
+same as in other renderers. GGX microfacets with visible normal sampling.
+Roughness can differ along the texture's u and v directions e.g. for brushed metal.
+*/
+pub struct Conductor {
+  pub eta: Color,
+  pub k: Color,
+  pub roughness_u: f32,
+  pub roughness_v: f32,
+  pub film: Option<ThinFilm>,
+}
+
+impl Conductor {
+  #[allow(dead_code)]
+  pub fn new((eta, k): (Color, Color), roughness: f32) -> Self {
+    Self::anisotropic((eta, k), roughness, roughness)
+  }
+
+  pub fn anisotropic((eta, k): (Color, Color), roughness_u: f32, roughness_v: f32) -> Self {
+    Self {
+      eta,
+      k,
+      roughness_u,
+      roughness_v,
+      film: None,
+    }
+  }
+
+  /** `gold`, `copper`, `aluminium` or `silver` */
+  pub fn preset_ior(name: &str) -> Option<(Color, Color)> {
+    match name {
+      "gold" => Some(GOLD_IOR),
+      "copper" => Some(COPPER_IOR),
+      "aluminium" | "aluminum" => Some(ALUMINIUM_IOR),
+      "silver" => Some(SILVER_IOR),
+      _ => None,
+    }
+  }
+
+  fn ggx(&self) -> GGX {
+    GGX::from_roughness(self.roughness_u, self.roughness_v)
+  }
+
+  fn fresnel(&self, hit: &RayHit, cos: f32) -> Color {
+    match &self.film {
+      Some(film) => film.reflectance(hit, cos, IOR_AIR, (self.eta, self.k)),
+      None => fresnel_conductor(cos, self.eta, self.k),
     }
-    result
+  }
+}
+
+impl Material for Conductor {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    reflection_sample(&self.ggx(), hit, wo, |cos| self.fresnel(hit, cos))
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    reflection_eval(&self.ggx(), hit, wo, wi, |cos| self.fresnel(hit, cos))
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    reflection_pdf(&self.ggx(), hit, wo, wi)
   }
 }
 
@@ -138,32 +422,727 @@ impl Material for Metal {
 #[derive(Clone, Debug)]
 /** Material that can either reflect/refract depending on IOR. */
 pub struct Dielectric {
+  /** Tints every bounce, no matter how thick the object is */
   pub albedo: Color,
   pub ior: f32, // https://en.wikipedia.org/wiki/List_of_refractive_indices
+  /** Per unit of distance travelled inside, see `beer_lambert`. Zero for clear glass */
+  pub absorption: Color,
+  /** Film on the outside. With `ior` 1 and film of water it's a soap bubble */
+  pub film: Option<ThinFilm>,
+}
+
+/**
+Light that travelled through the medium is absorbed exponentially with the distance.
+Ray that hits the back face started inside, at the previous hit on the same object,
+so `hit.t` is how far it went.
+
+https://en.wikipedia.org/wiki/Beer%E2%80%93Lambert_law
+*/
+fn beer_lambert(absorption: Color, hit: &RayHit) -> Color {
+  if hit.front_face || absorption.is_zero() {
+    return Color::one();
+  }
+  let d = hit.t;
+  Color::new(
+    (-absorption.x() * d).exp(),
+    (-absorption.y() * d).exp(),
+    (-absorption.z() * d).exp(),
+  )
 }
 
 impl Material for Dielectric {
-  fn bsdf(&self, r_in: &Ray, hit: &RayHit) -> BSDFResult {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
     let (ior_from, ior_into) = if hit.front_face {
       (IOR_AIR, self.ior)
     } else {
       (self.ior, IOR_AIR)
     };
 
-    let reflectance_at_angle = reflectance_schlick(r_in.dir, hit.normal, ior_from, ior_into);
+    let r_dir = !wo;
+    // film makes it different per channel, pick by the average
+    let reflectance = match &self.film {
+      Some(film) => {
+        let ior = (Color::uni(ior_into), Color::zero());
+        film.reflectance(hit, wo.dot(hit.normal), ior_from, ior)
+      }
+      None => Color::uni(reflectance_schlick(r_dir, hit.normal, ior_from, ior_into)),
+    };
+    let reflectance_at_angle = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
     // I don't like this randomness here, but let's do by the book for now..
     let sample_use_reflect_cause_angle = reflectance_at_angle > rand::random::<f32>();
-    let maybe_refracted = refract(r_in.dir, hit.normal, ior_from, ior_into);
+    let maybe_refracted = refract(r_dir, hit.normal, ior_from, ior_into);
+
+    let (refracted, fresnel) = match maybe_refracted {
+      Some(x) if !sample_use_reflect_cause_angle => (
+        x,
+        (Color::one() - reflectance) / (1.0 - reflectance_at_angle),
+      ),
+      Some(_) => (
+        reflect(r_dir, hit.normal),
+        reflectance / reflectance_at_angle,
+      ),
+      None => (reflect(r_dir, hit.normal), Color::one()),
+    };
+
+    Some(BSDFSample {
+      wi: refracted.unit_vector(),
+      weight: self.albedo * fresnel * beer_lambert(self.absorption, hit),
+      pdf: 1.0,
+      delta: true,
+    })
+  }
+}
+
+///////////////////////
+// Rough dielectric
+
+#[derive(Clone, Debug)]
+/**
+Frosted glass. Microfacets that both reflect and refract (Walter et al. 2007),
+with GGX distribution and exact Fresnel. `albedo` tints only the transmitted light.
+Light that bounces between the microfacets more than once is lost, so very rough
+glass gets darker.
 
-    let refracted = match maybe_refracted {
-      Some(x) if !sample_use_reflect_cause_angle => x,
-      _ => reflect(r_in.dir, hit.normal),
+Roughness 0 is a perfectly smooth surface, but it's not quite `Dielectric`. Besides
+exact Fresnel instead of Schlick's, refracted radiance is divided by eta² as the
+light is squeezed into a narrower cone. `Dielectric` skips that, for closed objects
+it cancels out when the light leaves anyway.
+
+https://www.cs.cornell.edu/~srm/publications/EGSR07-btdf.pdf
+*/
+pub struct RoughDielectric {
+  pub albedo: Color,
+  pub ior: f32,
+  pub roughness: f32,
+  /** Same as `Dielectric::absorption` */
+  pub absorption: Color,
+}
+
+impl RoughDielectric {
+  fn ggx(&self) -> GGX {
+    GGX::from_roughness(self.roughness, self.roughness)
+  }
+
+  /**
+  IOR of the side the light goes into divided by the side it comes from.
+  Hit normal always points towards `wo`, so from inside of the object it's flipped.
+  */
+  fn eta(&self, hit: &RayHit) -> f32 {
+    if hit.front_face {
+      self.ior / IOR_AIR
+    } else {
+      IOR_AIR / self.ior
+    }
+  }
+
+  /** Only the transmitted light is tinted */
+  fn tint(&self, hit: &RayHit, wi_local: Vec3) -> Color {
+    let albedo = if wi_local.z() < 0.0 {
+      self.albedo
+    } else {
+      Color::one()
     };
+    albedo * beer_lambert(self.absorption, hit)
+  }
+}
+
+impl Material for RoughDielectric {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    let ggx = self.ggx();
+    let eta = self.eta(hit);
+    let frame = hit.shading_frame();
+    let wo_local = frame.world_to_local(wo);
+    let wi_local = dielectric_sample(&ggx, eta, wo_local)?;
+    let wi = frame.local(wi_local.x(), wi_local.y(), wi_local.z());
+    let tint = self.tint(hit, wi_local);
+
+    if ggx.is_smooth() {
+      // Fresnel cancels out with the probability of reflecting
+      let spread = if wi_local.z() < 0.0 { eta * eta } else { 1.0 };
+      return Some(BSDFSample {
+        wi,
+        weight: tint / spread,
+        pdf: 1.0,
+        delta: true,
+      });
+    }
+    let pdf = dielectric_pdf(&ggx, eta, wo_local, wi_local);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BSDFSample {
+      wi,
+      weight: tint * (dielectric_eval(&ggx, eta, wo_local, wi_local) / pdf),
+      pdf,
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    let frame = hit.shading_frame();
+    let (wo, wi) = (frame.world_to_local(wo), frame.world_to_local(wi));
+    self.tint(hit, wi) * dielectric_eval(&self.ggx(), self.eta(hit), wo, wi)
+  }
 
-    BSDFResult {
-      diffuse: self.albedo,
-      bounce: Some(Ray::new(hit.p, refracted)),
-      ..Default::default()
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    let frame = hit.shading_frame();
+    let (wo, wi) = (frame.world_to_local(wo), frame.world_to_local(wi));
+    dielectric_pdf(&self.ggx(), self.eta(hit), wo, wi)
+  }
+}
+
+///////////////////////
+// Mix
+
+#[derive(Clone, Debug)]
+/**
+Blend of two materials e.g. dirt mask over paint. `weight` is how much of `second`
+there is, read from the red channel of the texture. Each bounce picks one of the
+materials, but the returned `pdf` and `weight` account for both of them.
+*/
+pub struct MixMaterial {
+  pub first: Arc<dyn Material>,
+  pub second: Arc<dyn Material>,
+  pub weight: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+  pub fn new(
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+  ) -> Self {
+    Self {
+      first,
+      second,
+      weight,
     }
   }
+
+  #[allow(dead_code)]
+  pub fn constant(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f32) -> Self {
+    Self::new(
+      first,
+      second,
+      Arc::new(SolidColorTex::new(weight, weight, weight)),
+    )
+  }
+
+  fn weight(&self, hit: &RayHit) -> f32 {
+    self.weight.sample(hit).x().clamp(0.0, 1.0)
+  }
+}
+
+impl Material for MixMaterial {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    let w = self.weight(hit);
+    let (picked, other, p) = if rand::random::<f32>() < w {
+      (&self.second, &self.first, w)
+    } else {
+      (&self.first, &self.second, 1.0 - w)
+    };
+    let sample = picked.sample(hit, wo)?;
+    if sample.delta {
+      // the other material can't pick the same direction, mix factor cancels out
+      return Some(sample);
+    }
+    let pdf = p * sample.pdf + (1.0 - p) * other.pdf(hit, wo, sample.wi);
+    if pdf <= 0.0 {
+      return None;
+    }
+    let eval = picked.eval(hit, wo, sample.wi) * p + other.eval(hit, wo, sample.wi) * (1.0 - p);
+    Some(BSDFSample {
+      wi: sample.wi,
+      weight: eval / pdf,
+      pdf,
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    let w = self.weight(hit);
+    self.first.eval(hit, wo, wi) * (1.0 - w) + self.second.eval(hit, wo, wi) * w
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    let w = self.weight(hit);
+    self.first.pdf(hit, wo, wi) * (1.0 - w) + self.second.pdf(hit, wo, wi) * w
+  }
+
+  fn emitted(&self, r_in: &Ray, hit: &RayHit) -> Color {
+    let w = self.weight(hit);
+    self.first.emitted(r_in, hit) * (1.0 - w) + self.second.emitted(r_in, hit) * w
+  }
+
+  fn is_emissive(&self) -> bool {
+    self.first.is_emissive() || self.second.is_emissive()
+  }
+}
+
+///////////////////////
+// Coated
+
+#[derive(Clone, Debug)]
+/**
+Thin dielectric layer (clearcoat, varnish) over any `base` material, e.g. car paint or
+varnished wood. The coat reflects by Fresnel with GGX microfacets. Light that gets through
+is lit by the base and has to pass the coat again on the way out.
+Refraction inside the coat and bounces between the layers are ignored.
+*/
+pub struct CoatedMaterial {
+  pub base: Arc<dyn Material>,
+  pub ior: f32,
+  /** Roughness of the coat, 0 is a mirror-like finish */
+  pub roughness: f32,
+}
+
+impl CoatedMaterial {
+  pub fn new(base: Arc<dyn Material>, ior: f32, roughness: f32) -> Self {
+    Self {
+      base,
+      ior,
+      roughness,
+    }
+  }
+
+  fn ggx(&self) -> GGX {
+    GGX::from_roughness(self.roughness, self.roughness)
+  }
+
+  /** Fraction of light reflected by the coat */
+  fn fresnel(&self, hit: &RayHit, dir: Vec3) -> f32 {
+    fresnel_dielectric(hit.normal.dot(dir).abs(), self.ior / IOR_AIR)
+  }
+}
+
+impl Material for CoatedMaterial {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    // pick the coat as often as it reflects
+    let coat_probability = self.fresnel(hit, wo);
+    let sample = if rand::random::<f32>() < coat_probability {
+      let sample = reflection_sample(&self.ggx(), hit, wo, |_| Color::one())?;
+      if sample.delta {
+        return Some(sample); // Fresnel cancels out with the probability
+      }
+      sample
+    } else {
+      let sample = self.base.sample(hit, wo)?;
+      if sample.delta {
+        // light passes the coat twice, the first pass cancels out with the probability
+        return Some(BSDFSample {
+          weight: sample.weight * (1.0 - self.fresnel(hit, sample.wi)),
+          ..sample
+        });
+      }
+      sample
+    };
+
+    let pdf = self.pdf(hit, wo, sample.wi);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BSDFSample {
+      wi: sample.wi,
+      weight: self.eval(hit, wo, sample.wi) / pdf,
+      pdf,
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    let eta = self.ior / IOR_AIR;
+    let coat = reflection_eval(&self.ggx(), hit, wo, wi, |cos| {
+      Color::uni(fresnel_dielectric(cos, eta))
+    });
+    let through = (1.0 - self.fresnel(hit, wo)) * (1.0 - self.fresnel(hit, wi));
+    coat + self.base.eval(hit, wo, wi) * through
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    let coat_probability = self.fresnel(hit, wo);
+    coat_probability * reflection_pdf(&self.ggx(), hit, wo, wi)
+      + (1.0 - coat_probability) * self.base.pdf(hit, wo, wi)
+  }
+
+  fn emitted(&self, r_in: &Ray, hit: &RayHit) -> Color {
+    self.base.emitted(r_in, hit) * (1.0 - self.fresnel(hit, !r_in.dir.unit_vector()))
+  }
+
+  fn is_emissive(&self) -> bool {
+    self.base.is_emissive()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::light::DiffuseLight;
+  use crate::utils::random_in_cone;
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn hit_from_above(material: Arc<dyn Material>) -> RayHit {
+    RayHit {
+      p: Point3d::zero(),
+      normal: Vec3::up(),
+      geometric_normal: Vec3::up(),
+      tangent: Vec3::right(),
+      bitangent: Vec3::forward(),
+      t: 1.0,
+      u: 0.0,
+      v: 0.0,
+      front_face: true,
+      vertex_color: None,
+      material,
+    }
+  }
+
+  /** Light under the coat is reflected back at grazing angles, whatever the ray length */
+  #[test]
+  fn coated_emission_at_grazing_angle() {
+    let light = Arc::new(DiffuseLight::color(Color::one(), 1.0));
+    let material = CoatedMaterial::new(light, 1.5, 0.0);
+    let hit = hit_from_above(Arc::new(material.clone()));
+    let p = Point3d::zero();
+    let from_above = Ray::new(p, Vec3::new(0.0, -1.0, 0.0));
+    let grazing_dir = Vec3::new(1.0, -0.05, 0.0).unit_vector();
+    // e.g. shadow rays are as long as the distance to the light
+    let grazing = Ray {
+      origin: p,
+      dir: grazing_dir * 5.0,
+    };
+    let expected = 1.0 - fresnel_dielectric(0.05f32.atan().sin(), 1.5);
+    assert_approx_eq!(material.emitted(&from_above, &hit).x(), 0.96, 1e-3);
+    assert_approx_eq!(material.emitted(&grazing, &hit).x(), expected, 1e-3);
+    assert!(expected < 0.5);
+  }
+
+  /** `weight` returned by `sample` has to agree with `eval / pdf` */
+  #[test]
+  fn sample_matches_eval_and_pdf() {
+    let materials: Vec<Arc<dyn Material>> = vec![
+      Arc::new(Lambert::color(0.5, 0.6, 0.7)),
+      Arc::new(OrenNayar::from_color(Color::new(0.5, 0.6, 0.7), 0.8)),
+      Arc::new(Metal {
+        albedo: Color::new(0.9, 0.8, 0.7),
+        roughness: 0.3,
+        film: None,
+      }),
+      Arc::new(Conductor::anisotropic(GOLD_IOR, 0.6, 0.2)),
+      Arc::new(Conductor {
+        film: Some(ThinFilm::new(1.6, 350.0)),
+        ..Conductor::new(ALUMINIUM_IOR, 0.3)
+      }),
+      Arc::new(RoughDielectric {
+        albedo: Color::new(0.9, 0.95, 1.0),
+        ior: 1.5,
+        roughness: 0.4,
+        absorption: Color::zero(),
+      }),
+      Arc::new(MixMaterial::constant(
+        Arc::new(Lambert::color(0.5, 0.6, 0.7)),
+        Arc::new(Conductor::new(GOLD_IOR, 0.3)),
+        0.4,
+      )),
+      Arc::new(CoatedMaterial::new(
+        Arc::new(Lambert::color(0.5, 0.6, 0.7)),
+        1.5,
+        0.2,
+      )),
+    ];
+    let wo = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    for material in materials {
+      let hit = hit_from_above(material.clone());
+      for _ in 0..100 {
+        let sample = match material.sample(&hit, wo) {
+          Some(s) => s,
+          None => continue,
+        };
+        assert!(!sample.delta);
+        let pdf = material.pdf(&hit, wo, sample.wi);
+        assert_approx_eq!(sample.pdf, pdf, pdf * 1e-3);
+        let expected = material.eval(&hit, wo, sample.wi) / pdf;
+        assert_approx_eq!(sample.weight.x(), expected.x(), 1e-3);
+        assert_approx_eq!(sample.weight.z(), expected.z(), 1e-3);
+      }
+    }
+  }
+
+  #[test]
+  fn delta_materials_do_not_eval() {
+    let materials: Vec<Arc<dyn Material>> = vec![
+      Arc::new(Metal {
+        albedo: Color::one(),
+        roughness: 0.0,
+        film: None,
+      }),
+      Arc::new(Conductor::new(SILVER_IOR, 0.0)),
+      Arc::new(RoughDielectric {
+        albedo: Color::one(),
+        ior: 1.5,
+        roughness: 0.0,
+        absorption: Color::zero(),
+      }),
+      Arc::new(Dielectric {
+        albedo: Color::one(),
+        ior: 1.5,
+        absorption: Color::zero(),
+        film: None,
+      }),
+    ];
+    let wo = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    for material in materials {
+      let hit = hit_from_above(material.clone());
+      let sample = material.sample(&hit, wo).unwrap();
+      assert!(sample.delta);
+      assert!(material.eval(&hit, wo, sample.wi).is_zero());
+      assert_eq!(material.pdf(&hit, wo, sample.wi), 0.0);
+    }
+  }
+
+  /** Highlight is stretched along `roughness_u`, which follows the tangent */
+  #[test]
+  fn anisotropy_follows_tangent() {
+    let material = Arc::new(Conductor::anisotropic(GOLD_IOR, 0.6, 0.1));
+    let along_u = hit_from_above(material.clone());
+    let along_v = RayHit {
+      tangent: Vec3::forward(),
+      bitangent: Vec3::right(),
+      ..along_u.clone()
+    };
+    let wo = Vec3::new(0.0, 1.0, 0.0);
+    let wi_x = Vec3::new(0.5, 1.0, 0.0).unit_vector();
+    let wi_z = Vec3::new(0.0, 1.0, 0.5).unit_vector();
+    let f = |hit: &RayHit, wi: Vec3| material.eval(hit, wo, wi).y();
+    assert!(f(&along_u, wi_x) > 2.0 * f(&along_u, wi_z));
+    assert_approx_eq!(f(&along_u, wi_x), f(&along_v, wi_z), 1e-4);
+    assert_approx_eq!(f(&along_u, wi_z), f(&along_v, wi_x), 1e-4);
+  }
+
+  /** Average `weight` of samples and integral of `eval` both estimate reflected energy */
+  #[test]
+  fn sampled_albedo_matches_eval() {
+    let material = Arc::new(Conductor::anisotropic(COPPER_IOR, 0.5, 0.3));
+    let hit = hit_from_above(material.clone());
+    let wo = Vec3::new(0.6, 0.8, 0.0);
+    let n = 50000;
+    let sampled: f32 = (0..n)
+      .filter_map(|_| material.sample(&hit, wo))
+      .map(|s| s.weight.y())
+      .sum();
+    let integrated: f32 = (0..n)
+      .map(|_| {
+        let wi = random_in_cone(Vec3::up(), 0.0);
+        material.eval(&hit, wo, wi).y() * 2.0 * PI
+      })
+      .sum();
+    let (sampled, integrated) = (sampled / n as f32, integrated / n as f32);
+    assert!(sampled < 1.0);
+    assert_approx_eq!(sampled, integrated, integrated * 0.05);
+  }
+
+  /** White rough diffuse surface must not lose energy, whatever the roughness */
+  #[test]
+  fn oren_nayar_preserves_energy() {
+    for roughness in [0.0, 0.5, 1.0] {
+      let material = Arc::new(OrenNayar::from_color(Color::one(), roughness));
+      let hit = hit_from_above(material.clone());
+      for wo in [Vec3::up(), Vec3::new(0.8, 0.2, 0.0).unit_vector()] {
+        let n = 20000;
+        let albedo: f32 = (0..n)
+          .filter_map(|_| material.sample(&hit, wo))
+          .map(|s| s.weight.y())
+          .sum();
+        assert_approx_eq!(albedo / n as f32, 1.0, 0.02);
+      }
+    }
+  }
+
+  #[test]
+  fn oren_nayar_is_flatter_than_lambert() {
+    let lambert = Arc::new(Lambert::color(0.5, 0.5, 0.5));
+    let smooth = Arc::new(OrenNayar::from_color(Color::uni(0.5), 0.0));
+    let rough = Arc::new(OrenNayar::from_color(Color::uni(0.5), 1.0));
+    let hit = hit_from_above(lambert.clone());
+    let wo = Vec3::new(1.0, 0.3, 0.0).unit_vector();
+    let towards_light = Vec3::new(1.0, 0.4, 0.0).unit_vector();
+    let away = Vec3::new(-1.0, 0.4, 0.0).unit_vector();
+
+    for wi in [towards_light, away] {
+      assert_approx_eq!(
+        smooth.eval(&hit, wo, wi).y(),
+        lambert.eval(&hit, wo, wi).y(),
+        1e-4
+      );
+    }
+    // more light goes back to the source than forward
+    let back = rough.eval(&hit, wo, towards_light).y();
+    assert!(back > lambert.eval(&hit, wo, towards_light).y());
+    assert!(back > 1.5 * rough.eval(&hit, wo, away).y());
+  }
+
+  #[test]
+  fn mix_weight_picks_material() {
+    let lambert = Arc::new(Lambert::color(0.2, 0.4, 0.6));
+    let mirror = Arc::new(Metal {
+      albedo: Color::one(),
+      roughness: 0.0,
+      film: None,
+    });
+    let wo = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    let only_first = MixMaterial::constant(lambert.clone(), mirror.clone(), 0.0);
+    let hit = hit_from_above(lambert.clone());
+    for _ in 0..20 {
+      assert!(!only_first.sample(&hit, wo).unwrap().delta);
+    }
+    let half = MixMaterial::constant(lambert.clone(), mirror, 0.5);
+    let wi = Vec3::up();
+    assert_approx_eq!(
+      half.eval(&hit, wo, wi).z(),
+      0.5 * lambert.eval(&hit, wo, wi).z(),
+      1e-5
+    );
+  }
+
+  /** Coat only redistributes light, white base under it still reflects everything */
+  #[test]
+  fn coated_white_base_preserves_energy() {
+    let white = Arc::new(Lambert::color(1.0, 1.0, 1.0));
+    for roughness in [0.0, 0.3] {
+      let material = Arc::new(CoatedMaterial::new(white.clone(), 1.5, roughness));
+      let hit = hit_from_above(material.clone());
+      let wo = Vec3::new(0.6, 0.8, 0.0);
+      let n = 20000;
+      let albedo: f32 = (0..n)
+        .filter_map(|_| material.sample(&hit, wo))
+        .map(|s| s.weight.y())
+        .sum();
+      let albedo = albedo / n as f32;
+      // some light is lost, it would bounce between the layers
+      assert!(albedo < 1.02);
+      assert!(albedo > 0.85);
+    }
+  }
+
+  #[test]
+  fn coat_reflects_more_at_grazing_angles() {
+    let black = Arc::new(Lambert::color(0.0, 0.0, 0.0));
+    let material = Arc::new(CoatedMaterial::new(black, 1.5, 0.0));
+    let hit = hit_from_above(material.clone());
+    let reflectance = |wo: Vec3| {
+      let n = 2000;
+      let total: f32 = (0..n)
+        .filter_map(|_| material.sample(&hit, wo.unit_vector()))
+        .map(|s| s.weight.y())
+        .sum();
+      total / n as f32
+    };
+    let head_on = reflectance(Vec3::up());
+    assert_approx_eq!(head_on, 0.04, 0.01);
+    assert!(reflectance(Vec3::new(1.0, 0.1, 0.0)) > 0.3);
+  }
+
+  /** Film doesn't absorb anything, light it doesn't reflect goes through */
+  #[test]
+  fn soap_bubble_preserves_energy() {
+    let material = Arc::new(Dielectric {
+      albedo: Color::one(),
+      ior: 1.0,
+      absorption: Color::zero(),
+      film: Some(ThinFilm::new(1.33, 400.0)),
+    });
+    let hit = hit_from_above(material.clone());
+    let wo = Vec3::new(0.6, 0.8, 0.0);
+    let n = 20000;
+    let (mut total, mut reflected) = (Color::zero(), Color::zero());
+    for _ in 0..n {
+      let sample = material.sample(&hit, wo).unwrap();
+      total = total + sample.weight / n as f32;
+      if sample.wi.y() > 0.0 {
+        reflected = reflected + sample.weight / n as f32;
+      }
+    }
+    assert_approx_eq!(total.x(), 1.0, 0.05);
+    assert_approx_eq!(total.z(), 1.0, 0.05);
+    // no film would mean no reflection at all, the film reflects some colors more
+    assert!(reflected.max_component() > 0.05);
+    assert!((reflected.x() - reflected.z()).abs() > 0.02);
+  }
+
+  /** Same as above, but over the whole sphere and from both sides of the surface */
+  #[test]
+  fn rough_dielectric_sampling_matches_eval() {
+    let material = Arc::new(RoughDielectric {
+      albedo: Color::one(),
+      ior: 1.5,
+      roughness: 0.5,
+      absorption: Color::zero(),
+    });
+    let wo = Vec3::new(0.6, 0.8, 0.0);
+    for front_face in [true, false] {
+      let hit = RayHit {
+        front_face,
+        ..hit_from_above(material.clone())
+      };
+      // uniform sampling of the sphere is much noisier, needs more samples
+      let (n, n_uniform) = (50000, 500000);
+      let sampled: f32 = (0..n)
+        .filter_map(|_| material.sample(&hit, wo))
+        .map(|s| s.weight.y())
+        .sum();
+      let integrated: f32 = (0..n_uniform)
+        .map(|_| {
+          let wi = random_in_cone(Vec3::up(), -1.0);
+          material.eval(&hit, wo, wi).y() * 4.0 * PI
+        })
+        .sum();
+      let (sampled, integrated) = (sampled / n as f32, integrated / n_uniform as f32);
+      assert_approx_eq!(sampled, integrated, integrated * 0.05);
+    }
+  }
+
+  /** From inside the glass, past the critical angle light can't get out */
+  #[test]
+  fn rough_dielectric_total_internal_reflection() {
+    let material = Arc::new(RoughDielectric {
+      albedo: Color::one(),
+      ior: 1.5,
+      roughness: 0.05,
+      absorption: Color::zero(),
+    });
+    let hit = RayHit {
+      front_face: false,
+      ..hit_from_above(material.clone())
+    };
+    let wo = Vec3::new(0.95, 0.3, 0.0).unit_vector();
+    for _ in 0..100 {
+      if let Some(sample) = material.sample(&hit, wo) {
+        assert!(sample.wi.y() > 0.0);
+      }
+    }
+  }
+
+  #[test]
+  fn absorption_depends_on_distance() {
+    let material = Arc::new(Dielectric {
+      albedo: Color::one(),
+      ior: 1.0, // never reflects
+      absorption: Color::new(0.0, 0.5, 2.0),
+      film: None,
+    });
+    let hit = |front_face, t| RayHit {
+      front_face,
+      t,
+      ..hit_from_above(material.clone())
+    };
+    let wo = Vec3::up();
+    let entering = material.sample(&hit(true, 3.0), wo).unwrap().weight;
+    assert!(entering.x() == 1.0 && entering.z() == 1.0);
+
+    let thin = material.sample(&hit(false, 1.0), wo).unwrap().weight;
+    let thick = material.sample(&hit(false, 2.0), wo).unwrap().weight;
+    assert_approx_eq!(thin.x(), 1.0);
+    assert_approx_eq!(thin.y(), (-0.5f32).exp());
+    assert_approx_eq!(thick.z(), thin.z() * thin.z());
+  }
 }
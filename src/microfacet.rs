@@ -0,0 +1,578 @@
+use std::f32::consts::PI;
+
+use crate::material::BSDFSample;
+use crate::traceable::RayHit;
+use crate::utils::RGB_WAVELENGTHS_NM;
+use crate::vec3::{Color, Vec3};
+
+// Rough surfaces made of tiny perfect mirrors (microfacets). How their normals are
+// spread is described by GGX (Trowbridge-Reitz) distribution, how they hide each
+// other by Smith masking function. Everything is in the local shading space,
+// where the normal is +z.
+//
+// https://jcgt.org/published/0003/02/03/ (Heitz - Understanding the Masking-Shadowing Function)
+// https://jcgt.org/published/0007/04/01/ (Heitz - Sampling the GGX Distribution of Visible Normals)
+
+/** Below this alpha the surface is a perfect mirror (roughness 0.001) */
+const SMOOTH_ALPHA: f32 = 1e-6;
+/** Very low alpha makes the distribution numerically unstable */
+const MIN_ALPHA: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct GGX {
+  /** Along the first tangent */
+  pub alpha_x: f32,
+  /** Along the second tangent */
+  pub alpha_y: f32,
+}
+
+impl GGX {
+  /** Roughness is squared, so that it changes the look more linearly */
+  pub fn from_roughness(roughness_u: f32, roughness_v: f32) -> Self {
+    let to_alpha = |r: f32| r.clamp(0.0, 1.0).powi(2);
+    Self {
+      alpha_x: to_alpha(roughness_u),
+      alpha_y: to_alpha(roughness_v),
+    }
+  }
+
+  /** Reflects like a mirror, should be sampled as delta distribution */
+  pub fn is_smooth(&self) -> bool {
+    self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+  }
+
+  fn alpha(&self) -> (f32, f32) {
+    (self.alpha_x.max(MIN_ALPHA), self.alpha_y.max(MIN_ALPHA))
+  }
+
+  /** Density of microfacet normals, wrt. projected area of the surface */
+  pub fn d(&self, wm: Vec3) -> f32 {
+    if wm.z() <= 0.0 {
+      return 0.0;
+    }
+    let (ax, ay) = self.alpha();
+    let e = (wm.x() / ax).powi(2) + (wm.y() / ay).powi(2) + wm.z().powi(2);
+    1.0 / (PI * ax * ay * e * e)
+  }
+
+  /** Smith's auxiliary function, area of microfacets hidden when looking from `w` */
+  fn lambda(&self, w: Vec3) -> f32 {
+    let (ax, ay) = self.alpha();
+    let cos_sq = w.z() * w.z();
+    if cos_sq == 0.0 {
+      return f32::INFINITY;
+    }
+    let alpha_tan_sq = ((w.x() * ax).powi(2) + (w.y() * ay).powi(2)) / cos_sq;
+    ((1.0 + alpha_tan_sq).sqrt() - 1.0) / 2.0
+  }
+
+  /** Fraction of microfacets visible from `w` */
+  pub fn g1(&self, w: Vec3) -> f32 {
+    1.0 / (1.0 + self.lambda(w))
+  }
+
+  /** Fraction of microfacets visible from both `wo` and `wi` (height-correlated) */
+  pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+    1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+  }
+
+  /** Density of normals visible from `wo`, which is what `sample_normal` picks */
+  pub fn visible_normal_pdf(&self, wo: Vec3, wm: Vec3) -> f32 {
+    if wo.z() <= 0.0 {
+      return 0.0;
+    }
+    self.g1(wo) * wo.dot(wm).max(0.0) * self.d(wm) / wo.z()
+  }
+
+  /** Pick microfacet normal visible from `wo`. Sample the projected hemisphere */
+  pub fn sample_normal(&self, wo: Vec3) -> Vec3 {
+    let (ax, ay) = self.alpha();
+    // stretch the view, so the microfacets form a hemisphere
+    let wh = Vec3::new(ax * wo.x(), ay * wo.y(), wo.z()).unit_vector();
+    let len_sq = wh.x() * wh.x() + wh.y() * wh.y();
+    let t1 = if len_sq > 0.0 {
+      Vec3::new(-wh.y(), wh.x(), 0.0) / len_sq.sqrt()
+    } else {
+      Vec3::right()
+    };
+    let t2 = wh.cross(t1);
+
+    // point on the disk, warped to the part of hemisphere that is visible
+    let r = rand::random::<f32>().sqrt();
+    let phi = 2.0 * PI * rand::random::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + wh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + wh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // and back to the ellipsoid
+    Vec3::new(ax * nh.x(), ay * nh.y(), nh.z().max(1e-6)).unit_vector()
+  }
+}
+
+///////////////////////
+// Fresnel
+
+/** Schlick's approximation, with reflectance at normal incidence per channel */
+pub fn fresnel_schlick(f0: Color, cos: f32) -> Color {
+  let t = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+  f0 + (Color::one() - f0) * t
+}
+
+/**
+Exact reflectance of a conductor with complex IOR `eta + i*k`, unpolarized light.
+
+https://pbr-book.org/3ed-2018/Reflection_Models/Specular_Reflection_and_Transmission#FresnelReflectance
+*/
+pub fn fresnel_conductor(cos: f32, eta: Color, k: Color) -> Color {
+  let cos = cos.clamp(0.0, 1.0);
+  let cos_sq = cos * cos;
+  let sin_sq = 1.0 - cos_sq;
+  let channel = |eta: f32, k: f32| {
+    let t0 = eta * eta - k * k - sin_sq;
+    let a_sq_plus_b_sq = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a_sq_plus_b_sq + cos_sq;
+    let a = (0.5 * (a_sq_plus_b_sq + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos_sq * a_sq_plus_b_sq + sin_sq * sin_sq;
+    let t4 = t2 * sin_sq;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+  };
+  Color::new(
+    channel(eta.x(), k.x()),
+    channel(eta.y(), k.y()),
+    channel(eta.z(), k.z()),
+  )
+}
+
+/**
+Exact reflectance between two dielectrics, unpolarized light. `eta` is the IOR
+of the side light goes into divided by the side it comes from. 1 for total internal reflection.
+*/
+pub fn fresnel_dielectric(cos: f32, eta: f32) -> f32 {
+  let cos_i = cos.clamp(0.0, 1.0);
+  let sin_sq_t = (1.0 - cos_i * cos_i) / (eta * eta);
+  if sin_sq_t >= 1.0 {
+    return 1.0;
+  }
+  let cos_t = (1.0 - sin_sq_t).sqrt();
+  let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+///////////////////////
+// Thin film
+//
+// Transparent film (soap, oil, oxide layer) thinner than the wavelength of light, on top
+// of some other material. Light reflected from the top and the bottom of the film interferes,
+// so how much is reflected depends on the wavelength. Colors change with the thickness
+// of the film and the viewing angle. Evaluated at one wavelength per RGB channel.
+//
+// https://en.wikipedia.org/wiki/Thin-film_interference
+// https://belcour.github.io/blog/research/publication/2017/05/01/brdf-thin-film.html
+
+#[derive(Clone, Copy, Debug)]
+/** Just enough complex numbers for amplitudes of light waves */
+struct Complex {
+  re: f32,
+  im: f32,
+}
+
+impl Complex {
+  fn new(re: f32, im: f32) -> Self {
+    Self { re, im }
+  }
+
+  fn real(re: f32) -> Self {
+    Self::new(re, 0.0)
+  }
+
+  fn norm_sq(&self) -> f32 {
+    self.re * self.re + self.im * self.im
+  }
+
+  /** Principal square root, real part is never negative */
+  fn sqrt(&self) -> Self {
+    let r = self.norm_sq().sqrt();
+    let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+    let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+    Self::new(re, if self.im < 0.0 { -im } else { im })
+  }
+
+  /** e^(i*self) */
+  fn exp_i(&self) -> Self {
+    let decay = (-self.im).exp();
+    Self::new(decay * self.re.cos(), decay * self.re.sin())
+  }
+}
+
+impl std::ops::Add for Complex {
+  type Output = Complex;
+  fn add(self, o: Complex) -> Complex {
+    Complex::new(self.re + o.re, self.im + o.im)
+  }
+}
+
+impl std::ops::Sub for Complex {
+  type Output = Complex;
+  fn sub(self, o: Complex) -> Complex {
+    Complex::new(self.re - o.re, self.im - o.im)
+  }
+}
+
+impl std::ops::Mul for Complex {
+  type Output = Complex;
+  fn mul(self, o: Complex) -> Complex {
+    Complex::new(
+      self.re * o.re - self.im * o.im,
+      self.re * o.im + self.im * o.re,
+    )
+  }
+}
+
+impl std::ops::Div for Complex {
+  type Output = Complex;
+  fn div(self, o: Complex) -> Complex {
+    let d = o.norm_sq();
+    Complex::new(
+      (self.re * o.re + self.im * o.im) / d,
+      (self.im * o.re - self.re * o.im) / d,
+    )
+  }
+}
+
+/**
+Cosine of the angle inside medium with IOR `n`, for light coming at `sin_sq` from
+medium with IOR `n_from`. Complex past the critical angle and inside conductors.
+*/
+fn refracted_cos(n_from: f32, sin_sq: f32, n: Complex) -> Complex {
+  let sin_sq_t = Complex::real(n_from * n_from * sin_sq) / (n * n);
+  (Complex::real(1.0) - sin_sq_t).sqrt()
+}
+
+/** Amplitudes of the reflected wave (s and p polarized) going from `n1` into `n2` */
+fn amplitude_reflection(
+  n1: Complex,
+  cos1: Complex,
+  n2: Complex,
+  cos2: Complex,
+) -> (Complex, Complex) {
+  let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+  let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+  (rs, rp)
+}
+
+/**
+Reflectance of a material with complex IOR `eta + i*k` (`k` is 0 for dielectrics)
+covered by a film with `film_ior` and `thickness` in nanometres. Light comes from
+medium with IOR `outside`. Same as `fresnel_conductor` for film with zero thickness.
+Sums all the bounces inside the film (Airy summation), unpolarized light.
+*/
+pub fn fresnel_thin_film(
+  cos: f32,
+  outside: f32,
+  film_ior: f32,
+  thickness: f32,
+  eta: Color,
+  k: Color,
+) -> Color {
+  let cos1 = cos.clamp(1e-4, 1.0);
+  let sin_sq = 1.0 - cos1 * cos1;
+  let n1 = Complex::real(outside);
+  let n2 = Complex::real(film_ior);
+  let cos2 = refracted_cos(outside, sin_sq, n2);
+  let (r12_s, r12_p) = amplitude_reflection(n1, Complex::real(cos1), n2, cos2);
+
+  let channel = |i: usize| {
+    let n3 = Complex::new(eta[i], k[i]);
+    let cos3 = refracted_cos(outside, sin_sq, n3);
+    let (r23_s, r23_p) = amplitude_reflection(n2, cos2, n3, cos3);
+    // light reflected from the bottom travels through the film twice
+    let phase = Complex::real(4.0 * PI * film_ior * thickness / RGB_WAVELENGTHS_NM[i]) * cos2;
+    let shift = phase.exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+      let r23 = r23 * shift;
+      ((r12 + r23) / (Complex::real(1.0) + r12 * r23)).norm_sq()
+    };
+    0.5 * (airy(r12_s, r23_s) + airy(r12_p, r23_p))
+  };
+  Color::new(channel(0), channel(1), channel(2))
+}
+
+///////////////////////
+// Reflection
+//
+// Shared by materials that only reflect. `fresnel` gets the cosine between
+// `wo` and the microfacet normal. Anisotropy follows `RayHit::tangent`.
+
+/** Mirror `wo` around `wm`. Both point away from the surface */
+pub fn reflect_local(wo: Vec3, wm: Vec3) -> Vec3 {
+  wm * (2.0 * wo.dot(wm)) - wo
+}
+
+/**
+Bend `wo` through the surface with normal `wm` (on the same side as `wo`).
+`eta` as in `fresnel_dielectric`. `None` for total internal reflection.
+*/
+pub fn refract_local(wo: Vec3, wm: Vec3, eta: f32) -> Option<Vec3> {
+  let cos_i = wo.dot(wm);
+  let sin_sq_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+  if sin_sq_t >= 1.0 {
+    return None;
+  }
+  let cos_t = (1.0 - sin_sq_t).sqrt();
+  Some(!wo / eta + wm * (cos_i / eta - cos_t))
+}
+
+pub fn reflection_sample(
+  ggx: &GGX,
+  hit: &RayHit,
+  wo: Vec3,
+  fresnel: impl Fn(f32) -> Color,
+) -> Option<BSDFSample> {
+  let frame = hit.shading_frame();
+  let wo_local = frame.world_to_local(wo);
+  if wo_local.z() <= 0.0 {
+    return None;
+  }
+
+  if ggx.is_smooth() {
+    let wi = frame.local(-wo_local.x(), -wo_local.y(), wo_local.z());
+    return Some(BSDFSample {
+      wi,
+      weight: fresnel(wo_local.z()),
+      pdf: 1.0,
+      delta: true,
+    });
+  }
+
+  let wm = ggx.sample_normal(wo_local);
+  let wi_local = reflect_local(wo_local, wm);
+  if wi_local.z() <= 0.0 {
+    return None; // reflected into the surface, shadowed by other microfacets
+  }
+  let cos_om = wo_local.dot(wm);
+  // BSDF * cos / pdf = (D * G * F / (4 * cos_o)) / (G1 * D * cos_om / cos_o / (4 * cos_om))
+  Some(BSDFSample {
+    wi: frame.local(wi_local.x(), wi_local.y(), wi_local.z()),
+    weight: fresnel(cos_om) * (ggx.g(wo_local, wi_local) / ggx.g1(wo_local)),
+    pdf: ggx.visible_normal_pdf(wo_local, wm) / (4.0 * cos_om),
+    delta: false,
+  })
+}
+
+/** BSDF * cos. Zero for smooth surfaces, those are delta distributions */
+pub fn reflection_eval(
+  ggx: &GGX,
+  hit: &RayHit,
+  wo: Vec3,
+  wi: Vec3,
+  fresnel: impl Fn(f32) -> Color,
+) -> Color {
+  let frame = hit.shading_frame();
+  let (wo, wi) = (frame.world_to_local(wo), frame.world_to_local(wi));
+  if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+    return Color::zero();
+  }
+  let wm = (wo + wi).unit_vector();
+  fresnel(wo.dot(wm)) * (ggx.d(wm) * ggx.g(wo, wi) / (4.0 * wo.z()))
+}
+
+pub fn reflection_pdf(ggx: &GGX, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+  let frame = hit.shading_frame();
+  let (wo, wi) = (frame.world_to_local(wo), frame.world_to_local(wi));
+  if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+    return 0.0;
+  }
+  let wm = (wo + wi).unit_vector();
+  // half vector changes 4 * cos slower than the reflected direction
+  ggx.visible_normal_pdf(wo, wm) / (4.0 * wo.dot(wm))
+}
+
+///////////////////////
+// Reflection and transmission
+//
+// For dielectrics, in local space. `eta` as in `fresnel_dielectric`. Which of the two
+// happens is picked by the Fresnel reflectance. Transmitted light is divided by `eta²`,
+// because it gets squeezed into smaller solid angle in the dense medium.
+//
+// https://www.cs.cornell.edu/~srm/publications/EGSR07-btdf.pdf
+
+/**
+Half vector for refraction, oriented towards `wo`. `None` if `wo` or `wi`
+would be on the back side of the microfacet.
+*/
+fn refraction_half_vector(wo: Vec3, wi: Vec3, eta: f32) -> Option<Vec3> {
+  let mut wm = (wo + wi * eta).unit_vector();
+  if wm.z() < 0.0 {
+    wm = !wm;
+  }
+  if wo.dot(wm) <= 0.0 || wi.dot(wm) >= 0.0 {
+    return None;
+  }
+  Some(wm)
+}
+
+/** Pick reflected or refracted direction. Smooth surfaces use the macro normal */
+pub fn dielectric_sample(ggx: &GGX, eta: f32, wo: Vec3) -> Option<Vec3> {
+  if wo.z() <= 0.0 {
+    return None;
+  }
+  let wm = if ggx.is_smooth() {
+    Vec3::new(0.0, 0.0, 1.0)
+  } else {
+    ggx.sample_normal(wo)
+  };
+  let reflectance = fresnel_dielectric(wo.dot(wm), eta);
+  let wi = match refract_local(wo, wm, eta) {
+    Some(wi) if rand::random::<f32>() >= reflectance => wi,
+    _ => reflect_local(wo, wm),
+  };
+  // has to end up on the same side of the macro surface as of the microfacet
+  let reflected = wi.z() > 0.0;
+  if wi.z() == 0.0 || reflected != (wi.dot(wm) > 0.0) {
+    return None;
+  }
+  Some(wi)
+}
+
+/** BSDF * cos. Zero for smooth surfaces, those are delta distributions */
+pub fn dielectric_eval(ggx: &GGX, eta: f32, wo: Vec3, wi: Vec3) -> f32 {
+  if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() == 0.0 {
+    return 0.0;
+  }
+  if wi.z() > 0.0 {
+    let wm = (wo + wi).unit_vector();
+    let reflectance = fresnel_dielectric(wo.dot(wm), eta);
+    return reflectance * ggx.d(wm) * ggx.g(wo, wi) / (4.0 * wo.z());
+  }
+
+  let wm = match refraction_half_vector(wo, wi, eta) {
+    Some(wm) => wm,
+    None => return 0.0,
+  };
+  let transmittance = 1.0 - fresnel_dielectric(wo.dot(wm), eta);
+  let denom = (wi.dot(wm) + wo.dot(wm) / eta).powi(2);
+  let f_cos =
+    transmittance * ggx.d(wm) * ggx.g(wo, wi) * (wi.dot(wm) * wo.dot(wm)).abs() / (wo.z() * denom);
+  f_cos / (eta * eta)
+}
+
+pub fn dielectric_pdf(ggx: &GGX, eta: f32, wo: Vec3, wi: Vec3) -> f32 {
+  if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() == 0.0 {
+    return 0.0;
+  }
+  if wi.z() > 0.0 {
+    let wm = (wo + wi).unit_vector();
+    let reflectance = fresnel_dielectric(wo.dot(wm), eta);
+    return reflectance * ggx.visible_normal_pdf(wo, wm) / (4.0 * wo.dot(wm));
+  }
+
+  let wm = match refraction_half_vector(wo, wi, eta) {
+    Some(wm) => wm,
+    None => return 0.0,
+  };
+  let transmittance = 1.0 - fresnel_dielectric(wo.dot(wm), eta);
+  // how fast the half vector changes with the refracted direction
+  let denom = (wi.dot(wm) + wo.dot(wm) / eta).powi(2);
+  transmittance * ggx.visible_normal_pdf(wo, wm) * wi.dot(wm).abs() / denom
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_approx_eq::assert_approx_eq;
+
+  /** Integral over the upper hemisphere, midpoint rule on a grid uniform in solid angle */
+  fn integrate_upper(f: impl Fn(Vec3) -> f32) -> f32 {
+    let n = 500;
+    let mut sum = 0.0;
+    for i in 0..n {
+      let z = (i as f32 + 0.5) / n as f32;
+      let r = (1.0 - z * z).sqrt();
+      for j in 0..n {
+        let phi = (j as f32 + 0.5) / n as f32 * 2.0 * PI;
+        sum += f(Vec3::new(r * phi.cos(), r * phi.sin(), z));
+      }
+    }
+    sum * 2.0 * PI / (n * n) as f32
+  }
+
+  /** Projected area of all microfacets is the area of the surface */
+  #[test]
+  fn d_is_normalized() {
+    for ggx in [GGX::from_roughness(0.5, 0.5), GGX::from_roughness(0.7, 0.3)] {
+      let integral = integrate_upper(|wm| ggx.d(wm) * wm.z());
+      assert_approx_eq!(integral, 1.0, 0.01);
+    }
+  }
+
+  #[test]
+  fn visible_normal_pdf_matches_sampling() {
+    let ggx = GGX::from_roughness(0.6, 0.4);
+    let wo = Vec3::new(0.5, 0.2, 0.8).unit_vector();
+    assert_approx_eq!(integrate_upper(|wm| ggx.visible_normal_pdf(wo, wm)), 1.0, 0.01);
+
+    // sampled normals are distributed the same as the pdf says
+    let n = 50000;
+    let mut mean = Vec3::zero();
+    for _ in 0..n {
+      let wm = ggx.sample_normal(wo);
+      assert!(wm.z() > 0.0 && wo.dot(wm) > -1e-4);
+      mean = mean + wm / n as f32;
+    }
+    for axis in 0..3 {
+      let expected = integrate_upper(|wm| wm[axis] * ggx.visible_normal_pdf(wo, wm));
+      assert_approx_eq!(mean[axis], expected, 0.01);
+    }
+  }
+
+  #[test]
+  fn fresnel_conductor_normal_incidence() {
+    let (eta, k) = (Color::new(0.2, 0.9, 1.1), Color::new(3.9, 2.4, 2.1));
+    let f = fresnel_conductor(1.0, eta, k);
+    let expected = |n: f32, k: f32| ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k);
+    assert_approx_eq!(f.x(), expected(0.2, 3.9), 1e-4);
+    assert_approx_eq!(f.z(), expected(1.1, 2.1), 1e-4);
+    // every conductor is a mirror at grazing angles
+    assert_approx_eq!(fresnel_conductor(0.0, eta, k).y(), 1.0, 1e-4);
+  }
+
+  #[test]
+  fn thin_film_without_interference() {
+    let (eta, k) = (Color::new(0.2, 0.9, 1.1), Color::new(3.9, 2.4, 2.1));
+    for cos in [1.0, 0.7, 0.2] {
+      let expected = fresnel_conductor(cos, eta, k);
+      // no film at all, or film of the same IOR as the air around it
+      let no_film = fresnel_thin_film(cos, 1.0, 1.4, 0.0, eta, k);
+      let invisible_film = fresnel_thin_film(cos, 1.0, 1.0, 300.0, eta, k);
+      for f in [no_film, invisible_film] {
+        assert_approx_eq!(f.x(), expected.x(), 1e-3);
+        assert_approx_eq!(f.z(), expected.z(), 1e-3);
+      }
+      let glass = fresnel_thin_film(cos, 1.0, 1.4, 0.0, Color::uni(1.5), Color::zero());
+      assert_approx_eq!(glass.y(), fresnel_dielectric(cos, 1.5), 1e-3);
+    }
+  }
+
+  /** Soap film in the air, quarter wavelength thick reflects the most */
+  #[test]
+  fn thin_film_interference() {
+    let air = (Color::one(), Color::zero());
+    let film = |thickness| fresnel_thin_film(1.0, 1.0, 1.33, thickness, air.0, air.1);
+    let green = RGB_WAVELENGTHS_NM[1];
+    let quarter = film(green / (4.0 * 1.33)).y();
+    let half = film(green / (2.0 * 1.33)).y();
+    // two reflections with r = 0.14 add up or cancel out
+    let r = (0.33f32 / 2.33).powi(2);
+    assert_approx_eq!(quarter, 4.0 * r / (1.0 + r).powi(2), 1e-3);
+    assert_approx_eq!(half, 0.0, 1e-4);
+    // different thickness of the same film has a different color
+    let c = film(green / (4.0 * 1.33));
+    assert!(c.y() > c.x() && c.y() > c.z());
+  }
+}
@@ -0,0 +1,280 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::material::{BSDFSample, Material};
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::traceable::RayHit;
+use crate::vec3::{Color, Vec3};
+
+// Detail (bricks, scratches, wood grain) that tilts the shading normal instead of
+// changing the geometry. Orientation of the maps comes from `RayHit::tangent` and
+// `RayHit::bitangent`, so the surface needs texture coordinates.
+//
+// https://learnopengl.com/Advanced-Lighting/Normal-Mapping
+// https://pbr-book.org/3ed-2018/Materials/Bump_Mapping
+
+/** Step in texture coordinates when comparing heights of the neighbouring points */
+const BUMP_DELTA: f32 = 0.0005;
+
+/** `hit.normal` is flipped for back faces. This flips it (or any other vector) back */
+fn outward(hit: &RayHit, v: Vec3) -> Vec3 {
+  if hit.front_face {
+    v
+  } else {
+    !v
+  }
+}
+
+/** Tilts the shading normal */
+pub trait NormalModifier: fmt::Debug + Send + Sync {
+  /**
+  New shading normal, both it and `normal` are on the outer side of the surface.
+  Does not have to be normalized.
+  */
+  fn tilt(&self, hit: &RayHit, normal: Vec3) -> Vec3;
+}
+
+///////////////////////
+// Normal map
+
+#[derive(Clone, Debug)]
+/**
+Tangent-space normal map, usually an `ImageTex`. Red is along `tangent` (u grows),
+green along `bitangent` (v grows), blue along the normal. `[0.5, 0.5, 1.0]` is flat.
+*/
+pub struct NormalMap {
+  pub map: Arc<dyn Texture>,
+  /** 1 is the normal as stored in the map, 0 is flat */
+  pub strength: f32,
+  /**
+  Green points up in the image (OpenGL style, glTF) instead of along v.
+  Texture rows go down with v, so it's flipped
+  */
+  pub green_up: bool,
+}
+
+impl NormalModifier for NormalMap {
+  fn tilt(&self, hit: &RayHit, normal: Vec3) -> Vec3 {
+    let c = self.map.sample(hit) * 2.0 - Color::one();
+    let green = if self.green_up { -c.y() } else { c.y() };
+    let tangent = hit.tangent - normal * normal.dot(hit.tangent);
+    if tangent.near_zero() {
+      return normal;
+    }
+    let tangent = tangent.unit_vector();
+    let mut bitangent = normal.cross(tangent);
+    // texture can be mirrored
+    if bitangent.dot(hit.bitangent) < 0.0 {
+      bitangent = !bitangent;
+    }
+    tangent * (c.x() * self.strength) + bitangent * (green * self.strength) + normal * c.z()
+  }
+}
+
+///////////////////////
+// Bump map
+
+#[derive(Clone, Debug)]
+/**
+Surface is moved along the normal by `height` (red channel), normal is recalculated
+from that. Height changes between neighbouring points are found by sampling the
+texture again, so it should be smooth (e.g. `NoiseTex`).
+*/
+pub struct BumpMap {
+  pub height: Arc<dyn Texture>,
+  /** Height of the bumps in scene units, for texture value 1 */
+  pub scale: f32,
+}
+
+impl BumpMap {
+  fn height_at(&self, hit: &RayHit, du: f32, dv: f32) -> f32 {
+    let moved = RayHit {
+      p: hit.p + hit.tangent * du + hit.bitangent * dv,
+      u: hit.u + du,
+      v: hit.v + dv,
+      ..hit.clone()
+    };
+    self.height.sample(&moved).x() * self.scale
+  }
+}
+
+impl NormalModifier for BumpMap {
+  fn tilt(&self, hit: &RayHit, normal: Vec3) -> Vec3 {
+    let h = self.height_at(hit, 0.0, 0.0);
+    let dh_du = (self.height_at(hit, BUMP_DELTA, 0.0) - h) / BUMP_DELTA;
+    let dh_dv = (self.height_at(hit, 0.0, BUMP_DELTA) - h) / BUMP_DELTA;
+    let dpdu = hit.tangent + normal * dh_du;
+    let dpdv = hit.bitangent + normal * dh_dv;
+    let bumped = dpdu.cross(dpdv);
+    if bumped.near_zero() {
+      return normal;
+    }
+    // for flat surface it's `tangent x bitangent`, mirrored texture flips it
+    if hit.tangent.cross(hit.bitangent).dot(normal) < 0.0 {
+      !bumped
+    } else {
+      bumped
+    }
+  }
+}
+
+///////////////////////
+// Material
+
+#[derive(Clone, Debug)]
+/**
+Material `base` sees normal tilted by `modifier`. Rays still start from the actual
+surface, see `RayHit::spawn_ray`.
+*/
+pub struct ModifiedNormal {
+  pub base: Arc<dyn Material>,
+  pub modifier: Arc<dyn NormalModifier>,
+}
+
+impl ModifiedNormal {
+  pub fn new(base: Arc<dyn Material>, modifier: Arc<dyn NormalModifier>) -> Self {
+    Self { base, modifier }
+  }
+
+  fn shading_hit(&self, hit: &RayHit) -> RayHit {
+    let normal = outward(hit, hit.normal);
+    let tilted = self.modifier.tilt(hit, normal);
+    // can't tilt so much that it faces into the surface
+    let tilted = if tilted.dot(outward(hit, hit.geometric_normal)) > 0.0 {
+      tilted.unit_vector()
+    } else {
+      normal
+    };
+    RayHit {
+      normal: outward(hit, tilted),
+      ..hit.clone()
+    }
+  }
+}
+
+impl Material for ModifiedNormal {
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    self.base.sample(&self.shading_hit(hit), wo)
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    self.base.eval(&self.shading_hit(hit), wo, wi)
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    self.base.pdf(&self.shading_hit(hit), wo, wi)
+  }
+
+  fn emitted(&self, r_in: &Ray, hit: &RayHit) -> Color {
+    self.base.emitted(r_in, &self.shading_hit(hit))
+  }
+
+  fn is_emissive(&self) -> bool {
+    self.base.is_emissive()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::Lambert;
+  use crate::texture::{SolidColorTex, UVDebugTex};
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn hit(front_face: bool) -> RayHit {
+    let normal = if front_face { Vec3::up() } else { !Vec3::up() };
+    RayHit {
+      p: Point3d::zero(),
+      normal,
+      geometric_normal: normal,
+      tangent: Vec3::right(),
+      bitangent: Vec3::forward(),
+      t: 1.0,
+      u: 0.3,
+      v: 0.6,
+      front_face,
+      vertex_color: None,
+      material: Arc::new(Lambert::color(0.5, 0.5, 0.5)),
+    }
+  }
+
+  fn normal_map(r: f32, g: f32, b: f32, strength: f32) -> ModifiedNormal {
+    ModifiedNormal::new(
+      Arc::new(Lambert::color(0.5, 0.5, 0.5)),
+      Arc::new(NormalMap {
+        map: Arc::new(SolidColorTex::new(r, g, b)),
+        strength,
+        green_up: false,
+      }),
+    )
+  }
+
+  #[test]
+  fn flat_normal_map() {
+    for material in [
+      normal_map(0.5, 0.5, 1.0, 1.0),
+      normal_map(0.75, 0.5, 0.933, 0.0),
+    ] {
+      let n = material.shading_hit(&hit(true)).normal;
+      assert_approx_eq!(n.y(), 1.0, 1e-4);
+    }
+  }
+
+  #[test]
+  fn normal_map_tilts_towards_tangent() {
+    let material = normal_map(0.75, 0.5, 0.933, 1.0);
+    let n = material.shading_hit(&hit(true)).normal;
+    assert_approx_eq!(n.x(), 0.5, 1e-3);
+    assert_approx_eq!(n.y(), 0.866, 1e-3);
+    assert_approx_eq!(n.z(), 0.0, 1e-4);
+    // from below, the same surface is tilted the other way
+    let n = material.shading_hit(&hit(false)).normal;
+    assert_approx_eq!(n.x(), -0.5, 1e-3);
+    assert_approx_eq!(n.y(), -0.866, 1e-3);
+  }
+
+  #[test]
+  fn normal_map_green_up() {
+    let hit = hit(true);
+    let mut material = normal_map(0.5, 0.75, 0.933, 1.0);
+    let n = material.shading_hit(&hit).normal;
+    assert_approx_eq!(n.dot(hit.bitangent), 0.5, 1e-3);
+    material.modifier = Arc::new(NormalMap {
+      map: Arc::new(SolidColorTex::new(0.5, 0.75, 0.933)),
+      strength: 1.0,
+      green_up: true,
+    });
+    let n = material.shading_hit(&hit).normal;
+    assert_approx_eq!(n.dot(hit.bitangent), -0.5, 1e-3);
+    assert_approx_eq!(n.x(), 0.0, 1e-4);
+  }
+
+  /** Height grows with `u`, so the normal leans back */
+  #[test]
+  fn bump_map_slope() {
+    let material = ModifiedNormal::new(
+      Arc::new(Lambert::color(0.5, 0.5, 0.5)),
+      Arc::new(BumpMap {
+        height: Arc::new(UVDebugTex {}),
+        scale: 0.5,
+      }),
+    );
+    let hit = hit(true);
+    let shading_hit = material.shading_hit(&hit);
+    let expected = Vec3::new(-0.5, 1.0, 0.0).unit_vector();
+    assert_approx_eq!(shading_hit.normal.x(), expected.x(), 1e-3);
+    assert_approx_eq!(shading_hit.normal.y(), expected.y(), 1e-3);
+    assert_approx_eq!(shading_hit.normal.z(), 0.0, 1e-3);
+    assert_approx_eq!(shading_hit.geometric_normal.y(), 1.0);
+
+    // base material is lit the most from the direction of the tilted normal
+    let wo = Vec3::up();
+    assert_approx_eq!(
+      material.eval(&hit, wo, expected).y(),
+      0.5 / std::f32::consts::PI,
+      1e-4
+    );
+  }
+}
@@ -0,0 +1,686 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::light::DiffuseLight;
+use crate::material::{Dielectric, Lambert, Material, Metal};
+use crate::texture::{ImageTex, Texture};
+use crate::triangle_mesh::{MeshData, TriangleMesh};
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::WorldObjectsList;
+
+// Wavefront OBJ + MTL loader. Each group/object and material combination becomes
+// a separate `TriangleMesh`. Polygons are triangulated as triangle fans,
+// so they are expected to be convex. Normals missing from the file are generated
+// based on smoothing groups ('s off' means flat shading).
+//
+// http://paulbourke.net/dataformats/obj/
+// http://paulbourke.net/dataformats/mtl/
+
+#[derive(Debug)]
+/** Everything that can go wrong when loading OBJ/MTL */
+pub enum ObjError {
+  Io(PathBuf, std::io::Error),
+  Parse {
+    file: PathBuf,
+    line: usize,
+    message: String,
+  },
+  Image(PathBuf, image::ImageError),
+}
+
+impl fmt::Display for ObjError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ObjError::Io(path, e) => write!(f, "Could not read '{}': {}", path.display(), e),
+      ObjError::Parse {
+        file,
+        line,
+        message,
+      } => write!(f, "{}:{}: {}", file.display(), line, message),
+      ObjError::Image(path, e) => {
+        write!(f, "Could not load texture '{}': {}", path.display(), e)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ObjError {}
+
+/**
+Load all meshes from OBJ file. Materials are read from referenced MTL files.
+Faces without material use `default_material`.
+*/
+pub fn load_obj(
+  path: &Path,
+  default_material: Arc<dyn Material>,
+) -> Result<WorldObjectsList, ObjError> {
+  let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+  let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+  let obj = ObjData::parse(BufReader::new(file), path)?;
+
+  let mut materials: HashMap<String, MtlDef> = HashMap::new();
+  for mtl_file in &obj.material_libs {
+    for mtl in MtlDef::load(&base_dir.join(mtl_file))? {
+      materials.insert(mtl.name.clone(), mtl);
+    }
+  }
+
+  let mut textures = TextureCache::new();
+  let mut built_materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+  let mut result: WorldObjectsList = Vec::new();
+
+  for group in &obj.groups {
+    let material = match &group.material {
+      None => default_material.clone(),
+      Some(name) => match built_materials.get(name) {
+        Some(m) => m.clone(),
+        None => {
+          let m = match materials.get(name) {
+            Some(mtl) => mtl.to_material(&mut textures)?,
+            None => {
+              warn!("OBJ '{}' uses unknown material '{}'", path.display(), name);
+              default_material.clone()
+            }
+          };
+          built_materials.insert(name.clone(), m.clone());
+          m
+        }
+      },
+    };
+
+    let mesh_data = group.build_mesh_data(&obj);
+    if !mesh_data.indices.is_empty() {
+      result.push(Arc::new(TriangleMesh::new(mesh_data, material)));
+    }
+  }
+
+  let triangles: usize = obj.groups.iter().map(|g| g.triangles.len()).sum();
+  info!(
+    "Loaded '{}': {} meshes, {} triangles",
+    path.display(),
+    result.len(),
+    triangles
+  );
+  Ok(result)
+}
+
+///////////////////////
+// OBJ
+
+/** Vertex of a face, indices are already resolved to 0-based */
+#[derive(Clone, Copy, Debug)]
+struct FaceVertex {
+  v: usize,
+  vt: Option<usize>,
+  vn: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ObjTriangle {
+  vertices: [FaceVertex; 3],
+  /** 0 means no smoothing */
+  smoothing_group: u32,
+}
+
+/** Faces that share both group and material. Becomes a single `TriangleMesh` */
+#[derive(Debug)]
+struct ObjGroup {
+  name: String,
+  material: Option<String>,
+  triangles: Vec<ObjTriangle>,
+}
+
+/** Where the normal for the vertex comes from */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum NormalSource {
+  /** 'vn' index from file */
+  Explicit(usize),
+  /** Average of faces from the same smoothing group */
+  Smooth(u32),
+  /** Normal of the face with this index */
+  Flat(usize),
+}
+
+#[derive(Debug, Default)]
+struct ObjData {
+  positions: Vec<Point3d>,
+  uvs: Vec<(f32, f32)>,
+  normals: Vec<Vec3>,
+  groups: Vec<ObjGroup>,
+  material_libs: Vec<String>,
+}
+
+impl ObjData {
+  fn parse(reader: impl BufRead, path: &Path) -> Result<ObjData, ObjError> {
+    let mut obj = ObjData::default();
+    let mut group_name = String::from("default");
+    let mut material: Option<String> = None;
+    let mut smoothing_group: u32 = 0;
+
+    for (line_idx, line) in reader.lines().enumerate() {
+      let line = line.map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+      let parse_err = |message: String| ObjError::Parse {
+        file: path.to_path_buf(),
+        line: line_idx + 1,
+        message,
+      };
+
+      let line = strip_comment(&line);
+      let mut tokens = line.split_whitespace();
+      let keyword = match tokens.next() {
+        Some(k) => k,
+        None => continue,
+      };
+      let args: Vec<&str> = tokens.collect();
+
+      match keyword {
+        "v" => {
+          let p = parse_floats(&args, 3).map_err(parse_err)?;
+          obj.positions.push(Point3d::new(p[0], p[1], p[2]));
+        }
+        "vt" => {
+          let uv = parse_floats(&args, 1).map_err(parse_err)?;
+          // OBJ has v=0 at the bottom of the image, `ImageTex` at the top
+          let v = uv.get(1).copied().unwrap_or(0.0);
+          obj.uvs.push((uv[0], 1.0 - v));
+        }
+        "vn" => {
+          let n = parse_floats(&args, 3).map_err(parse_err)?;
+          obj.normals.push(Vec3::new(n[0], n[1], n[2]));
+        }
+        "f" => {
+          if args.len() < 3 {
+            return Err(parse_err(format!(
+              "Face needs at least 3 vertices, got {}",
+              args.len()
+            )));
+          }
+          let face = args
+            .iter()
+            .map(|a| obj.parse_face_vertex(a))
+            .collect::<Result<Vec<FaceVertex>, String>>()
+            .map_err(parse_err)?;
+
+          let group = obj.current_group(&group_name, &material);
+          // triangle fan
+          for i in 1..(face.len() - 1) {
+            group.triangles.push(ObjTriangle {
+              vertices: [face[0], face[i], face[i + 1]],
+              smoothing_group,
+            });
+          }
+        }
+        "g" | "o" => {
+          group_name = if args.is_empty() {
+            String::from("default")
+          } else {
+            args.join(" ")
+          };
+        }
+        "usemtl" => {
+          material = args.first().map(|s| s.to_string());
+        }
+        "mtllib" => {
+          obj.material_libs.extend(args.iter().map(|s| s.to_string()));
+        }
+        "s" => {
+          smoothing_group = match args.first() {
+            None | Some(&"off") => 0,
+            Some(v) => v
+              .parse::<u32>()
+              .map_err(|_| parse_err(format!("Invalid smoothing group '{}'", v)))?,
+          };
+        }
+        // lines, points, free-form geometry etc.
+        _ => {}
+      }
+    }
+
+    Ok(obj)
+  }
+
+  /** Get group for new faces. Creates new group if name or material changed */
+  fn current_group(&mut self, name: &str, material: &Option<String>) -> &mut ObjGroup {
+    let is_same = match self.groups.last() {
+      Some(g) => g.name == name && &g.material == material,
+      None => false,
+    };
+    if !is_same {
+      self.groups.push(ObjGroup {
+        name: name.to_string(),
+        material: material.clone(),
+        triangles: Vec::new(),
+      });
+    }
+    self.groups.last_mut().unwrap()
+  }
+
+  /** Parse 'v', 'v/vt', 'v//vn' or 'v/vt/vn' */
+  fn parse_face_vertex(&self, s: &str) -> Result<FaceVertex, String> {
+    let mut parts = s.split('/');
+    let v = parts.next().unwrap_or("");
+    let vt = parts.next().filter(|p| !p.is_empty());
+    let vn = parts.next().filter(|p| !p.is_empty());
+
+    Ok(FaceVertex {
+      v: resolve_index(v, self.positions.len())?,
+      vt: vt
+        .map(|idx| resolve_index(idx, self.uvs.len()))
+        .transpose()?,
+      vn: vn
+        .map(|idx| resolve_index(idx, self.normals.len()))
+        .transpose()?,
+    })
+  }
+}
+
+impl ObjGroup {
+  /** Deduplicate vertices and generate missing normals */
+  fn build_mesh_data(&self, obj: &ObjData) -> MeshData {
+    let has_uvs = self
+      .triangles
+      .iter()
+      .any(|t| t.vertices.iter().any(|v| v.vt.is_some()));
+    let mut mesh = MeshData::default();
+    let mut vertex_map: HashMap<(usize, Option<usize>, NormalSource), u32> = HashMap::new();
+
+    for (face_idx, tri) in self.triangles.iter().enumerate() {
+      let [p0, p1, p2] = [
+        obj.positions[tri.vertices[0].v],
+        obj.positions[tri.vertices[1].v],
+        obj.positions[tri.vertices[2].v],
+      ];
+      // not normalized, so that bigger faces have more influence on smooth normals
+      let face_normal = (p1 - p0).cross(p2 - p0);
+      if face_normal.near_zero() {
+        continue; // degenerate triangle, can never be hit
+      }
+
+      let mut indices = [0u32; 3];
+      for (i, fv) in tri.vertices.iter().enumerate() {
+        let normal_source = match (fv.vn, tri.smoothing_group) {
+          (Some(vn), _) => NormalSource::Explicit(vn),
+          (None, 0) => NormalSource::Flat(face_idx),
+          (None, group) => NormalSource::Smooth(group),
+        };
+
+        let idx = *vertex_map
+          .entry((fv.v, fv.vt, normal_source))
+          .or_insert_with(|| {
+            mesh.positions.push(obj.positions[fv.v]);
+            if has_uvs {
+              mesh
+                .uvs
+                .push(fv.vt.map(|vt| obj.uvs[vt]).unwrap_or((0.0, 0.0)));
+            }
+            mesh.normals.push(match normal_source {
+              NormalSource::Explicit(vn) => obj.normals[vn],
+              _ => Vec3::zero(),
+            });
+            (mesh.positions.len() - 1) as u32
+          });
+
+        if !matches!(normal_source, NormalSource::Explicit(_)) {
+          let n = &mut mesh.normals[idx as usize];
+          *n = *n + face_normal;
+        }
+        indices[i] = idx;
+      }
+      mesh.indices.push(indices);
+    }
+
+    for n in mesh.normals.iter_mut() {
+      *n = if n.near_zero() {
+        Vec3::up() // opposite faces in the same smoothing group, nothing sensible to do
+      } else {
+        n.unit_vector()
+      };
+    }
+
+    mesh
+  }
+}
+
+fn strip_comment(line: &str) -> &str {
+  match line.find('#') {
+    Some(idx) => &line[..idx],
+    None => line,
+  }
+}
+
+fn parse_floats(args: &[&str], min_count: usize) -> Result<Vec<f32>, String> {
+  if args.len() < min_count {
+    return Err(format!(
+      "Expected at least {} numbers, got {}",
+      min_count,
+      args.len()
+    ));
+  }
+  args
+    .iter()
+    .map(|a| {
+      a.parse::<f32>()
+        .map_err(|_| format!("Invalid number '{}'", a))
+    })
+    .collect()
+}
+
+/** OBJ indices are 1-based, negative values are relative to the end of the list */
+fn resolve_index(s: &str, count: usize) -> Result<usize, String> {
+  let idx = s
+    .parse::<i64>()
+    .map_err(|_| format!("Invalid index '{}'", s))?;
+  let resolved = match idx {
+    i if i > 0 => i - 1,
+    i if i < 0 => count as i64 + i,
+    _ => return Err(String::from("Index 0 is not valid in OBJ")),
+  };
+  if resolved < 0 || resolved >= count as i64 {
+    return Err(format!(
+      "Index {} out of range, there are only {} elements",
+      idx, count
+    ));
+  }
+  Ok(resolved as usize)
+}
+
+///////////////////////
+// MTL
+
+#[derive(Clone, Debug)]
+struct MtlDef {
+  name: String,
+  /** Diffuse color */
+  kd: Color,
+  /** Specular color */
+  ks: Color,
+  /** Specular exponent */
+  ns: f32,
+  /** IOR */
+  ni: f32,
+  /** Opacity */
+  d: f32,
+  /** Emissive */
+  ke: Color,
+  /** Transmission filter */
+  tf: Option<Color>,
+  illum: Option<u32>,
+  /** Diffuse texture, relative to MTL file */
+  map_kd: Option<PathBuf>,
+}
+
+impl MtlDef {
+  fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      kd: Color::uni(0.8),
+      ks: Color::zero(),
+      ns: 0.0,
+      ni: 1.0,
+      d: 1.0,
+      ke: Color::zero(),
+      tf: None,
+      illum: None,
+      map_kd: None,
+    }
+  }
+
+  fn load(path: &Path) -> Result<Vec<MtlDef>, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut result: Vec<MtlDef> = Vec::new();
+
+    for (line_idx, line) in BufReader::new(file).lines().enumerate() {
+      let line = line.map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+      let parse_err = |message: String| ObjError::Parse {
+        file: path.to_path_buf(),
+        line: line_idx + 1,
+        message,
+      };
+
+      let line = strip_comment(&line);
+      let mut tokens = line.split_whitespace();
+      let keyword = match tokens.next() {
+        Some(k) => k,
+        None => continue,
+      };
+      let args: Vec<&str> = tokens.collect();
+
+      if keyword == "newmtl" {
+        let name = args.join(" ");
+        result.push(MtlDef::new(&name));
+        continue;
+      }
+      let mtl = match result.last_mut() {
+        Some(mtl) => mtl,
+        None => return Err(parse_err(format!("'{}' before 'newmtl'", keyword))),
+      };
+
+      let color = |args: &[&str]| -> Result<Color, String> {
+        let c = parse_floats(args, 1)?;
+        Ok(match c[..] {
+          [v] => Color::uni(v),
+          _ if c.len() >= 3 => Color::new(c[0], c[1], c[2]),
+          _ => return Err(format!("Invalid color '{}'", args.join(" "))),
+        })
+      };
+      let float = |args: &[&str]| -> Result<f32, String> { Ok(parse_floats(args, 1)?[0]) };
+
+      match keyword {
+        "Kd" => mtl.kd = color(&args).map_err(parse_err)?,
+        "Ks" => mtl.ks = color(&args).map_err(parse_err)?,
+        "Ke" => mtl.ke = color(&args).map_err(parse_err)?,
+        "Tf" => mtl.tf = Some(color(&args).map_err(parse_err)?),
+        "Ns" => mtl.ns = float(&args).map_err(parse_err)?,
+        "Ni" => mtl.ni = float(&args).map_err(parse_err)?,
+        "d" => mtl.d = float(&args).map_err(parse_err)?,
+        "Tr" => mtl.d = 1.0 - float(&args).map_err(parse_err)?,
+        "illum" => {
+          let v = args.first().and_then(|v| v.parse::<u32>().ok());
+          mtl.illum = Some(v.ok_or_else(|| parse_err(String::from("Invalid illum")))?);
+        }
+        "map_Kd" => {
+          // options like '-s 1 1 1' are not supported, file name is the last token
+          let file_name = args
+            .last()
+            .ok_or_else(|| parse_err(String::from("Missing texture path")))?;
+          let file_name = file_name.replace('\\', "/");
+          mtl.map_kd = Some(base_dir.join(file_name));
+        }
+        _ => {}
+      }
+    }
+
+    Ok(result)
+  }
+
+  /**
+  Map MTL onto the closest material we have. Priority is:
+  emissive, transparent, specular and diffuse as fallback.
+  */
+  fn to_material(&self, textures: &mut TextureCache) -> Result<Arc<dyn Material>, ObjError> {
+    let max_component = |c: Color| c.x().max(c.y()).max(c.z());
+    let is_transparent =
+      self.d < 1.0 || matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9));
+    let is_metal = self.illum == Some(3)
+      || (max_component(self.ks) > 0.0 && max_component(self.ks) > max_component(self.kd));
+
+    let result: Arc<dyn Material> = if max_component(self.ke) > 0.0 {
+      Arc::new(DiffuseLight::color(self.ke, 1.0))
+    } else if is_transparent {
+      Arc::new(Dielectric {
+        albedo: self.tf.unwrap_or_else(Color::one),
+        ior: if self.ni > 1.0 { self.ni } else { 1.5 },
+        absorption: Color::zero(),
+        film: None,
+      })
+    } else if is_metal {
+      Arc::new(Metal {
+        albedo: self.ks,
+        // Blinn-Phong exponent to roughness, as in e.g. Walter et al.
+        roughness: (2.0 / (self.ns + 2.0)).sqrt(),
+        film: None,
+      })
+    } else {
+      match &self.map_kd {
+        Some(path) => Arc::new(Lambert::texture(textures.get(path)?)),
+        None => Arc::new(Lambert::from_color(self.kd)),
+      }
+    };
+    Ok(result)
+  }
+}
+
+/** Materials often share textures, so load each only once */
+struct TextureCache {
+  textures: HashMap<PathBuf, Arc<dyn Texture>>,
+}
+
+impl TextureCache {
+  fn new() -> Self {
+    Self {
+      textures: HashMap::new(),
+    }
+  }
+
+  fn get(&mut self, path: &Path) -> Result<Arc<dyn Texture>, ObjError> {
+    if let Some(t) = self.textures.get(path) {
+      return Ok(t.clone());
+    }
+    let tex: Arc<dyn Texture> =
+      Arc::new(ImageTex::load(path).map_err(|e| ObjError::Image(path.to_path_buf(), e))?);
+    self.textures.insert(path.to_path_buf(), tex.clone());
+    Ok(tex)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::SolidColor;
+  use crate::ray::Ray;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn parse(text: &str) -> Result<ObjData, ObjError> {
+    ObjData::parse(text.as_bytes(), Path::new("test.obj"))
+  }
+
+  const QUAD: &str = "
+    # quad made from a single polygon
+    v 0 0 0
+    v 1 0 0
+    v 1 1 0
+    v 0 1 0
+    vt 0 0
+    vt 1 0
+    vt 1 1
+    vt 0 1
+    s 1
+    f 1/1 2/2 3/3 4/4
+  ";
+
+  #[test]
+  fn triangulates_polygons() {
+    let obj = parse(QUAD).unwrap();
+    assert_eq!(obj.groups.len(), 1);
+    assert_eq!(obj.groups[0].triangles.len(), 2);
+
+    let mesh = obj.groups[0].build_mesh_data(&obj);
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.indices.len(), 2);
+    assert_eq!(mesh.uvs[2], (1.0, 0.0)); // v is flipped
+    assert_approx_eq!(mesh.normals[0].z(), 1.0);
+  }
+
+  #[test]
+  fn negative_indices() {
+    let obj = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1").unwrap();
+    let v: Vec<usize> = obj.groups[0].triangles[0]
+      .vertices
+      .iter()
+      .map(|fv| fv.v)
+      .collect();
+    assert_eq!(v, vec![0, 1, 2]);
+  }
+
+  #[test]
+  fn groups_and_materials_split_meshes() {
+    let obj = parse(
+      "v 0 0 0\nv 1 0 0\nv 0 1 0
+      g a\nusemtl red\nf 1 2 3\nf 1 2 3
+      usemtl green\nf 1 2 3
+      g b\nf 1 2 3",
+    )
+    .unwrap();
+    let groups: Vec<(&str, Option<&str>, usize)> = obj
+      .groups
+      .iter()
+      .map(|g| (g.name.as_str(), g.material.as_deref(), g.triangles.len()))
+      .collect();
+    assert_eq!(
+      groups,
+      vec![
+        ("a", Some("red"), 2),
+        ("a", Some("green"), 1),
+        ("b", Some("green"), 1)
+      ]
+    );
+  }
+
+  #[test]
+  fn smoothing_groups() {
+    // 2 faces at 90dgr sharing an edge
+    let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1
+      s 1\nf 1 2 3\nf 1 4 2";
+    let obj = parse(text).unwrap();
+    let mesh = obj.groups[0].build_mesh_data(&obj);
+    assert_eq!(mesh.positions.len(), 4); // shared vertices
+    let n = mesh.normals[0];
+    assert_approx_eq!(n.y(), n.z());
+    assert_approx_eq!(n.length(), 1.0);
+
+    let text = text.replace("s 1", "s off");
+    let obj = parse(&text).unwrap();
+    let mesh = obj.groups[0].build_mesh_data(&obj);
+    assert_eq!(mesh.positions.len(), 6); // flat shading, no sharing
+  }
+
+  #[test]
+  fn errors() {
+    let err = parse("v 0 0 0\nv 1 0 0\nf 1 2 3").unwrap_err();
+    match err {
+      ObjError::Parse { line, .. } => assert_eq!(line, 3),
+      _ => panic!("Expected parse error"),
+    }
+    assert!(parse("v 0 zero 0").is_err());
+    assert!(parse("v 0 0 0\nf 1 1").is_err());
+    assert!(load_obj(
+      Path::new("does/not/exist.obj"),
+      Arc::new(Lambert::color(1.0, 1.0, 1.0))
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn load_with_materials() {
+    let default_mat = Arc::new(SolidColor {
+      color: Color::one(),
+    });
+    let meshes = load_obj(Path::new("assets/models/cornell_box.obj"), default_mat).unwrap();
+    assert!(!meshes.is_empty());
+
+    // ray from the camera hits the back wall
+    let r = Ray::new(Point3d::new(0.0, 1.0, 3.5), Vec3::new(0.0, 0.0, -1.0));
+    let hit = meshes
+      .iter()
+      .filter_map(|m| m.check_intersection(&r, 0.001, f32::INFINITY))
+      .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap())
+      .unwrap();
+    assert_approx_eq!(hit.p.z(), -1.0);
+  }
+}
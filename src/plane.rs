@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use crate::aabb::AABB;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::traceable::{RayHit, Traceable};
+use crate::vec3::{Point3d, Vec3};
+
+#[derive(Clone, Debug)]
+/**
+Infinite plane going through `point`. Has no bounding box, so BVH tests it
+separately for every ray. Texture coordinates are distances (in scene units)
+along 2 directions perpendicular to the normal, so they are not limited to [0, 1].
+*/
+pub struct Plane {
+  pub point: Point3d,
+  /** Unit vector */
+  pub normal: Vec3,
+  pub material: Arc<dyn Material>,
+  tangent: Vec3,
+  bitangent: Vec3,
+}
+
+impl Plane {
+  pub fn new(point: Point3d, normal: Vec3, material: Arc<dyn Material>) -> Self {
+    let normal = normal.unit_vector();
+    // any vector that is not parallel to normal
+    let helper = if normal.x().abs() > 0.9 {
+      Vec3::up()
+    } else {
+      Vec3::right()
+    };
+    let bitangent = normal.cross(helper).unit_vector();
+    let tangent = bitangent.cross(normal);
+    Self {
+      point,
+      normal,
+      material,
+      tangent,
+      bitangent,
+    }
+  }
+}
+
+impl Traceable for Plane {
+  fn bounding_box(&self) -> Option<AABB> {
+    None
+  }
+
+  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    let denom = self.normal.dot(r.dir);
+    if denom.abs() < 1e-8 {
+      return None; // parallel
+    }
+    let t = (self.point - r.origin).dot(self.normal) / denom;
+    if t < t_min || t > t_max {
+      return None;
+    }
+
+    let p = r.at(t);
+    let d = p - self.point;
+    let (front_face, normal) = RayHit::check_is_front_face(r, self.normal);
+    Some(RayHit {
+      p,
+      t,
+      u: d.dot(self.tangent),
+      v: d.dot(self.bitangent),
+      normal,
+      geometric_normal: normal,
+      tangent: self.tangent,
+      bitangent: self.bitangent,
+      front_face,
+      vertex_color: None,
+      material: self.material.clone(),
+    })
+  }
+}
@@ -0,0 +1,568 @@
+use log::{info, warn};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::material::{Lambert, Material};
+use crate::texture::VertexColorTex;
+use crate::triangle_mesh::{MeshData, TriangleMesh};
+use crate::vec3::{Color, Point3d, Vec3};
+
+// Stanford PLY loader, both ASCII and binary (little and big endian).
+// Reads vertex positions, normals, colors, texture coordinates and faces.
+// Other elements and properties are skipped. Polygons are triangulated
+// as triangle fans.
+//
+// http://paulbourke.net/dataformats/ply/
+
+#[derive(Debug)]
+/** Everything that can go wrong when loading PLY */
+pub enum PlyError {
+  Io(PathBuf, std::io::Error),
+  Parse { file: PathBuf, message: String },
+}
+
+impl fmt::Display for PlyError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PlyError::Io(path, e) => write!(f, "Could not read '{}': {}", path.display(), e),
+      PlyError::Parse { file, message } => write!(f, "{}: {}", file.display(), message),
+    }
+  }
+}
+
+impl std::error::Error for PlyError {}
+
+/**
+Load PLY file as a single triangle mesh. If `material` is not provided,
+vertex colors are used as albedo (or gray if the file has no colors).
+*/
+pub fn load_ply(
+  path: &Path,
+  material: Option<Arc<dyn Material>>,
+) -> Result<TriangleMesh, PlyError> {
+  let file = File::open(path).map_err(|e| PlyError::Io(path.to_path_buf(), e))?;
+  let mesh = parse_ply(BufReader::new(file)).map_err(|message| PlyError::Parse {
+    file: path.to_path_buf(),
+    message,
+  })?;
+  info!(
+    "Loaded '{}': {} vertices, {} triangles",
+    path.display(),
+    mesh.positions.len(),
+    mesh.indices.len()
+  );
+
+  let material = material.unwrap_or_else(|| {
+    Arc::new(Lambert::texture(Arc::new(VertexColorTex {
+      fallback: Color::uni(0.8),
+    })))
+  });
+  Ok(TriangleMesh::new(mesh, material))
+}
+
+/** Parse whole PLY file. Error message does not contain the file name */
+fn parse_ply<R: BufRead>(mut reader: R) -> Result<MeshData, String> {
+  let header = PlyHeader::parse(&mut reader)?;
+  let mut values: Box<dyn ValueReader> = match header.format {
+    PlyFormat::Ascii => {
+      let mut text = String::new();
+      reader
+        .read_to_string(&mut text)
+        .map_err(|e| e.to_string())?;
+      Box::new(AsciiReader {
+        tokens: text
+          .split_whitespace()
+          .map(String::from)
+          .collect::<Vec<_>>()
+          .into_iter(),
+      })
+    }
+    PlyFormat::BinaryLittleEndian => Box::new(BinaryReader {
+      reader,
+      big_endian: false,
+    }),
+    PlyFormat::BinaryBigEndian => Box::new(BinaryReader {
+      reader,
+      big_endian: true,
+    }),
+  };
+
+  let mut mesh = MeshData::default();
+  let mut has_faces = false;
+  for element in &header.elements {
+    match element.name.as_str() {
+      "vertex" => read_vertices(element, values.as_mut(), &mut mesh)?,
+      "face" => {
+        has_faces = true;
+        read_faces(element, values.as_mut(), &mut mesh)?
+      }
+      _ => {
+        for _ in 0..element.count {
+          for prop in &element.properties {
+            prop.read(values.as_mut())?;
+          }
+        }
+      }
+    }
+  }
+
+  if !has_faces {
+    return Err(String::from(
+      "File has no faces, point clouds are not supported",
+    ));
+  }
+  mesh.validate()?;
+  Ok(mesh)
+}
+
+///////////////////////
+// Header
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+  Ascii,
+  BinaryLittleEndian,
+  BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+  I8,
+  U8,
+  I16,
+  U16,
+  I32,
+  U32,
+  F32,
+  F64,
+}
+
+impl ScalarType {
+  fn parse(name: &str) -> Result<ScalarType, String> {
+    match name {
+      "char" | "int8" => Ok(ScalarType::I8),
+      "uchar" | "uint8" => Ok(ScalarType::U8),
+      "short" | "int16" => Ok(ScalarType::I16),
+      "ushort" | "uint16" => Ok(ScalarType::U16),
+      "int" | "int32" => Ok(ScalarType::I32),
+      "uint" | "uint32" => Ok(ScalarType::U32),
+      "float" | "float32" => Ok(ScalarType::F32),
+      "double" | "float64" => Ok(ScalarType::F64),
+      _ => Err(format!("Unknown property type '{}'", name)),
+    }
+  }
+
+  fn size(self) -> usize {
+    match self {
+      ScalarType::I8 | ScalarType::U8 => 1,
+      ScalarType::I16 | ScalarType::U16 => 2,
+      ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+      ScalarType::F64 => 8,
+    }
+  }
+
+  /** Value that integer colors are divided by, so that they end up in [0, 1] */
+  fn color_scale(self) -> f64 {
+    match self {
+      ScalarType::U8 => 255.0,
+      ScalarType::U16 => 65535.0,
+      _ => 1.0,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+enum PropertyKind {
+  Scalar(ScalarType),
+  List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Clone, Debug)]
+struct PropertyDef {
+  name: String,
+  kind: PropertyKind,
+}
+
+impl PropertyDef {
+  /** Read the value. Lists are returned whole, scalars as single item list */
+  fn read(&self, values: &mut dyn ValueReader) -> Result<Vec<f64>, String> {
+    match self.kind {
+      PropertyKind::Scalar(ty) => Ok(vec![values.read(ty)?]),
+      PropertyKind::List { count, item } => {
+        let n = values.read(count)? as usize;
+        (0..n).map(|_| values.read(item)).collect()
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+struct ElementDef {
+  name: String,
+  count: usize,
+  properties: Vec<PropertyDef>,
+}
+
+impl ElementDef {
+  fn property_idx(&self, names: &[&str]) -> Option<usize> {
+    self
+      .properties
+      .iter()
+      .position(|p| names.contains(&p.name.as_str()))
+  }
+}
+
+#[derive(Debug)]
+struct PlyHeader {
+  format: PlyFormat,
+  elements: Vec<ElementDef>,
+}
+
+impl PlyHeader {
+  fn parse<R: BufRead>(reader: &mut R) -> Result<PlyHeader, String> {
+    let mut read_line = || -> Result<String, String> {
+      let mut line = String::new();
+      match reader.read_line(&mut line) {
+        Ok(0) => Err(String::from("Unexpected end of file in header")),
+        Ok(_) => Ok(line.trim().to_string()),
+        Err(e) => Err(e.to_string()),
+      }
+    };
+
+    if read_line()? != "ply" {
+      return Err(String::from("Not a PLY file"));
+    }
+
+    let mut format: Option<PlyFormat> = None;
+    let mut elements: Vec<ElementDef> = Vec::new();
+    loop {
+      let line = read_line()?;
+      let tokens: Vec<&str> = line.split_whitespace().collect();
+      match tokens[..] {
+        [] | ["comment", ..] | ["obj_info", ..] => {}
+        ["end_header"] => break,
+        ["format", fmt, _version] => {
+          format = Some(match fmt {
+            "ascii" => PlyFormat::Ascii,
+            "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+            "binary_big_endian" => PlyFormat::BinaryBigEndian,
+            _ => return Err(format!("Unknown format '{}'", fmt)),
+          })
+        }
+        ["element", name, count] => elements.push(ElementDef {
+          name: name.to_string(),
+          count: count
+            .parse()
+            .map_err(|_| format!("Invalid element count '{}'", count))?,
+          properties: Vec::new(),
+        }),
+        ["property", "list", count, item, name] => {
+          let kind = PropertyKind::List {
+            count: ScalarType::parse(count)?,
+            item: ScalarType::parse(item)?,
+          };
+          add_property(&mut elements, name, kind)?
+        }
+        ["property", ty, name] => {
+          let kind = PropertyKind::Scalar(ScalarType::parse(ty)?);
+          add_property(&mut elements, name, kind)?
+        }
+        _ => return Err(format!("Invalid header line '{}'", line)),
+      }
+    }
+
+    match format {
+      Some(format) => Ok(PlyHeader { format, elements }),
+      None => Err(String::from("Header does not specify format")),
+    }
+  }
+}
+
+fn add_property(elements: &mut [ElementDef], name: &str, kind: PropertyKind) -> Result<(), String> {
+  match elements.last_mut() {
+    Some(el) => {
+      el.properties.push(PropertyDef {
+        name: name.to_string(),
+        kind,
+      });
+      Ok(())
+    }
+    None => Err(format!("Property '{}' declared before any element", name)),
+  }
+}
+
+///////////////////////
+// Body
+
+/** Reads values one by one, regardless if file is text or binary */
+trait ValueReader {
+  fn read(&mut self, ty: ScalarType) -> Result<f64, String>;
+}
+
+struct AsciiReader {
+  tokens: std::vec::IntoIter<String>,
+}
+
+impl ValueReader for AsciiReader {
+  fn read(&mut self, _ty: ScalarType) -> Result<f64, String> {
+    let token = self
+      .tokens
+      .next()
+      .ok_or_else(|| String::from("Unexpected end of file"))?;
+    token
+      .parse::<f64>()
+      .map_err(|_| format!("Invalid number '{}'", token))
+  }
+}
+
+struct BinaryReader<R: Read> {
+  reader: R,
+  big_endian: bool,
+}
+
+impl<R: Read> ValueReader for BinaryReader<R> {
+  fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+    let mut buf = [0u8; 8];
+    let bytes = &mut buf[..ty.size()];
+    self
+      .reader
+      .read_exact(bytes)
+      .map_err(|_| String::from("Unexpected end of file"))?;
+    if !self.big_endian {
+      bytes.reverse(); // everything below reads big endian
+    }
+
+    let value = match ty {
+      ScalarType::I8 => i8::from_be_bytes([bytes[0]]) as f64,
+      ScalarType::U8 => bytes[0] as f64,
+      ScalarType::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+      ScalarType::U16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+      ScalarType::I32 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+      ScalarType::U32 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+      ScalarType::F32 => f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+      ScalarType::F64 => f64::from_be_bytes(buf),
+    };
+    Ok(value)
+  }
+}
+
+fn read_vertices(
+  element: &ElementDef,
+  values: &mut dyn ValueReader,
+  mesh: &mut MeshData,
+) -> Result<(), String> {
+  let idx = |names: &[&str]| element.property_idx(names);
+  let position = match (idx(&["x"]), idx(&["y"]), idx(&["z"])) {
+    (Some(x), Some(y), Some(z)) => [x, y, z],
+    _ => return Err(String::from("Vertex element is missing x, y or z")),
+  };
+  let normal = match (idx(&["nx"]), idx(&["ny"]), idx(&["nz"])) {
+    (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+    _ => None,
+  };
+  let color = match (
+    idx(&["red", "r", "diffuse_red"]),
+    idx(&["green", "g", "diffuse_green"]),
+    idx(&["blue", "b", "diffuse_blue"]),
+  ) {
+    (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+    _ => None,
+  };
+  let uv = match (
+    idx(&["u", "s", "texture_u", "texture_s"]),
+    idx(&["v", "t", "texture_v", "texture_t"]),
+  ) {
+    (Some(u), Some(v)) => Some([u, v]),
+    _ => None,
+  };
+
+  let color_scale = |i: usize| match element.properties[i].kind {
+    PropertyKind::Scalar(ty) => ty.color_scale(),
+    PropertyKind::List { .. } => 1.0,
+  };
+  let color_scales = color.map(|c| c.map(color_scale));
+
+  for _ in 0..element.count {
+    let props = element
+      .properties
+      .iter()
+      .map(|p| p.read(values).map(|v| v.first().copied().unwrap_or(0.0)))
+      .collect::<Result<Vec<f64>, String>>()?;
+    let get = |i: usize| props[i] as f32;
+
+    let [x, y, z] = position;
+    mesh.positions.push(Point3d::new(get(x), get(y), get(z)));
+    if let Some([x, y, z]) = normal {
+      mesh.normals.push(Vec3::new(get(x), get(y), get(z)));
+    }
+    if let (Some(c), Some(scale)) = (color, color_scales) {
+      let channel = |i: usize| (props[c[i]] / scale[i]) as f32;
+      mesh
+        .colors
+        .push(Color::new(channel(0), channel(1), channel(2)));
+    }
+    if let Some([u, v]) = uv {
+      mesh.uvs.push((get(u), get(v)));
+    }
+  }
+  Ok(())
+}
+
+fn read_faces(
+  element: &ElementDef,
+  values: &mut dyn ValueReader,
+  mesh: &mut MeshData,
+) -> Result<(), String> {
+  let indices_idx = element
+    .property_idx(&["vertex_indices", "vertex_index"])
+    .ok_or_else(|| String::from("Face element is missing vertex_indices"))?;
+
+  let mut skipped = 0;
+  for _ in 0..element.count {
+    for (i, prop) in element.properties.iter().enumerate() {
+      let v = prop.read(values)?;
+      if i != indices_idx {
+        continue;
+      }
+      if v.len() < 3 {
+        skipped += 1;
+        continue;
+      }
+      for k in 2..v.len() {
+        mesh
+          .indices
+          .push([v[0] as u32, v[k - 1] as u32, v[k] as u32]);
+      }
+    }
+  }
+
+  if skipped > 0 {
+    warn!("Skipped {} faces with less than 3 vertices", skipped);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_approx_eq::assert_approx_eq;
+
+  const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment unit quad, red and blue
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 255 0 0
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 0 0 255
+4 0 1 2 3
+";
+
+  fn binary_quad(big_endian: bool) -> Vec<u8> {
+    let format = if big_endian {
+      "binary_big_endian"
+    } else {
+      "binary_little_endian"
+    };
+    let header = format!(
+      "ply\nformat {} 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nelement face 2\nproperty uchar flags\nproperty list uchar uint vertex_indices\nend_header\n",
+      format
+    );
+    let mut data = header.into_bytes();
+    let to_bytes = |v: &[u8; 4]| {
+      let mut v = *v;
+      if !big_endian {
+        v.reverse();
+      }
+      v
+    };
+    for p in &[
+      [0.0f32, 0.0, 0.0],
+      [1.0, 0.0, 0.0],
+      [1.0, 1.0, 0.0],
+      [0.0, 1.0, 0.0],
+    ] {
+      for c in p {
+        data.extend_from_slice(&to_bytes(&c.to_be_bytes()));
+      }
+    }
+    for face in &[[0u32, 1, 2], [0, 2, 3]] {
+      data.push(7); // ignored property
+      data.push(3);
+      for i in face {
+        data.extend_from_slice(&to_bytes(&i.to_be_bytes()));
+      }
+    }
+    data
+  }
+
+  #[test]
+  fn ascii() {
+    let mesh = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.normals.len(), 4);
+    assert!(mesh.uvs.is_empty());
+    assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+    assert_approx_eq!(mesh.colors[0].x(), 1.0);
+    assert_approx_eq!(mesh.colors[2].z(), 1.0);
+  }
+
+  #[test]
+  fn binary() {
+    for &big_endian in &[false, true] {
+      let mesh = parse_ply(&binary_quad(big_endian)[..]).unwrap();
+      assert_eq!(mesh.positions.len(), 4);
+      assert_approx_eq!(mesh.positions[2].x(), 1.0);
+      assert_approx_eq!(mesh.positions[2].y(), 1.0);
+      assert!(mesh.normals.is_empty());
+      assert!(mesh.colors.is_empty());
+      assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+  }
+
+  #[test]
+  fn truncated_binary() {
+    let data = binary_quad(false);
+    assert!(parse_ply(&data[..data.len() - 1]).is_err());
+  }
+
+  #[test]
+  fn point_cloud() {
+    let ply = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n";
+    assert!(parse_ply(ply.as_bytes()).is_err());
+  }
+
+  #[test]
+  fn vertex_colors_as_albedo() {
+    use crate::ray::Ray;
+    use crate::traceable::Traceable;
+
+    let mesh = load_ply(Path::new("assets/models/color_cube.ply"), None).unwrap();
+    assert_eq!(mesh.triangle_count(), 12);
+    // hit the +x face near its (1,1,1) corner
+    let r = Ray::new(Point3d::new(2.0, 0.49, 0.49), Vec3::new(-1.0, 0.0, 0.0));
+    let hit = mesh.check_intersection(&r, 0.001, f32::INFINITY).unwrap();
+    let albedo = hit.material.sample(&hit, !r.dir).unwrap().weight;
+    assert!(albedo.x() > 0.95 && albedo.y() > 0.95 && albedo.z() > 0.95);
+  }
+
+  #[test]
+  fn not_ply() {
+    assert!(parse_ply("solid cube\n".as_bytes()).is_err());
+  }
+}
@@ -0,0 +1,467 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::material::{BSDFSample, Material};
+use crate::microfacet::{
+  dielectric_eval, dielectric_pdf, dielectric_sample, fresnel_schlick, reflect_local, GGX,
+};
+use crate::ray::Ray;
+use crate::texture::{SolidColorTex, Texture};
+use crate::traceable::RayHit;
+use crate::utils::luminance;
+use crate::vec3::{Color, Vec3};
+
+// "Physically Based Shading at Disney" (Burley 2012) with transmission from the 2015
+// follow-up. One material made of several lobes: diffuse with retro-reflection and
+// sheen, GGX specular, rough glass and a clearcoat layer on top. Lobes are blended
+// by the parameters, a single one is sampled and weighted against all the others.
+//
+// https://media.disneyanimation.com/uploads/production/publication_asset/48/asset/s2012_pbs_disney_brdf_notes_v3.pdf
+// https://blog.selfshadow.com/publications/s2015-shading-course/burley/s2015_pbs_disney_bsdf_notes.pdf
+
+/** Perfectly smooth lobes would be delta distributions, which can't be blended */
+const MIN_ROUGHNESS: f32 = 0.02;
+/** Clearcoat is polyurethane, IOR 1.5 */
+const CLEARCOAT_F0: f32 = 0.04;
+
+#[derive(Clone, Debug)]
+/**
+Principled material, same parameters as e.g. Blender or Substance use. Every
+parameter is a texture. Scalar parameters use the red channel of their texture, so
+grayscale maps work as they are. Base color is also multiplied by mesh's vertex colors.
+*/
+pub struct PrincipledMaterial {
+  pub base_color: Arc<dyn Texture>,
+  /** 0 is dielectric, 1 is metal tinted by base color */
+  pub metallic: Arc<dyn Texture>,
+  pub roughness: Arc<dyn Texture>,
+  /** Reflectance of dielectrics, 0.5 is 4% at normal incidence (IOR 1.5) */
+  pub specular: Arc<dyn Texture>,
+  /** Tints dielectric specular towards base color */
+  pub specular_tint: Arc<dyn Texture>,
+  /** Extra reflection at grazing angles, for cloth */
+  pub sheen: Arc<dyn Texture>,
+  pub sheen_tint: Arc<dyn Texture>,
+  /** Second, white specular layer e.g. car paint */
+  pub clearcoat: Arc<dyn Texture>,
+  /** 0 is satin, 1 is glossy */
+  pub clearcoat_gloss: Arc<dyn Texture>,
+  /** 0 is opaque, 1 is glass tinted by base color */
+  pub transmission: Arc<dyn Texture>,
+  /** Used by transmission */
+  pub ior: Arc<dyn Texture>,
+  /** Light emitted from both sides of the surface. `None` for most materials */
+  pub emission: Option<Arc<dyn Texture>>,
+}
+
+fn constant(v: f32) -> Arc<dyn Texture> {
+  Arc::new(SolidColorTex::new(v, v, v))
+}
+
+impl PrincipledMaterial {
+  /** Rough plastic with given color. Same defaults as Blender's Principled BSDF */
+  pub fn new(base_color: Color) -> Self {
+    Self {
+      base_color: Arc::new(SolidColorTex::from_color(base_color)),
+      metallic: constant(0.0),
+      roughness: constant(0.5),
+      specular: constant(0.5),
+      specular_tint: constant(0.0),
+      sheen: constant(0.0),
+      sheen_tint: constant(0.5),
+      clearcoat: constant(0.0),
+      clearcoat_gloss: constant(1.0),
+      transmission: constant(0.0),
+      ior: constant(1.5),
+      emission: None,
+    }
+  }
+
+  fn lobes(&self, hit: &RayHit) -> Lobes {
+    let scalar = |tex: &Arc<dyn Texture>| tex.sample(hit).x();
+    let clamped = |tex: &Arc<dyn Texture>| scalar(tex).clamp(0.0, 1.0);
+
+    let base_color = self.base_color.sample(hit) * hit.vertex_color.unwrap_or_else(Color::one);
+    let metallic = clamped(&self.metallic);
+    let roughness = clamped(&self.roughness).max(MIN_ROUGHNESS);
+    let transmission = clamped(&self.transmission);
+    let ior = scalar(&self.ior).max(1.0);
+
+    // hue and saturation of the base color, without its brightness
+    let lum = luminance(base_color);
+    let tint = if lum > 0.0 {
+      base_color / lum
+    } else {
+      Color::one()
+    };
+    let specular_color = lerp(Color::one(), tint, clamped(&self.specular_tint));
+    let spec0 = lerp(
+      specular_color * (0.08 * clamped(&self.specular)),
+      base_color,
+      metallic,
+    );
+
+    let transmission_weight = (1.0 - metallic) * transmission;
+    Lobes {
+      base_color,
+      roughness,
+      spec0,
+      sheen: lerp(Color::one(), tint, clamped(&self.sheen_tint)) * clamped(&self.sheen),
+      clearcoat: 0.25 * clamped(&self.clearcoat),
+      clearcoat_alpha: lerp_f32(0.1, 0.001, clamped(&self.clearcoat_gloss)),
+      diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+      specular_weight: 1.0 - transmission_weight,
+      transmission_weight,
+      eta: if hit.front_face { ior } else { 1.0 / ior },
+      // back face of transmissive object is seen only from inside, it's just glass there
+      inside: !hit.front_face && transmission_weight > 0.0,
+      front_face: hit.front_face,
+      ggx: GGX::from_roughness(roughness, roughness),
+    }
+  }
+}
+
+fn lerp(a: Color, b: Color, t: f32) -> Color {
+  a * (1.0 - t) + b * t
+}
+
+fn lerp_f32(a: f32, b: f32, t: f32) -> f32 {
+  a * (1.0 - t) + b * t
+}
+
+/** `(1 - cos)^5`, the shape of Schlick's Fresnel */
+fn schlick_weight(cos: f32) -> f32 {
+  (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+///////////////////////
+// Lobes
+
+/** Parameters at the hit point, everything needed to evaluate the lobes */
+struct Lobes {
+  base_color: Color,
+  roughness: f32,
+  /** Specular color at normal incidence, mix of dielectric and metal */
+  spec0: Color,
+  sheen: Color,
+  clearcoat: f32,
+  clearcoat_alpha: f32,
+  diffuse_weight: f32,
+  specular_weight: f32,
+  transmission_weight: f32,
+  eta: f32,
+  inside: bool,
+  front_face: bool,
+  ggx: GGX,
+}
+
+enum Lobe {
+  Diffuse,
+  Specular,
+  Transmission,
+  Clearcoat,
+}
+
+impl Lobes {
+  /** Probability of sampling each lobe, roughly how much light it reflects */
+  fn probabilities(&self, wo: Vec3) -> [(Lobe, f32); 4] {
+    let mut p = if self.inside {
+      [0.0, 0.0, 1.0, 0.0]
+    } else {
+      [
+        self.diffuse_weight * luminance(self.base_color),
+        self.specular_weight * luminance(fresnel_schlick(self.spec0, wo.z())),
+        self.transmission_weight,
+        self.clearcoat * fresnel_schlick(Color::uni(CLEARCOAT_F0), wo.z()).x(),
+      ]
+    };
+    let total: f32 = p.iter().sum();
+    if total > 0.0 {
+      p.iter_mut().for_each(|x| *x /= total);
+    }
+    [
+      (Lobe::Diffuse, p[0]),
+      (Lobe::Specular, p[1]),
+      (Lobe::Transmission, p[2]),
+      (Lobe::Clearcoat, p[3]),
+    ]
+  }
+
+  /** Transmitted light is tinted once, when it enters the object */
+  fn transmission_tint(&self, wi: Vec3) -> Color {
+    if self.front_face && wi.z() < 0.0 {
+      self.base_color
+    } else {
+      Color::one()
+    }
+  }
+
+  /** BSDF * cos, all lobes together. Local space */
+  fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+      return Color::zero();
+    }
+    let transmission = self.transmission_tint(wi) * dielectric_eval(&self.ggx, self.eta, wo, wi);
+    if self.inside {
+      return transmission;
+    }
+    let mut f = transmission * self.transmission_weight;
+    if wi.z() < 0.0 {
+      return f;
+    }
+
+    let wh = (wo + wi).unit_vector();
+    let cos_d = wi.dot(wh);
+
+    // diffuse, with retro-reflection of rough surfaces at grazing angles
+    let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+    let fl = 1.0 + (fd90 - 1.0) * schlick_weight(wi.z());
+    let fv = 1.0 + (fd90 - 1.0) * schlick_weight(wo.z());
+    let diffuse = self.base_color * (fl * fv / PI) + self.sheen * schlick_weight(cos_d);
+    f = f + diffuse * (self.diffuse_weight * wi.z());
+
+    let specular = fresnel_schlick(self.spec0, wo.dot(wh))
+      * (self.ggx.d(wh) * self.ggx.g(wo, wi) / (4.0 * wo.z()));
+    f = f + specular * self.specular_weight;
+
+    if self.clearcoat > 0.0 {
+      // masking uses fixed roughness, as in the paper
+      let g = clearcoat_masking();
+      let fresnel = lerp_f32(CLEARCOAT_F0, 1.0, schlick_weight(cos_d));
+      let d = gtr1(wh.z(), self.clearcoat_alpha);
+      f = f + Color::uni(self.clearcoat * fresnel * d * g.g1(wo) * g.g1(wi) / (4.0 * wo.z()));
+    }
+    f
+  }
+
+  fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+      return 0.0;
+    }
+    let wh = (wo + wi).unit_vector();
+    self
+      .probabilities(wo)
+      .iter()
+      .filter(|(_, p)| *p > 0.0)
+      .map(|(lobe, p)| {
+        let pdf = match lobe {
+          Lobe::Transmission => dielectric_pdf(&self.ggx, self.eta, wo, wi),
+          _ if wi.z() < 0.0 => 0.0,
+          Lobe::Diffuse => wi.z() / PI,
+          Lobe::Specular => self.ggx.visible_normal_pdf(wo, wh) / (4.0 * wo.dot(wh)),
+          Lobe::Clearcoat => gtr1(wh.z(), self.clearcoat_alpha) * wh.z() / (4.0 * wo.dot(wh)),
+        };
+        p * pdf
+      })
+      .sum()
+  }
+
+  fn sample(&self, wo: Vec3) -> Option<Vec3> {
+    if wo.z() <= 0.0 {
+      return None;
+    }
+    let mut u = rand::random::<f32>();
+    let probabilities = self.probabilities(wo);
+    let lobe = probabilities
+      .iter()
+      .find(|(_, p)| {
+        u -= p;
+        u < 0.0
+      })
+      .or_else(|| probabilities.iter().rev().find(|(_, p)| *p > 0.0))
+      .map(|(lobe, _)| lobe)?;
+
+    let wi = match lobe {
+      Lobe::Diffuse => {
+        // cosine-weighted, same as `Lambert`
+        let dir = Vec3::new(0.0, 0.0, 1.0) + Vec3::rand_unit();
+        if dir.near_zero() {
+          Vec3::new(0.0, 0.0, 1.0)
+        } else {
+          dir.unit_vector()
+        }
+      }
+      Lobe::Specular => reflect_local(wo, self.ggx.sample_normal(wo)),
+      Lobe::Transmission => dielectric_sample(&self.ggx, self.eta, wo)?,
+      Lobe::Clearcoat => reflect_local(wo, sample_gtr1(self.clearcoat_alpha)),
+    };
+    Some(wi)
+  }
+}
+
+///////////////////////
+// Clearcoat
+
+/** GTR1 (Berry) distribution, has longer tails than GGX */
+fn gtr1(cos_h: f32, alpha: f32) -> f32 {
+  if cos_h <= 0.0 {
+    return 0.0;
+  }
+  let a_sq = alpha * alpha;
+  let t = 1.0 + (a_sq - 1.0) * cos_h * cos_h;
+  (a_sq - 1.0) / (PI * a_sq.ln() * t)
+}
+
+/** Normal picked with probability `gtr1 * cos_h` */
+fn sample_gtr1(alpha: f32) -> Vec3 {
+  let a_sq = alpha * alpha;
+  let u: f32 = rand::random();
+  let cos_sq = (1.0 - a_sq.powf(1.0 - u)) / (1.0 - a_sq);
+  let cos_h = cos_sq.clamp(0.0, 1.0).sqrt();
+  let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+  let phi = 2.0 * PI * rand::random::<f32>();
+  Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+}
+
+fn clearcoat_masking() -> GGX {
+  GGX::from_roughness(0.5, 0.5)
+}
+
+impl Material for PrincipledMaterial {
+  /** One lobe picks the direction, the weight includes all of them */
+  fn sample(&self, hit: &RayHit, wo: Vec3) -> Option<BSDFSample> {
+    let lobes = self.lobes(hit);
+    let frame = hit.shading_frame();
+    let wo = frame.world_to_local(wo);
+    let wi = lobes.sample(wo)?;
+    let pdf = lobes.pdf(wo, wi);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BSDFSample {
+      wi: frame.local(wi.x(), wi.y(), wi.z()),
+      weight: lobes.eval(wo, wi) / pdf,
+      pdf,
+      delta: false,
+    })
+  }
+
+  fn eval(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> Color {
+    let frame = hit.shading_frame();
+    self
+      .lobes(hit)
+      .eval(frame.world_to_local(wo), frame.world_to_local(wi))
+  }
+
+  fn pdf(&self, hit: &RayHit, wo: Vec3, wi: Vec3) -> f32 {
+    let frame = hit.shading_frame();
+    self
+      .lobes(hit)
+      .pdf(frame.world_to_local(wo), frame.world_to_local(wi))
+  }
+
+  fn emitted(&self, _r_in: &Ray, hit: &RayHit) -> Color {
+    match &self.emission {
+      Some(tex) => tex.sample(hit),
+      None => Color::zero(),
+    }
+  }
+
+  fn is_emissive(&self) -> bool {
+    self.emission.is_some()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::utils::random_in_cone;
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn hit(material: Arc<dyn Material>, front_face: bool) -> RayHit {
+    RayHit {
+      p: Point3d::zero(),
+      normal: Vec3::up(),
+      geometric_normal: Vec3::up(),
+      tangent: Vec3::right(),
+      bitangent: Vec3::forward(),
+      t: 1.0,
+      u: 0.0,
+      v: 0.0,
+      front_face,
+      vertex_color: None,
+      material,
+    }
+  }
+
+  fn materials() -> Vec<PrincipledMaterial> {
+    let plastic = PrincipledMaterial::new(Color::new(0.8, 0.3, 0.2));
+    let metal = PrincipledMaterial {
+      metallic: constant(1.0),
+      roughness: constant(0.3),
+      ..PrincipledMaterial::new(Color::new(0.9, 0.8, 0.5))
+    };
+    let car_paint = PrincipledMaterial {
+      clearcoat: constant(1.0),
+      clearcoat_gloss: constant(0.8),
+      sheen: constant(0.5),
+      specular_tint: constant(0.5),
+      ..PrincipledMaterial::new(Color::new(0.1, 0.2, 0.6))
+    };
+    let glass = PrincipledMaterial {
+      transmission: constant(0.8),
+      roughness: constant(0.4),
+      ..PrincipledMaterial::new(Color::new(0.9, 1.0, 0.9))
+    };
+    vec![plastic, metal, car_paint, glass]
+  }
+
+  /** `weight` returned by `sample` has to agree with `eval / pdf` */
+  #[test]
+  fn sample_matches_eval_and_pdf() {
+    let wo = Vec3::new(0.6, 0.8, 0.0);
+    for material in materials() {
+      let material = Arc::new(material);
+      for front_face in [true, false] {
+        let hit = hit(material.clone(), front_face);
+        for _ in 0..200 {
+          let sample = match material.sample(&hit, wo) {
+            Some(s) => s,
+            None => continue,
+          };
+          let pdf = material.pdf(&hit, wo, sample.wi);
+          assert_approx_eq!(sample.pdf, pdf, pdf * 1e-3);
+          let expected = material.eval(&hit, wo, sample.wi) / pdf;
+          assert_approx_eq!(sample.weight.y(), expected.y(), expected.y() * 1e-3 + 1e-5);
+        }
+      }
+    }
+  }
+
+  /** Average `weight` of samples and integral of `eval` both estimate reflected energy */
+  #[test]
+  fn sampled_albedo_matches_eval() {
+    let wo = Vec3::new(0.6, 0.8, 0.0);
+    for material in materials() {
+      let material = Arc::new(material);
+      let hit = hit(material.clone(), true);
+      let (n, n_uniform) = (50000, 500000);
+      let sampled: f32 = (0..n)
+        .filter_map(|_| material.sample(&hit, wo))
+        .map(|s| luminance(s.weight))
+        .sum();
+      let integrated: f32 = (0..n_uniform)
+        .map(|_| {
+          let wi = random_in_cone(Vec3::up(), -1.0);
+          luminance(material.eval(&hit, wo, wi)) * 4.0 * PI
+        })
+        .sum();
+      let (sampled, integrated) = (sampled / n as f32, integrated / n_uniform as f32);
+      assert_approx_eq!(sampled, integrated, integrated * 0.05);
+    }
+  }
+
+  #[test]
+  fn gtr1_is_normalized() {
+    let n = 100000;
+    let alpha = 0.3;
+    let sum: f32 = (0..n)
+      .map(|_| {
+        let wh = random_in_cone(Vec3::new(0.0, 0.0, 1.0), 0.0);
+        gtr1(wh.z(), alpha) * wh.z() * 2.0 * PI
+      })
+      .sum();
+    assert_approx_eq!(sum / n as f32, 1.0, 0.03);
+  }
+}
@@ -4,6 +4,7 @@ use crate::aabb::AABB;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::traceable::{RayHit, Traceable};
+use crate::utils::area_pdf_to_solid_angle;
 use crate::vec3::{Point3d, Vec3};
 
 #[derive(Clone, Debug)]
@@ -35,8 +36,8 @@ impl Traceable for Rectangle {
   fn bounding_box(&self) -> Option<AABB> {
     // since plane has infini-small depth, we pad it a bit
     Some(AABB {
-      min: Point3d::new(self.x0, self.y0, self.k + 0.1),
-      max: Point3d::new(self.x1, self.y1, self.k - 0.1),
+      min: Point3d::new(self.x0, self.y0, self.k - 0.1),
+      max: Point3d::new(self.x1, self.y1, self.k + 0.1),
     })
   }
 
@@ -63,8 +64,41 @@ impl Traceable for Rectangle {
       u: (p.x() - self.x0) / (self.x1 - self.x0),
       v: (p.y() - self.y0) / (self.y1 - self.y0),
       normal: outward_normal,
+      geometric_normal: outward_normal,
+      tangent: Vec3::right() * (self.x1 - self.x0),
+      bitangent: Vec3::up() * (self.y1 - self.y0),
       front_face,
+      vertex_color: None,
       material: self.material.clone(),
     })
   }
+
+  fn is_light(&self) -> bool {
+    self.material.is_emissive()
+  }
+
+  fn sample_direction(&self, origin: Point3d) -> Option<(Vec3, f32)> {
+    let p = Point3d::new(
+      self.x0 + rand::random::<f32>() * (self.x1 - self.x0),
+      self.y0 + rand::random::<f32>() * (self.y1 - self.y0),
+      self.k,
+    );
+    let to_point = p - origin;
+    let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+    let pdf = area_pdf_to_solid_angle(1.0 / area, to_point, !Vec3::forward());
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some((to_point, pdf))
+  }
+
+  fn direction_pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    match self.check_intersection(r, t_min, t_max) {
+      Some(hit) => {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf_to_solid_angle(1.0 / area, hit.p - r.origin, !Vec3::forward())
+      }
+      None => 0.0,
+    }
+  }
 }
@@ -0,0 +1,1294 @@
+use glam::f32::Mat3;
+use noise::Perlin;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::box_prim::BoxPrim;
+use crate::bvh::BVH;
+use crate::environment::EnvironmentMap;
+use crate::gltf_loader::{load_gltf, GltfError};
+use crate::isotropic_mat::IsotropicMat;
+use crate::light::{DiffuseLight, DirectionalLight, PointLight, PunctualLight, SpotLight};
+use crate::material::{
+  CoatedMaterial, Conductor, Dielectric, Lambert, Material, Metal, MixMaterial, OrenNayar,
+  RoughDielectric, SolidColor, ThinFilm,
+};
+use crate::normal_map::{BumpMap, ModifiedNormal, NormalMap};
+use crate::obj_loader::{load_obj, ObjError};
+use crate::plane::Plane;
+use crate::ply_loader::{load_ply, PlyError};
+use crate::principled::PrincipledMaterial;
+use crate::rectangle::Rectangle;
+use crate::scenes::scene_settings::SceneSettings;
+use crate::sky::Sky;
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTex, ImageTex, NoiseTex, SolidColorTex, Texture, UVDebugTex};
+use crate::traceable::Traceable;
+use crate::transform::Transform;
+use crate::triangle::Triangle;
+use crate::vec3::{Color, Vec3};
+use crate::volumetric::Volumetric;
+use crate::world::World;
+
+// Declarative scene description stored as TOML. Example:
+//
+//   [settings]
+//   camera_position = [0.0, 1.0, 3.5]
+//
+//   [materials.red]
+//   type = "lambert"
+//   albedo = [1.0, 0.0, 0.0]
+//
+//   [[objects]]
+//   type = "sphere"
+//   center = [0.0, 0.0, 0.0]
+//   radius = 0.5
+//   material = "red"
+//
+// Textures and materials are declared once by name and then referenced.
+// See `assets/scenes` for complete files.
+
+// Values in file are f64, so they are written back exactly as they were typed
+type V3 = [f64; 3];
+
+fn to_vec3(v: V3) -> Vec3 {
+  Vec3::new(v[0] as f32, v[1] as f32, v[2] as f32)
+}
+
+fn from_vec3(v: Vec3) -> V3 {
+  [v.x() as f64, v.y() as f64, v.z() as f64]
+}
+
+#[derive(Debug)]
+/** Everything that can go wrong when reading/writing scene file */
+pub enum SceneFileError {
+  Io(std::io::Error),
+  Parse(toml::de::Error),
+  Write(toml::ser::Error),
+  Image(String, image::ImageError),
+  UnknownTexture(String),
+  UnknownMaterial(String),
+  InvalidMaterial(String),
+  Obj(ObjError),
+  Ply(PlyError),
+  Gltf(GltfError),
+  SkyWithEnvironment,
+}
+
+impl fmt::Display for SceneFileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SceneFileError::Io(e) => write!(f, "IO error: {}", e),
+      SceneFileError::Parse(e) => write!(f, "Invalid scene file: {}", e),
+      SceneFileError::Write(e) => write!(f, "Could not serialize scene: {}", e),
+      SceneFileError::Image(path, e) => write!(f, "Could not load image '{}': {}", path, e),
+      SceneFileError::UnknownTexture(name) => write!(f, "Unknown texture '{}'", name),
+      SceneFileError::UnknownMaterial(name) => write!(f, "Unknown material '{}'", name),
+      SceneFileError::InvalidMaterial(msg) => write!(f, "Invalid material: {}", msg),
+      SceneFileError::Obj(e) => write!(f, "Could not load OBJ: {}", e),
+      SceneFileError::Ply(e) => write!(f, "Could not load PLY: {}", e),
+      SceneFileError::Gltf(e) => write!(f, "Could not load glTF: {}", e),
+      SceneFileError::SkyWithEnvironment => {
+        write!(f, "Scene cannot have both sky and environment map")
+      }
+    }
+  }
+}
+
+impl std::error::Error for SceneFileError {}
+
+///////////////////////
+// Settings
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/** File representation of `SceneSettings`. Missing values use `SceneSettings::default()` */
+pub struct SettingsDef {
+  pub camera_position: V3,
+  pub camera_target: V3,
+  pub camera_aperture: f64,
+  pub camera_fov: f64,
+  pub background: V3,
+  pub samples_per_pixel: usize,
+  pub max_bounces: i32,
+}
+
+impl Default for SettingsDef {
+  fn default() -> Self {
+    SettingsDef::from(&SceneSettings::default())
+  }
+}
+
+impl From<&SceneSettings> for SettingsDef {
+  fn from(cfg: &SceneSettings) -> Self {
+    Self {
+      camera_position: from_vec3(cfg.camera_position),
+      camera_target: from_vec3(cfg.camera_target),
+      camera_aperture: cfg.camera_aperture as f64,
+      camera_fov: cfg.camera_fov as f64,
+      background: from_vec3(cfg.background),
+      samples_per_pixel: cfg.samples_per_pixel,
+      max_bounces: cfg.max_bounces,
+    }
+  }
+}
+
+impl From<&SettingsDef> for SceneSettings {
+  fn from(cfg: &SettingsDef) -> Self {
+    Self {
+      camera_position: to_vec3(cfg.camera_position),
+      camera_target: to_vec3(cfg.camera_target),
+      camera_aperture: cfg.camera_aperture as f32,
+      camera_fov: cfg.camera_fov as f32,
+      background: to_vec3(cfg.background),
+      environment: None, // see `SceneFile::environment`
+      samples_per_pixel: cfg.samples_per_pixel,
+      max_bounces: cfg.max_bounces,
+      lights: Vec::new(), // listed separately in `SceneFile::lights`
+    }
+  }
+}
+
+///////////////////////
+// Lights
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+/** Lights without geometry. Angles are in degrees */
+pub enum LightDef {
+  Point {
+    position: V3,
+    color: V3,
+    intensity: f64,
+  },
+  /** `inner_angle` and `outer_angle` are measured from the `direction` */
+  Spot {
+    position: V3,
+    direction: V3,
+    color: V3,
+    intensity: f64,
+    #[serde(default)]
+    inner_angle: f64,
+    outer_angle: f64,
+  },
+  Directional {
+    direction: V3,
+    color: V3,
+    intensity: f64,
+    #[serde(default)]
+    angular_radius: f64,
+  },
+}
+
+fn build_light(light: &LightDef) -> Arc<dyn PunctualLight> {
+  match light {
+    LightDef::Point {
+      position,
+      color,
+      intensity,
+    } => Arc::new(PointLight::new(
+      to_vec3(*position),
+      to_vec3(*color),
+      *intensity as f32,
+    )),
+    LightDef::Spot {
+      position,
+      direction,
+      color,
+      intensity,
+      inner_angle,
+      outer_angle,
+    } => Arc::new(SpotLight::new(
+      to_vec3(*position),
+      to_vec3(*direction),
+      to_vec3(*color),
+      *intensity as f32,
+      (*inner_angle as f32).to_radians(),
+      (*outer_angle as f32).to_radians(),
+    )),
+    LightDef::Directional {
+      direction,
+      color,
+      intensity,
+      angular_radius,
+    } => Arc::new(DirectionalLight::new(
+      to_vec3(*direction),
+      to_vec3(*color),
+      *intensity as f32,
+      (*angular_radius as f32).to_radians(),
+    )),
+  }
+}
+
+///////////////////////
+// Environment
+
+fn default_intensity() -> f64 {
+  1.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/**
+Equirectangular .hdr or .exr image, replaces `background`. Relative path is resolved against
+the directory of the scene file. Rotation is around the y axis, in degrees.
+*/
+pub struct EnvironmentDef {
+  pub path: String,
+  #[serde(default)]
+  pub rotation: f64,
+  #[serde(default = "default_intensity")]
+  pub intensity: f64,
+}
+
+fn default_turbidity() -> f64 {
+  3.0
+}
+
+fn default_ground_albedo() -> V3 {
+  [0.3, 0.3, 0.3]
+}
+
+fn default_sun_angular_radius() -> f64 {
+  0.27
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/**
+Analytic daylight sky with matching sun light, replaces `background`. Angles in degrees.
+Azimuth 0 is towards -z, 90 towards +x. Cannot be combined with `environment`.
+*/
+pub struct SkyDef {
+  pub sun_elevation: f64,
+  #[serde(default)]
+  pub sun_azimuth: f64,
+  #[serde(default = "default_turbidity")]
+  pub turbidity: f64,
+  #[serde(default = "default_ground_albedo")]
+  pub ground_albedo: V3,
+  #[serde(default = "default_intensity")]
+  pub intensity: f64,
+  #[serde(default = "default_sun_angular_radius")]
+  pub sun_angular_radius: f64,
+}
+
+/** Resolution of the image the sky is baked into. Sky is smooth, sun is separate */
+const SKY_MAP_SIZE: (usize, usize) = (256, 128);
+
+fn build_sky(sky: &SkyDef) -> Sky {
+  let elevation = (sky.sun_elevation as f32).to_radians();
+  let azimuth = (sky.sun_azimuth as f32).to_radians();
+  let sun_direction = Vec3::new(
+    elevation.cos() * azimuth.sin(),
+    elevation.sin(),
+    -elevation.cos() * azimuth.cos(),
+  );
+  Sky::new(
+    sun_direction,
+    sky.turbidity as f32,
+    to_vec3(sky.ground_albedo),
+    sky.intensity as f32,
+    (sky.sun_angular_radius as f32).to_radians(),
+  )
+}
+
+///////////////////////
+// Textures
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDef {
+  Solid {
+    color: V3,
+  },
+  UvDebug,
+  Checker {
+    color1: V3,
+    color2: V3,
+    scale: f64,
+  },
+  Noise {
+    scale: f64,
+  },
+  /** Relative paths are resolved against the directory of the scene file */
+  Image {
+    path: String,
+  },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+/** Either inline `[r, g, b]` or name of the texture declared in `[textures]` */
+pub enum ColorOrTexture {
+  Color(V3),
+  Texture(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+/** Either inline number or name of the texture declared in `[textures]` */
+pub enum FloatOrTexture {
+  Float(f64),
+  Texture(String),
+}
+
+///////////////////////
+// Materials
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/**
+Thin film over `metal`, `conductor` or `dielectric`, see `ThinFilm`. `thickness` is in
+nanometres, red channel of `thickness_map` texture multiplies it.
+*/
+pub struct FilmDef {
+  pub ior: f64,
+  pub thickness: f64,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub thickness_map: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDef {
+  SolidColor {
+    color: V3,
+  },
+  Lambert {
+    albedo: ColorOrTexture,
+  },
+  /** Rough diffuse, `roughness` 0 looks like `lambert` */
+  OrenNayar {
+    albedo: ColorOrTexture,
+    roughness: f64,
+  },
+  Metal {
+    albedo: V3,
+    roughness: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    film: Option<FilmDef>,
+  },
+  /**
+  Metal with complex IOR. Either `metal` preset ("gold", "copper", "aluminium",
+  "silver") or both `eta` and `k`. `roughness_v` is for brushed metals, by default
+  it's the same as `roughness`.
+  */
+  Conductor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eta: Option<V3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    k: Option<V3>,
+    roughness: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    roughness_v: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    film: Option<FilmDef>,
+  },
+  /** `absorption` is per unit of distance travelled inside, for colored glass and liquids */
+  Dielectric {
+    albedo: V3,
+    ior: f64,
+    #[serde(default)]
+    absorption: V3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    film: Option<FilmDef>,
+  },
+  /** Frosted glass. `albedo` tints only the transmitted light */
+  RoughDielectric {
+    albedo: V3,
+    ior: f64,
+    roughness: f64,
+    #[serde(default)]
+    absorption: V3,
+  },
+  /**
+  Disney principled BSDF. Missing parameters use defaults of `PrincipledMaterial::new`.
+  Scalar textures use their red channel.
+  */
+  Principled {
+    base_color: ColorOrTexture,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metallic: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    roughness: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    specular: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    specular_tint: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sheen: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sheen_tint: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clearcoat: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clearcoat_gloss: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transmission: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ior: Option<FloatOrTexture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    emission: Option<ColorOrTexture>,
+  },
+  /** Blend of two other materials, `weight` is how much of `material2` */
+  Mix {
+    material1: String,
+    material2: String,
+    weight: FloatOrTexture,
+  },
+  /** Clearcoat or varnish over other material */
+  Coated {
+    base: String,
+    ior: f64,
+    #[serde(default)]
+    roughness: f64,
+  },
+  /** Tangent-space normal map texture over other material */
+  NormalMap {
+    base: String,
+    map: String,
+    #[serde(default = "default_intensity")]
+    strength: f64,
+    /** For maps in OpenGL convention, see `NormalMap::green_up` */
+    #[serde(default)]
+    green_up: bool,
+  },
+  /** Red channel of `height` texture moves the surface by up to `scale` scene units */
+  BumpMap {
+    base: String,
+    height: String,
+    scale: f64,
+  },
+  DiffuseLight {
+    albedo: ColorOrTexture,
+    strength: f64,
+  },
+  Isotropic {
+    albedo: ColorOrTexture,
+  },
+}
+
+///////////////////////
+// Objects
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+/**
+Same as `Transform::from_transform_rot`. Rotation is in degrees and is applied
+in x, y, z order.
+*/
+pub struct TransformDef {
+  #[serde(default)]
+  pub rotation: V3,
+  #[serde(default)]
+  pub translation: V3,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeDef {
+  Sphere {
+    center: V3,
+    radius: f64,
+    material: String,
+  },
+  /** Infinite plane */
+  Plane {
+    point: V3,
+    normal: V3,
+    material: String,
+  },
+  Rectangle {
+    p0: [f64; 2],
+    p1: [f64; 2],
+    #[serde(default)]
+    k: f64,
+    material: String,
+  },
+  Box {
+    dims: V3,
+    material: String,
+  },
+  /** Counter-clockwise winding is the front face */
+  Triangle {
+    v0: V3,
+    v1: V3,
+    v2: V3,
+    material: String,
+  },
+  /**
+  Wavefront OBJ or PLY file. Relative paths are resolved against the directory of the scene file.
+  OBJ materials come from the MTL files, `material` is used for faces without one.
+  PLY uses `material` if provided, otherwise vertex colors.
+  */
+  Mesh {
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<String>,
+  },
+  /**
+  glTF 2.0 file (.gltf or .glb). Relative paths are resolved against the directory
+  of the scene file. Cameras in the file are ignored, lights are added to the scene
+  */
+  Gltf {
+    path: String,
+  },
+  /**
+  Volume with the same shape as the wrapped object. Wrapped object's material is ignored.
+  Without `shape` it fills the whole scene e.g. global fog
+  */
+  Volumetric {
+    density: f64,
+    albedo: ColorOrTexture,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shape: Option<Box<ObjectDef>>,
+  },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/** `ShapeDef` receives every key except `transform`, so it's the one rejecting typos */
+pub struct ObjectDef {
+  #[serde(flatten)]
+  pub shape: ShapeDef,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub transform: Option<TransformDef>,
+}
+
+///////////////////////
+// Scene file
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/** Whole scene as stored in the file */
+pub struct SceneFile {
+  pub settings: SettingsDef,
+  pub textures: BTreeMap<String, TextureDef>,
+  pub materials: BTreeMap<String, MaterialDef>,
+  pub objects: Vec<ObjectDef>,
+  pub lights: Vec<LightDef>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub environment: Option<EnvironmentDef>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sky: Option<SkyDef>,
+}
+
+impl SceneFile {
+  pub fn load(path: &Path) -> Result<SceneFile, SceneFileError> {
+    let text = std::fs::read_to_string(path).map_err(SceneFileError::Io)?;
+    SceneFile::from_toml(&text)
+  }
+
+  pub fn from_toml(text: &str) -> Result<SceneFile, SceneFileError> {
+    toml::from_str(text).map_err(SceneFileError::Parse)
+  }
+
+  #[allow(dead_code)]
+  pub fn save(&self, path: &Path) -> Result<(), SceneFileError> {
+    let text = self.to_toml()?;
+    std::fs::write(path, text).map_err(SceneFileError::Io)
+  }
+
+  pub fn to_toml(&self) -> Result<String, SceneFileError> {
+    // Going through `toml::Value` makes sure plain values are written before
+    // the nested tables, otherwise toml serializer refuses to write the file
+    let value = toml::Value::try_from(self).map_err(SceneFileError::Write)?;
+    toml::to_string(&value).map_err(SceneFileError::Write)
+  }
+
+  /**
+  Create all objects declared in the file. `base_dir` is used to resolve
+  relative paths e.g. for image textures.
+  */
+  pub fn build(&self, base_dir: &Path) -> Result<(World, SceneSettings), SceneFileError> {
+    let mut textures: HashMap<&str, Arc<dyn Texture>> = HashMap::new();
+    for (name, tex) in &self.textures {
+      textures.insert(name, build_texture(tex, base_dir)?);
+    }
+
+    let mut materials: HashMap<&str, Arc<dyn Material>> = HashMap::new();
+    for name in self.materials.keys() {
+      build_named_material(
+        name,
+        &self.materials,
+        &textures,
+        &mut materials,
+        &mut Vec::new(),
+      )?;
+    }
+
+    let mut cfg = SceneSettings::from(&self.settings);
+    cfg.lights = self.lights.iter().map(build_light).collect();
+    if let Some(env) = &self.environment {
+      let map = EnvironmentMap::load(
+        &base_dir.join(&env.path),
+        (env.rotation as f32).to_radians(),
+        env.intensity as f32,
+      )
+      .map_err(|e| SceneFileError::Image(env.path.clone(), e))?;
+      cfg.environment = Some(Arc::new(map));
+    }
+    if let Some(sky) = &self.sky {
+      if self.environment.is_some() {
+        return Err(SceneFileError::SkyWithEnvironment);
+      }
+      let sky = build_sky(sky);
+      let (width, height) = SKY_MAP_SIZE;
+      cfg.environment = Some(Arc::new(sky.to_environment_map(width, height)));
+      if let Some(sun) = sky.sun() {
+        cfg.lights.push(Arc::new(sun));
+      }
+    }
+
+    let mut world = World::new();
+    for obj in &self.objects {
+      world.add(build_object(
+        obj,
+        base_dir,
+        &textures,
+        &materials,
+        &mut cfg.lights,
+      )?);
+    }
+
+    Ok((world, cfg))
+  }
+}
+
+fn build_texture(tex: &TextureDef, base_dir: &Path) -> Result<Arc<dyn Texture>, SceneFileError> {
+  let result: Arc<dyn Texture> = match tex {
+    TextureDef::Solid { color } => Arc::new(SolidColorTex::from_color(to_vec3(*color))),
+    TextureDef::UvDebug => Arc::new(UVDebugTex {}),
+    TextureDef::Checker {
+      color1,
+      color2,
+      scale,
+    } => Arc::new(CheckerTex {
+      color1: to_vec3(*color1),
+      color2: to_vec3(*color2),
+      scale: *scale as f32,
+    }),
+    TextureDef::Noise { scale } => Arc::new(NoiseTex {
+      noise: Perlin::default(),
+      scale: *scale as f32,
+    }),
+    TextureDef::Image { path } => {
+      let image =
+        ImageTex::load(&base_dir.join(path)).map_err(|e| SceneFileError::Image(path.clone(), e))?;
+      Arc::new(image)
+    }
+  };
+  Ok(result)
+}
+
+fn get_texture(
+  albedo: &ColorOrTexture,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Texture>, SceneFileError> {
+  match albedo {
+    ColorOrTexture::Color(c) => Ok(Arc::new(SolidColorTex::from_color(to_vec3(*c)))),
+    ColorOrTexture::Texture(name) => textures
+      .get(name.as_str())
+      .cloned()
+      .ok_or_else(|| SceneFileError::UnknownTexture(name.clone())),
+  }
+}
+
+fn get_float_texture(
+  value: &FloatOrTexture,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Arc<dyn Texture>, SceneFileError> {
+  match value {
+    FloatOrTexture::Float(v) => Ok(Arc::new(SolidColorTex::from_color(Vec3::uni(*v as f32)))),
+    FloatOrTexture::Texture(name) => get_texture(&ColorOrTexture::Texture(name.clone()), textures),
+  }
+}
+
+fn build_film(
+  film: &Option<FilmDef>,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+) -> Result<Option<ThinFilm>, SceneFileError> {
+  let film = match film {
+    Some(film) => film,
+    None => return Ok(None),
+  };
+  let thickness_map = match &film.thickness_map {
+    Some(name) => Some(get_texture(
+      &ColorOrTexture::Texture(name.clone()),
+      textures,
+    )?),
+    None => None,
+  };
+  Ok(Some(ThinFilm {
+    thickness_map,
+    ..ThinFilm::new(film.ior as f32, film.thickness as f32)
+  }))
+}
+
+/**
+Materials can contain other materials, those are built first. `stack` holds
+the materials being built, to catch ones that contain themselves.
+*/
+fn build_named_material<'a>(
+  name: &str,
+  defs: &'a BTreeMap<String, MaterialDef>,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+  materials: &mut HashMap<&'a str, Arc<dyn Material>>,
+  stack: &mut Vec<&'a str>,
+) -> Result<Arc<dyn Material>, SceneFileError> {
+  if let Some(material) = materials.get(name) {
+    return Ok(material.clone());
+  }
+  let (name, def) = defs
+    .get_key_value(name)
+    .ok_or_else(|| SceneFileError::UnknownMaterial(name.to_string()))?;
+  if stack.contains(&name.as_str()) {
+    return Err(SceneFileError::InvalidMaterial(format!(
+      "'{}' contains itself",
+      name
+    )));
+  }
+
+  stack.push(name);
+  let material = build_material(def, textures, &mut |inner| {
+    build_named_material(inner, defs, textures, materials, stack)
+  })?;
+  stack.pop();
+  materials.insert(name, material.clone());
+  Ok(material)
+}
+
+/** Returns material with the given name, building it if needed */
+type MaterialLookup<'a> = dyn FnMut(&str) -> Result<Arc<dyn Material>, SceneFileError> + 'a;
+
+fn build_material(
+  mat: &MaterialDef,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+  get_material: &mut MaterialLookup,
+) -> Result<Arc<dyn Material>, SceneFileError> {
+  let result: Arc<dyn Material> = match mat {
+    MaterialDef::SolidColor { color } => Arc::new(SolidColor {
+      color: to_vec3(*color),
+    }),
+    MaterialDef::Lambert { albedo } => Arc::new(Lambert::texture(get_texture(albedo, textures)?)),
+    MaterialDef::OrenNayar { albedo, roughness } => Arc::new(OrenNayar::texture(
+      get_texture(albedo, textures)?,
+      *roughness as f32,
+    )),
+    MaterialDef::Metal {
+      albedo,
+      roughness,
+      film,
+    } => Arc::new(Metal {
+      albedo: to_vec3(*albedo),
+      roughness: *roughness as f32,
+      film: build_film(film, textures)?,
+    }),
+    MaterialDef::Conductor {
+      metal,
+      eta,
+      k,
+      roughness,
+      roughness_v,
+      film,
+    } => {
+      let ior = match (metal, eta, k) {
+        (_, Some(eta), Some(k)) => (to_vec3(*eta), to_vec3(*k)),
+        (Some(name), None, None) => Conductor::preset_ior(name)
+          .ok_or_else(|| SceneFileError::InvalidMaterial(format!("Unknown metal '{}'", name)))?,
+        _ => {
+          return Err(SceneFileError::InvalidMaterial(
+            "Conductor needs either `metal` or both `eta` and `k`".to_string(),
+          ))
+        }
+      };
+      let roughness_u = *roughness as f32;
+      let roughness_v = roughness_v.map_or(roughness_u, |r| r as f32);
+      Arc::new(Conductor {
+        film: build_film(film, textures)?,
+        ..Conductor::anisotropic(ior, roughness_u, roughness_v)
+      })
+    }
+    MaterialDef::Dielectric {
+      albedo,
+      ior,
+      absorption,
+      film,
+    } => Arc::new(Dielectric {
+      albedo: to_vec3(*albedo),
+      ior: *ior as f32,
+      absorption: to_vec3(*absorption),
+      film: build_film(film, textures)?,
+    }),
+    MaterialDef::RoughDielectric {
+      albedo,
+      ior,
+      roughness,
+      absorption,
+    } => Arc::new(RoughDielectric {
+      albedo: to_vec3(*albedo),
+      ior: *ior as f32,
+      roughness: *roughness as f32,
+      absorption: to_vec3(*absorption),
+    }),
+    MaterialDef::Principled {
+      base_color,
+      metallic,
+      roughness,
+      specular,
+      specular_tint,
+      sheen,
+      sheen_tint,
+      clearcoat,
+      clearcoat_gloss,
+      transmission,
+      ior,
+      emission,
+    } => {
+      let defaults = PrincipledMaterial::new(Color::one());
+      // missing parameters keep the default
+      let param = |value: &Option<FloatOrTexture>, default| match value {
+        Some(v) => get_float_texture(v, textures),
+        None => Ok(default),
+      };
+      Arc::new(PrincipledMaterial {
+        base_color: get_texture(base_color, textures)?,
+        metallic: param(metallic, defaults.metallic)?,
+        roughness: param(roughness, defaults.roughness)?,
+        specular: param(specular, defaults.specular)?,
+        specular_tint: param(specular_tint, defaults.specular_tint)?,
+        sheen: param(sheen, defaults.sheen)?,
+        sheen_tint: param(sheen_tint, defaults.sheen_tint)?,
+        clearcoat: param(clearcoat, defaults.clearcoat)?,
+        clearcoat_gloss: param(clearcoat_gloss, defaults.clearcoat_gloss)?,
+        transmission: param(transmission, defaults.transmission)?,
+        ior: param(ior, defaults.ior)?,
+        emission: match emission {
+          Some(v) => Some(get_texture(v, textures)?),
+          None => None,
+        },
+      })
+    }
+    MaterialDef::Mix {
+      material1,
+      material2,
+      weight,
+    } => Arc::new(MixMaterial::new(
+      get_material(material1)?,
+      get_material(material2)?,
+      get_float_texture(weight, textures)?,
+    )),
+    MaterialDef::Coated {
+      base,
+      ior,
+      roughness,
+    } => Arc::new(CoatedMaterial::new(
+      get_material(base)?,
+      *ior as f32,
+      *roughness as f32,
+    )),
+    MaterialDef::NormalMap {
+      base,
+      map,
+      strength,
+      green_up,
+    } => Arc::new(ModifiedNormal::new(
+      get_material(base)?,
+      Arc::new(NormalMap {
+        map: get_texture(&ColorOrTexture::Texture(map.clone()), textures)?,
+        strength: *strength as f32,
+        green_up: *green_up,
+      }),
+    )),
+    MaterialDef::BumpMap {
+      base,
+      height,
+      scale,
+    } => Arc::new(ModifiedNormal::new(
+      get_material(base)?,
+      Arc::new(BumpMap {
+        height: get_texture(&ColorOrTexture::Texture(height.clone()), textures)?,
+        scale: *scale as f32,
+      }),
+    )),
+    MaterialDef::DiffuseLight { albedo, strength } => Arc::new(DiffuseLight::texture(
+      get_texture(albedo, textures)?,
+      *strength as f32,
+    )),
+    MaterialDef::Isotropic { albedo } => {
+      Arc::new(IsotropicMat::texture(get_texture(albedo, textures)?))
+    }
+  };
+  Ok(result)
+}
+
+/** Punctual lights that come with the object (e.g. from glTF) are added to `lights` */
+fn build_object(
+  obj: &ObjectDef,
+  base_dir: &Path,
+  textures: &HashMap<&str, Arc<dyn Texture>>,
+  materials: &HashMap<&str, Arc<dyn Material>>,
+  lights: &mut Vec<Arc<dyn PunctualLight>>,
+) -> Result<Arc<dyn Traceable>, SceneFileError> {
+  let get_material = |name: &String| {
+    materials
+      .get(name.as_str())
+      .cloned()
+      .ok_or_else(|| SceneFileError::UnknownMaterial(name.clone()))
+  };
+
+  let mut object_lights: Vec<Arc<dyn PunctualLight>> = Vec::new();
+  let shape: Arc<dyn Traceable> = match &obj.shape {
+    ShapeDef::Sphere {
+      center,
+      radius,
+      material,
+    } => Arc::new(Sphere::new(
+      to_vec3(*center),
+      *radius as f32,
+      get_material(material)?,
+    )),
+    ShapeDef::Plane {
+      point,
+      normal,
+      material,
+    } => Arc::new(Plane::new(
+      to_vec3(*point),
+      to_vec3(*normal),
+      get_material(material)?,
+    )),
+    ShapeDef::Rectangle {
+      p0,
+      p1,
+      k,
+      material,
+    } => Arc::new(Rectangle::new(
+      (p0[0] as f32, p0[1] as f32),
+      (p1[0] as f32, p1[1] as f32),
+      *k as f32,
+      get_material(material)?,
+    )),
+    ShapeDef::Box { dims, material } => {
+      Arc::new(BoxPrim::new(to_vec3(*dims), get_material(material)?))
+    }
+    ShapeDef::Triangle {
+      v0,
+      v1,
+      v2,
+      material,
+    } => Arc::new(Triangle::new(
+      to_vec3(*v0),
+      to_vec3(*v1),
+      to_vec3(*v2),
+      get_material(material)?,
+    )),
+    ShapeDef::Mesh { path, material } => {
+      let path = base_dir.join(path);
+      let material = match material {
+        Some(name) => Some(get_material(name)?),
+        None => None,
+      };
+      if path.extension().and_then(|s| s.to_str()) == Some("ply") {
+        Arc::new(load_ply(&path, material).map_err(SceneFileError::Ply)?)
+      } else {
+        let default_material = material.unwrap_or_else(|| Arc::new(Lambert::color(0.8, 0.8, 0.8)));
+        let meshes = load_obj(&path, default_material).map_err(SceneFileError::Obj)?;
+        Arc::new(BVH::from_objects(&meshes))
+      }
+    }
+    ShapeDef::Gltf { path } => {
+      let scene = load_gltf(&base_dir.join(path)).map_err(SceneFileError::Gltf)?;
+      object_lights = scene.lights;
+      Arc::new(BVH::from_objects(&scene.objects))
+    }
+    ShapeDef::Volumetric {
+      density,
+      albedo,
+      shape,
+    } => {
+      let tex = get_texture(albedo, textures)?;
+      match shape {
+        Some(shape) => {
+          let inner = build_object(shape, base_dir, textures, materials, &mut object_lights)?;
+          Arc::new(Volumetric::texture(inner, *density as f32, tex))
+        }
+        None => Arc::new(Volumetric::everywhere(*density as f32, tex)),
+      }
+    }
+  };
+
+  match &obj.transform {
+    None => {
+      lights.append(&mut object_lights);
+      Ok(shape)
+    }
+    Some(tfx) => {
+      let rot = to_vec3(tfx.rotation);
+      let mat3 = Mat3::from_rotation_z(rot.z().to_radians())
+        * Mat3::from_rotation_y(rot.y().to_radians())
+        * Mat3::from_rotation_x(rot.x().to_radians());
+      let translation = to_vec3(tfx.translation).to_glam();
+      let transform = Transform::from_transform_rot(mat3, translation, shape);
+      let to_world = transform.object_to_world();
+      lights.extend(object_lights.iter().map(|l| l.transformed(to_world)));
+      Ok(Arc::new(transform))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+
+  const CORNELL_BOX: &str = include_str!("../assets/scenes/cornell_box.toml");
+  const PUNCTUAL_LIGHTS: &str = include_str!("../assets/scenes/punctual_lights.toml");
+  const ENVIRONMENT: &str = include_str!("../assets/scenes/environment.toml");
+  const SKY: &str = include_str!("../assets/scenes/sky.toml");
+  const METALS: &str = include_str!("../assets/scenes/metals.toml");
+  const FROSTED_GLASS: &str = include_str!("../assets/scenes/frosted_glass.toml");
+  const PRINCIPLED: &str = include_str!("../assets/scenes/principled.toml");
+  const ROUGH_DIFFUSE: &str = include_str!("../assets/scenes/rough_diffuse.toml");
+  const LAYERED: &str = include_str!("../assets/scenes/layered.toml");
+  const THIN_FILM: &str = include_str!("../assets/scenes/thin_film.toml");
+  const NORMAL_MAPS: &str = include_str!("../assets/scenes/normal_maps.toml");
+
+  #[test]
+  fn round_trip() {
+    let scene = SceneFile::from_toml(CORNELL_BOX).unwrap();
+    let text = scene.to_toml().unwrap();
+    let scene2 = SceneFile::from_toml(&text).unwrap();
+    assert_eq!(scene, scene2);
+  }
+
+  /** Scene that was created in code survives writing to disk and reading back */
+  #[test]
+  fn save_and_load() {
+    let mut scene = SceneFile::default();
+    scene.settings.camera_position = [1.0, 2.0, 3.0];
+    scene.materials.insert(
+      "red".to_string(),
+      MaterialDef::Lambert {
+        albedo: ColorOrTexture::Color([1.0, 0.0, 0.0]),
+      },
+    );
+    scene.objects.push(ObjectDef {
+      shape: ShapeDef::Sphere {
+        center: [0.0, 0.5, 0.0],
+        radius: 0.5,
+        material: "red".to_string(),
+      },
+      transform: Some(TransformDef {
+        rotation: [0.0, 45.0, 0.0],
+        translation: [1.0, 0.0, 0.0],
+      }),
+    });
+    scene.objects.push(ObjectDef {
+      shape: ShapeDef::Volumetric {
+        density: 0.5,
+        albedo: ColorOrTexture::Color([1.0, 1.0, 1.0]),
+        shape: Some(Box::new(ObjectDef {
+          shape: ShapeDef::Box {
+            dims: [1.0, 1.0, 1.0],
+            material: "red".to_string(),
+          },
+          transform: None,
+        })),
+      },
+      transform: None,
+    });
+
+    let path = std::env::temp_dir().join(format!("scene_test_{}.toml", std::process::id()));
+    scene.save(&path).unwrap();
+    let loaded = SceneFile::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(scene, loaded.unwrap());
+  }
+
+  #[test]
+  fn build_world() {
+    let scene = SceneFile::from_toml(CORNELL_BOX).unwrap();
+    let (world, cfg) = scene.build(Path::new("assets/scenes")).unwrap();
+    assert_eq!(world.objects.len(), scene.objects.len());
+    assert_eq!(cfg.max_bounces, scene.settings.max_bounces);
+  }
+
+  #[test]
+  fn build_lights() {
+    let scene = SceneFile::from_toml(PUNCTUAL_LIGHTS).unwrap();
+    let (_, cfg) = scene.build(Path::new("assets/scenes")).unwrap();
+    assert_eq!(cfg.lights.len(), 3);
+    let text = scene.to_toml().unwrap();
+    assert_eq!(scene, SceneFile::from_toml(&text).unwrap());
+  }
+
+  /** Lights of the wrapped object are moved by the transform of the wrapper */
+  #[test]
+  fn build_nested_lights() {
+    let light_dist = |shape: &str| {
+      let text = format!(
+        "[[objects]]\n{}\ntransform = {{ translation = [0.0, 3.0, 0.0] }}\n",
+        shape
+      );
+      let scene = SceneFile::from_toml(&text).unwrap();
+      let (_, cfg) = scene.build(Path::new("assets/scenes")).unwrap();
+      assert_eq!(cfg.lights.len(), 1);
+      cfg.lights[0].sample(Point3d::zero()).unwrap().dist
+    };
+    let gltf = light_dist("type = \"gltf\"\npath = \"../models/gltf_test.gltf\"");
+    let volumetric = light_dist(
+      "type = \"volumetric\"\ndensity = 1.0\nalbedo = [1.0, 1.0, 1.0]\n\
+      shape = { type = \"gltf\", path = \"../models/gltf_test.gltf\" }",
+    );
+    assert_approx_eq!(gltf, volumetric);
+    // light is at [0, 1, 1] in the glTF file
+    assert!((gltf - 2.0f32.sqrt()).abs() > 0.1);
+  }
+
+  #[test]
+  fn build_global_fog() {
+    let text = "[[objects]]\ntype = \"volumetric\"\ndensity = 0.1\nalbedo = [1.0, 1.0, 1.0]\n";
+    let scene = SceneFile::from_toml(text).unwrap();
+    let (world, _) = scene.build(Path::new("assets/scenes")).unwrap();
+    assert!(world.objects[0].bounding_box().is_none());
+    let text = scene.to_toml().unwrap();
+    assert_eq!(scene, SceneFile::from_toml(&text).unwrap());
+  }
+
+  #[test]
+  fn build_environment() {
+    let scene = SceneFile::from_toml(ENVIRONMENT).unwrap();
+    let (_, cfg) = scene.build(Path::new("assets/scenes")).unwrap();
+    assert!(cfg.environment.is_some());
+    let text = scene.to_toml().unwrap();
+    assert_eq!(scene, SceneFile::from_toml(&text).unwrap());
+  }
+
+  #[test]
+  fn build_sky_with_sun() {
+    let scene = SceneFile::from_toml(SKY).unwrap();
+    let (_, cfg) = scene.build(Path::new("assets/scenes")).unwrap();
+    assert!(cfg.environment.is_some());
+    assert_eq!(cfg.lights.len(), 1);
+    let text = scene.to_toml().unwrap();
+    assert_eq!(scene, SceneFile::from_toml(&text).unwrap());
+  }
+
+  #[test]
+  fn sky_with_environment() {
+    let mut scene = SceneFile::from_toml(SKY).unwrap();
+    scene.environment = SceneFile::from_toml(ENVIRONMENT).unwrap().environment;
+    match scene.build(Path::new("assets/scenes")) {
+      Err(SceneFileError::SkyWithEnvironment) => {}
+      _ => panic!("Expected sky with environment error"),
+    }
+  }
+
+  #[test]
+  fn build_advanced_materials() {
+    for text in [
+      METALS,
+      FROSTED_GLASS,
+      PRINCIPLED,
+      ROUGH_DIFFUSE,
+      LAYERED,
+      THIN_FILM,
+      NORMAL_MAPS,
+    ] {
+      let scene = SceneFile::from_toml(text).unwrap();
+      let (world, _) = scene.build(Path::new("assets/scenes")).unwrap();
+      assert_eq!(world.objects.len(), scene.objects.len());
+      let text = scene.to_toml().unwrap();
+      assert_eq!(scene, SceneFile::from_toml(&text).unwrap());
+    }
+  }
+
+  #[test]
+  fn unknown_metal() {
+    let scene = SceneFile::from_toml(
+      r#"
+      [materials.unobtainium]
+      type = "conductor"
+      metal = "unobtainium"
+      roughness = 0.1
+      "#,
+    )
+    .unwrap();
+    match scene.build(Path::new(".")) {
+      Err(SceneFileError::InvalidMaterial(msg)) => assert!(msg.contains("unobtainium")),
+      _ => panic!("Expected invalid material error"),
+    }
+  }
+
+  #[test]
+  fn material_contains_itself() {
+    let scene = SceneFile::from_toml(
+      r#"
+      [materials.paint]
+      type = "coated"
+      base = "dirty_paint"
+      ior = 1.5
+
+      [materials.dirty_paint]
+      type = "mix"
+      material1 = "paint"
+      material2 = "paint"
+      weight = 0.5
+      "#,
+    )
+    .unwrap();
+    match scene.build(Path::new(".")) {
+      Err(SceneFileError::InvalidMaterial(msg)) => assert!(msg.contains("contains itself")),
+      _ => panic!("Expected invalid material error"),
+    }
+  }
+
+  #[test]
+  fn missing_settings_use_defaults() {
+    let scene = SceneFile::from_toml("").unwrap();
+    assert_eq!(scene.settings, SettingsDef::default());
+    assert!(scene.objects.is_empty());
+  }
+
+  #[test]
+  fn unknown_object_key() {
+    let sphere = "type = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nmaterial = \"red\"";
+    let ok = format!("[[objects]]\n{}\nradius = 2.0\n", sphere);
+    assert!(SceneFile::from_toml(&ok).is_ok());
+    for typo in ["radus = 2.0", "transfrom = { translation = [1.0, 0.0, 0.0] }"] {
+      let text = format!("[[objects]]\n{}\nradius = 2.0\n{}\n", sphere, typo);
+      match SceneFile::from_toml(&text) {
+        Err(SceneFileError::Parse(err)) => assert!(err.to_string().contains("unknown field")),
+        _ => panic!("Expected parse error for '{}'", typo),
+      }
+    }
+    // nested object of the volume is checked too
+    let text = "[[objects]]\ntype = \"volumetric\"\ndensity = 1.0\nalbedo = [1.0, 1.0, 1.0]\n\
+      shape = { type = \"box\", dims = [1.0, 1.0, 1.0], material = \"red\", size = 2.0 }\n";
+    assert!(SceneFile::from_toml(text).is_err());
+  }
+
+  #[test]
+  fn unknown_material() {
+    let scene = SceneFile::from_toml(
+      r#"
+      [[objects]]
+      type = "sphere"
+      center = [0.0, 0.0, 0.0]
+      radius = 1.0
+      material = "does_not_exist"
+      "#,
+    )
+    .unwrap();
+    match scene.build(Path::new(".")) {
+      Err(SceneFileError::UnknownMaterial(name)) => assert_eq!(name, "does_not_exist"),
+      _ => panic!("Expected unknown material error"),
+    }
+  }
+}
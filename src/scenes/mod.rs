@@ -14,10 +14,12 @@ pub mod scene5;
 pub mod scene6;
 pub mod scene7;
 pub mod scene8;
+pub mod scene9;
+pub mod scene_registry;
 pub mod scene_settings;
 
 pub fn add_debug_spheres(aabb: Option<AABB>, world: &mut World) {
-  aabb.map(|a| {
+  if let Some(a) = aabb {
     println!("-- Dbg Spheres: {:?}", a);
 
     for &p in &a.to_points() {
@@ -32,5 +34,5 @@ pub fn add_debug_spheres(aabb: Option<AABB>, world: &mut World) {
       let s1 = Sphere::new(p, 0.04, mat_metal_red);
       world.add(Arc::new(s1));
     }
-  });
+  }
 }
@@ -5,9 +5,16 @@ use crate::sphere::Sphere;
 use crate::vec3::{Color, Point3d, Vec3};
 use crate::world::World;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene1",
+  description: "Camera aperture test.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(3.0, 3.0, 2.0),
@@ -17,17 +24,19 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
   let mat_1 = Arc::new(Lambert::color(0.3, 0.3, 0.7));
   let mat_ground = Arc::new(Lambert::from_color(Color::uni(0.3)));
   let mat_metal = Arc::new(Metal {
     albedo: Vec3::uni(0.8),
     roughness: 0.2,
+    film: None,
   });
   let mat_glass = Arc::new(Dielectric {
     ior: 1.5,
     albedo: Color::one(),
+    absorption: Color::zero(),
+    film: None,
   });
 
   //
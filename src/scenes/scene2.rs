@@ -1,14 +1,21 @@
-use log::info;
 use std::sync::Arc;
 
 use crate::material::{Dielectric, Lambert, Metal, SolidColor};
+use crate::plane::Plane;
 use crate::sphere::Sphere;
 use crate::vec3::{Point3d, Vec3};
 use crate::world::World;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene2",
+  description: "Materials test. Metal, glass, lambert diffuse etc.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(0.0, 2.0, 5.0),
@@ -17,10 +24,7 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  info!("Scene2 is materials test. Metal, glass, lambert diffuse etc.");
-
   let mat_ground = Arc::new(Lambert::color(0.15, 0.3, 0.15)); // DO NOT USE SOLID COLOR HERE!
 
   let mat_grey = Arc::new(SolidColor { color: Vec3::one() });
@@ -28,36 +32,36 @@ pub fn load_scene(world: &mut World) {
   let mat_metal_black = Arc::new(Metal {
     albedo: Vec3::uni(0.2),
     roughness: 0.1,
+    film: None,
   });
   let mat_metal_silver = Arc::new(Metal {
     albedo: Vec3::uni(0.9),
     roughness: 0.0,
+    film: None,
   });
   let mat_metal_red = Arc::new(Metal {
     albedo: Vec3::new(0.7, 0.3, 0.3),
     roughness: 0.5,
+    film: None,
   });
-  let (ior, glass_dark, glass_light) = (1.3, 0.5, 0.7);
-  let mat_glass_red = Arc::new(Dielectric {
-    albedo: Vec3::new(glass_light, glass_dark, glass_dark),
-    ior,
-  });
-  let mat_glass_green = Arc::new(Dielectric {
-    albedo: Vec3::new(glass_dark, glass_light, glass_dark),
-    ior,
-  });
-  let mat_glass_blue = Arc::new(Dielectric {
-    albedo: Vec3::new(glass_dark, glass_dark, glass_light),
-    ior,
-  });
-  let mat_glass_teal = Arc::new(Dielectric {
-    albedo: Vec3::new(glass_dark, glass_light, glass_light),
-    ior,
-  });
+  // colored by absorption, so the edges of the balls are lighter than the center
+  let (ior, absorb_strong, absorb_weak) = (1.3, 2.5, 0.3);
+  let glass = |absorption: Vec3| {
+    Arc::new(Dielectric {
+      albedo: Vec3::one(),
+      ior,
+      absorption,
+      film: None,
+    })
+  };
+  let mat_glass_red = glass(Vec3::new(absorb_weak, absorb_strong, absorb_strong));
+  let mat_glass_green = glass(Vec3::new(absorb_strong, absorb_weak, absorb_strong));
+  let mat_glass_blue = glass(Vec3::new(absorb_strong, absorb_strong, absorb_weak));
+  let mat_glass_teal = glass(Vec3::new(absorb_strong, absorb_weak, absorb_weak));
 
   // ground
-  let s_ground = Sphere::new(Point3d::new(0.0, -1000.45, -1.2), 1000.0, mat_ground);
-  world.add(Arc::new(s_ground));
+  let ground = Plane::new(Point3d::new(0.0, -0.45, 0.0), Vec3::up(), mat_ground);
+  world.add(Arc::new(ground));
 
   // big balls
   let big_radius = 0.9;
@@ -1,14 +1,21 @@
-use log::warn;
 use std::sync::Arc;
 
 use crate::material::{Lambert, Metal};
+use crate::plane::Plane;
 use crate::sphere::Sphere;
 use crate::vec3::{Color, Point3d, Vec3};
 use crate::world::World;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene3",
+  description: "BVH test. e.g. on low settings it's 1min with BVH, and I gave up after 1h without",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(0.0, 2.0, 5.0),
@@ -17,17 +24,12 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  warn!(
-    "Scene3 is BVH test. e.g. on low settings it's 1min with BVH, and I gave up after 1h without"
-  );
-
   let mat_ground = Arc::new(Lambert::color(0.15, 0.3, 0.15)); // DO NOT USE SOLID COLOR HERE!
 
   // ground
-  let s_ground = Sphere::new(Point3d::new(0.0, -1000.45, -1.2), 1000.0, mat_ground);
-  world.add(Arc::new(s_ground));
+  let ground = Plane::new(Point3d::new(0.0, -0.45, 0.0), Vec3::up(), mat_ground);
+  world.add(Arc::new(ground));
 
   let cnt = 100;
   let radius = 0.3;
@@ -47,6 +49,7 @@ pub fn load_scene(world: &mut World) {
           rand::random::<f32>(),
         ),
         roughness: 0.05,
+        film: None,
       });
       let s1 = Sphere::new(p, radius, mat_metal_red);
       world.add(Arc::new(s1));
@@ -1,4 +1,3 @@
-use log::info;
 use noise::Perlin;
 use std::path::Path;
 use std::sync::Arc;
@@ -9,9 +8,16 @@ use crate::texture::{CheckerTex, ImageTex, NoiseTex, UVDebugTex};
 use crate::vec3::{Color, Point3d};
 use crate::world::World;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene4",
+  description: "Textures test.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(0.0, 2.0, 5.0),
@@ -20,10 +26,7 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  info!("Scene4 is textures test");
-
   // ground
   let ground_tex = CheckerTex {
     color1: Color::uni(0.3),
@@ -48,7 +51,7 @@ pub fn load_scene(world: &mut World) {
 
   // sphere 3 - noise
   let tex = NoiseTex {
-    noise: Perlin::new(),
+    noise: Perlin::default(),
     scale: 10.0,
   };
   let mat_tex = Arc::new(Lambert::texture(Arc::new(tex)));
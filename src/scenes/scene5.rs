@@ -1,6 +1,6 @@
-use log::info;
 use std::sync::Arc;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 use crate::light::DiffuseLight;
 use crate::material::{Dielectric, Lambert};
@@ -9,7 +9,13 @@ use crate::sphere::Sphere;
 use crate::vec3::{Color, Point3d, Vec3};
 use crate::world::World;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene5",
+  description: "Lights test.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(5.0, 2.0, 0.0),
@@ -18,10 +24,7 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  info!("Scene5 is lights test.");
-
   // ground
   let gray = 0.05;
   let mat_ground = Arc::new(Lambert::color(gray, gray, gray));
@@ -37,6 +40,8 @@ pub fn load_scene(world: &mut World) {
   let mat_glass_teal = Arc::new(Dielectric {
     albedo: Vec3::new(0.5, 0.7, 0.7),
     ior: 1.3,
+    absorption: Vec3::zero(),
+    film: None,
   });
   let sphere = Sphere::new(Vec3::new(1.5, 0.45, 0.5), 0.5, mat_glass_teal);
   world.add(Arc::new(sphere));
@@ -1,9 +1,9 @@
 use glam::f32::Mat3;
 use glam::f32::Vec3 as GVec3;
-use log::info;
 use std::path::Path;
 use std::sync::Arc;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 use crate::material::Lambert;
 use crate::rectangle::Rectangle;
@@ -13,7 +13,13 @@ use crate::transform::Transform;
 use crate::vec3::{Color, Point3d};
 use crate::world::World;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene6",
+  description: "Transformation test.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     camera_position: Point3d::new(5.0, 5.0, 5.0),
@@ -22,10 +28,7 @@ pub fn settings() -> SceneSettings {
   }
 }
 
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  info!("Scene6 is transformation test.");
-
   let size = 2.0;
   let dgr: f32 = 45.0;
 
@@ -1,5 +1,4 @@
 use glam::f32::Vec3 as gVec3;
-use log::info;
 use std::sync::Arc;
 
 use crate::box_prim::BoxPrim;
@@ -11,9 +10,16 @@ use crate::transform::Transform;
 use crate::vec3::{Color, Point3d};
 use crate::world::World;
 
+use super::scene_registry::SceneDef;
 use super::scene_settings::SceneSettings;
 
-#[allow(dead_code)]
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene8",
+  description: "Transforms playground.",
+  settings,
+  load_scene,
+};
+
 pub fn settings() -> SceneSettings {
   SceneSettings {
     // camera_position: Point3d::new(0.0, 0.0, 3.0),
@@ -25,10 +31,7 @@ pub fn settings() -> SceneSettings {
     ..Default::default()
   }
 }
-#[allow(dead_code)]
 pub fn load_scene(world: &mut World) {
-  info!("Scene8 is transforms playground.");
-
   let size = 1.0;
   let rad = |r: f32| r.to_radians();
 
@@ -0,0 +1,99 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::material::{Lambert, Metal};
+use crate::sphere::Sphere;
+use crate::texture::ImageTex;
+use crate::triangle::Triangle;
+use crate::triangle_mesh::{MeshData, TriangleMesh};
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::World;
+
+use super::scene_registry::SceneDef;
+use super::scene_settings::SceneSettings;
+
+pub const SCENE: SceneDef = SceneDef {
+  name: "scene9",
+  description: "Triangle meshes test. Smooth/flat normals, UVs and a lone triangle.",
+  settings,
+  load_scene,
+};
+
+pub fn settings() -> SceneSettings {
+  SceneSettings {
+    camera_position: Point3d::new(0.0, 2.0, 5.0),
+    camera_target: Point3d::new(0.0, 0.1, 0.0),
+    ..Default::default()
+  }
+}
+
+/** Sphere as a triangle mesh, so that it can be compared with the analytic one */
+fn uv_sphere(center: Point3d, radius: f32, segments: u32, rings: u32, smooth: bool) -> MeshData {
+  let mut mesh = MeshData::default();
+
+  for ring in 0..=rings {
+    let v = ring as f32 / rings as f32;
+    let theta = v * PI;
+    for segment in 0..=segments {
+      let u = segment as f32 / segments as f32;
+      let phi = u * 2.0 * PI;
+      let n = Vec3::new(
+        -phi.cos() * theta.sin(),
+        -theta.cos(),
+        phi.sin() * theta.sin(),
+      );
+      mesh.positions.push(center + n * radius);
+      mesh.uvs.push((u, v));
+      if smooth {
+        mesh.normals.push(n);
+      }
+    }
+  }
+
+  let row = segments + 1;
+  for ring in 0..rings {
+    for segment in 0..segments {
+      let i0 = ring * row + segment;
+      let i1 = i0 + 1;
+      let i2 = i0 + row;
+      let i3 = i2 + 1;
+      mesh.indices.push([i0, i1, i3]);
+      mesh.indices.push([i0, i3, i2]);
+    }
+  }
+
+  mesh
+}
+
+pub fn load_scene(world: &mut World) {
+  // ground
+  let mat_ground = Arc::new(Lambert::color(0.15, 0.3, 0.15));
+  let s_ground = Sphere::new(Point3d::new(0.0, -1000.45, -1.2), 1000.0, mat_ground);
+  world.add(Arc::new(s_ground));
+
+  // smooth, textured
+  let tex = ImageTex::new(Path::new("assets/test_texture.png"));
+  let mat_tex = Arc::new(Lambert::texture(Arc::new(tex)));
+  let mesh = uv_sphere(Point3d::new(0.0, 0.45, 0.0), 0.9, 32, 16, true);
+  world.add(Arc::new(TriangleMesh::new(mesh, mat_tex)));
+
+  // flat shaded, low poly
+  let mat_metal = Arc::new(Metal {
+    albedo: Color::uni(0.8),
+    roughness: 0.05,
+    film: None,
+  });
+  let mesh = uv_sphere(Point3d::new(2.0, 0.45, 0.0), 0.9, 12, 6, false);
+  world.add(Arc::new(TriangleMesh::new(mesh, mat_metal)));
+
+  // lone triangle
+  let mat_red = Arc::new(Lambert::color(0.7, 0.2, 0.2));
+  let tri = Triangle::new(
+    Point3d::new(-3.0, -0.45, -0.5),
+    Point3d::new(-1.2, -0.45, 0.0),
+    Point3d::new(-2.0, 1.3, -0.3),
+    mat_red,
+  );
+  world.add(Arc::new(tri));
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+use crate::world::World;
+
+use super::scene_settings::SceneSettings;
+use super::{scene1, scene2, scene3, scene4, scene5, scene6, scene7, scene8, scene9};
+
+/**
+Built-in scene. Each scene module exposes one as `SCENE` const.
+To add new scene, just append it to `SCENES`.
+*/
+#[derive(Clone, Copy)]
+pub struct SceneDef {
+  /** Unique name used to select scene e.g. from command line */
+  pub name: &'static str,
+  /** Short summary of what the scene is testing */
+  pub description: &'static str,
+  /** Camera and render settings the scene was designed for */
+  pub settings: fn() -> SceneSettings,
+  /** Add all objects of the scene to the world */
+  pub load_scene: fn(&mut World),
+}
+
+impl SceneDef {
+  /** Create world with all the scene's objects */
+  pub fn load(&self) -> (World, SceneSettings) {
+    let mut world = World::new();
+    (self.load_scene)(&mut world);
+    (world, (self.settings)())
+  }
+}
+
+impl fmt::Debug for SceneDef {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SceneDef")
+      .field("name", &self.name)
+      .field("description", &self.description)
+      .finish()
+  }
+}
+
+/** All built-in scenes */
+pub static SCENES: [SceneDef; 9] = [
+  scene1::SCENE,
+  scene2::SCENE,
+  scene3::SCENE,
+  scene4::SCENE,
+  scene5::SCENE,
+  scene6::SCENE,
+  scene7::SCENE,
+  scene8::SCENE,
+  scene9::SCENE,
+];
+
+/** Find built-in scene by its name */
+pub fn find_scene(name: &str) -> Option<&'static SceneDef> {
+  SCENES.iter().find(|s| s.name == name)
+}
+
+/** Names of all built-in scenes */
+pub fn scene_names() -> Vec<&'static str> {
+  SCENES.iter().map(|s| s.name).collect()
+}
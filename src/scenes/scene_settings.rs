@@ -1,3 +1,7 @@
+use std::sync::Arc;
+
+use crate::environment::EnvironmentMap;
+use crate::light::PunctualLight;
 use crate::vec3::{Color, Point3d};
 
 #[derive(Debug)]
@@ -6,9 +10,14 @@ pub struct SceneSettings {
   pub camera_target: Point3d,
   pub camera_aperture: f32,
   pub camera_fov: f32,
+  /** Color of rays that did not hit anything. Ignored if `environment` is set */
   pub background: Color,
+  /** Light coming from far away in every direction, e.g. HDR photo of the sky */
+  pub environment: Option<Arc<EnvironmentMap>>,
   pub samples_per_pixel: usize,
   pub max_bounces: i32,
+  /** Lights without geometry. Objects with emissive materials are found automatically */
+  pub lights: Vec<Arc<dyn PunctualLight>>,
 }
 
 impl Default for SceneSettings {
@@ -19,8 +28,10 @@ impl Default for SceneSettings {
       camera_aperture: 0.0,
       camera_fov: 40.0,
       background: Color::one(),
+      environment: None,
       samples_per_pixel: 250,
       max_bounces: 20,
+      lights: Vec::new(),
     }
   }
 }
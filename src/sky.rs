@@ -0,0 +1,290 @@
+use std::f32::consts::PI;
+
+use crate::environment::EnvironmentMap;
+use crate::light::DirectionalLight;
+use crate::utils::RGB_WAVELENGTHS_NM;
+use crate::vec3::{Color, Vec3};
+
+// Analytic daylight sky from "A Practical Analytic Model for Daylight"
+// (Preetham, Shirley, Smits 1999). Luminance and chromaticity of the sky are
+// Perez functions of the view angle and the angle to the sun, fitted for turbidity.
+// Sun itself is not part of the sky, it is returned as separate `DirectionalLight`.
+//
+// https://www2.cs.utah.edu/~shirley/papers/sunsky/sunsky.pdf
+
+/**
+Sky luminance from the paper is in kcd/m^2. Divide it so that surface
+lit by the noon sun has brightness close to 1.
+*/
+const LUMINANCE_SCALE: f32 = 1.0 / 30.0;
+/** Illuminance of the sun above the atmosphere, in klx */
+const SOLAR_ILLUMINANCE: f32 = 128.0;
+
+#[derive(Clone, Debug)]
+pub struct Sky {
+  /** Towards the sun. Unit vector */
+  pub sun_direction: Vec3,
+  /** Haziness of the air. 2 is very clear, 10 is hazy. Model is fitted for 2-10 */
+  pub turbidity: f32,
+  /** Color of the ground below the horizon, lit by the sun and the sky */
+  pub ground_albedo: Color,
+  /** Multiplier for both the sky and the sun */
+  pub intensity: f32,
+  /** Sun's size for soft shadows, in radians */
+  pub sun_angular_radius: f32,
+  zenith: [f32; 3],
+  perez: [[f32; 5]; 3],
+  ground: Color,
+}
+
+impl Sky {
+  pub fn new(
+    sun_direction: Vec3,
+    turbidity: f32,
+    ground_albedo: Color,
+    intensity: f32,
+    sun_angular_radius: f32,
+  ) -> Self {
+    let sun_direction = sun_direction.unit_vector();
+    let t = turbidity.clamp(1.0, 20.0);
+    // the fit is only for the sun above the horizon
+    let theta_s = sun_direction.y().clamp(0.0, 1.0).acos();
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+    let zenith_x = zenith_chromaticity(
+      t,
+      theta_s,
+      [
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+      ],
+    );
+    let zenith_y = zenith_chromaticity(
+      t,
+      theta_s,
+      [
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+      ],
+    );
+
+    let perez = [
+      [
+        0.1787 * t - 1.4630,
+        -0.3554 * t + 0.4275,
+        -0.0227 * t + 5.3251,
+        0.1206 * t - 2.5771,
+        -0.0670 * t + 0.3703,
+      ],
+      [
+        -0.0193 * t - 0.2592,
+        -0.0665 * t + 0.0008,
+        -0.0004 * t + 0.2125,
+        -0.0641 * t - 0.8989,
+        -0.0033 * t + 0.0452,
+      ],
+      [
+        -0.0167 * t - 0.2608,
+        -0.0950 * t + 0.0092,
+        -0.0079 * t + 0.2102,
+        -0.0441 * t - 1.6537,
+        -0.0109 * t + 0.0529,
+      ],
+    ];
+
+    let mut sky = Self {
+      sun_direction,
+      turbidity: t,
+      ground_albedo,
+      intensity,
+      sun_angular_radius,
+      zenith: [zenith_luminance, zenith_x, zenith_y],
+      perez,
+      ground: Color::zero(),
+    };
+    sky.ground = sky.ground_radiance();
+    sky
+  }
+
+  /** Light arriving from direction `dir`. Does not include the sun */
+  pub fn radiance(&self, dir: Vec3) -> Color {
+    let d = dir.unit_vector();
+    if d.y() < 0.0 {
+      return self.ground * self.intensity;
+    }
+    self.sky_radiance(d) * self.intensity
+  }
+
+  /** Sunlight after passing through the atmosphere. `None` after sunset */
+  pub fn sun(&self) -> Option<DirectionalLight> {
+    let transmittance = self.sun_transmittance();
+    if transmittance.is_zero() {
+      return None;
+    }
+    Some(DirectionalLight::new(
+      !self.sun_direction,
+      transmittance,
+      SOLAR_ILLUMINANCE * LUMINANCE_SCALE * self.intensity,
+      self.sun_angular_radius,
+    ))
+  }
+
+  /** Bake the sky into equirectangular image, so it can be importance sampled */
+  pub fn to_environment_map(&self, width: usize, height: usize) -> EnvironmentMap {
+    EnvironmentMap::from_fn(width, height, |dir| self.radiance(dir))
+  }
+
+  fn sky_radiance(&self, d: Vec3) -> Color {
+    let cos_theta = d.y().max(0.001);
+    let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+    let theta_s = self.sun_direction.y().clamp(0.0, 1.0).acos();
+
+    let mut xyy = [0.0; 3];
+    for (i, value) in xyy.iter_mut().enumerate() {
+      let coeffs = &self.perez[i];
+      *value = self.zenith[i] * perez(coeffs, cos_theta, gamma) / perez(coeffs, 1.0, theta_s);
+    }
+    xyy_to_rgb(xyy[1], xyy[2], xyy[0] * LUMINANCE_SCALE)
+  }
+
+  /** Lambertian ground lit by the sun and the sky above it */
+  fn ground_radiance(&self) -> Color {
+    // integrate sky over the upper hemisphere, weighted by cosine
+    let (n_theta, n_phi) = (16, 32);
+    let mut sky_irradiance = Color::zero();
+    for i in 0..n_theta {
+      let theta = (i as f32 + 0.5) / n_theta as f32 * PI / 2.0;
+      for j in 0..n_phi {
+        let phi = (j as f32 + 0.5) / n_phi as f32 * 2.0 * PI;
+        let dir = Vec3::new(
+          theta.sin() * phi.cos(),
+          theta.cos(),
+          theta.sin() * phi.sin(),
+        );
+        let solid_angle = theta.sin() * (PI / 2.0 / n_theta as f32) * (2.0 * PI / n_phi as f32);
+        sky_irradiance = sky_irradiance + self.sky_radiance(dir) * (theta.cos() * solid_angle);
+      }
+    }
+    let sun_irradiance = self.sun_transmittance()
+      * (SOLAR_ILLUMINANCE * LUMINANCE_SCALE * self.sun_direction.y().max(0.0));
+    self.ground_albedo * (sky_irradiance + sun_irradiance) / PI
+  }
+
+  /**
+  How much of the sunlight gets through Rayleigh scattering and aerosols (appendix
+  of the paper). Ozone and water vapor absorption are ignored.
+  */
+  fn sun_transmittance(&self) -> Color {
+    let cos_theta_s = self.sun_direction.y();
+    if cos_theta_s <= 0.0 {
+      return Color::zero();
+    }
+    let theta_deg = cos_theta_s.acos().to_degrees();
+    // relative optical mass, how much air is between us and the sun
+    let m = 1.0 / (cos_theta_s + 0.15 * (93.885 - theta_deg).powf(-1.253));
+    let alpha = 1.3;
+    let beta = 0.04608 * self.turbidity - 0.04586;
+    let t = RGB_WAVELENGTHS_NM.map(|nm| {
+      let lambda = nm / 1000.0; // in micrometers
+      let rayleigh = (-0.008735 * lambda.powf(-4.08) * m).exp();
+      let aerosol = (-beta * lambda.powf(-alpha) * m).exp();
+      rayleigh * aerosol
+    });
+    Color::new(t[0], t[1], t[2])
+  }
+}
+
+fn zenith_chromaticity(t: f32, theta_s: f32, m: [[f32; 4]; 3]) -> f32 {
+  let th = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+  let row = |r: [f32; 4]| r.iter().zip(th.iter()).map(|(a, b)| a * b).sum::<f32>();
+  t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+}
+
+/** Perez distribution, `theta` is the angle from zenith, `gamma` from the sun */
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+  let cos_gamma = gamma.cos();
+  (1.0 + c[0] * (c[1] / cos_theta).exp())
+    * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+/** CIE xyY into linear sRGB */
+fn xyy_to_rgb(x: f32, y: f32, lum: f32) -> Color {
+  if y <= 0.0 {
+    return Color::zero();
+  }
+  let cx = x / y * lum;
+  let cz = (1.0 - x - y) / y * lum;
+  Color::new(
+    (3.2406 * cx - 1.5372 * lum - 0.4986 * cz).max(0.0),
+    (-0.9689 * cx + 1.8758 * lum + 0.0415 * cz).max(0.0),
+    (0.0557 * cx - 0.2040 * lum + 1.0570 * cz).max(0.0),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::light::PunctualLight;
+  use crate::utils::luminance;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn sun_at(elevation_deg: f32) -> Vec3 {
+    let e = elevation_deg.to_radians();
+    Vec3::new(0.0, e.sin(), -e.cos())
+  }
+
+  fn sky_at(elevation_deg: f32) -> Sky {
+    Sky::new(sun_at(elevation_deg), 3.0, Color::uni(0.3), 1.0, 0.0)
+  }
+
+  #[test]
+  fn zenith_matches_zenith_luminance() {
+    let sky = sky_at(40.0);
+    let zenith = luminance(sky.radiance(Vec3::up()));
+    assert_approx_eq!(zenith, sky.zenith[0] * LUMINANCE_SCALE, zenith * 0.02);
+  }
+
+  #[test]
+  fn brighter_towards_the_sun() {
+    let sky = sky_at(30.0);
+    let towards = sky.radiance(sun_at(40.0));
+    let away = sky.radiance(Vec3::new(
+      0.0,
+      40f32.to_radians().sin(),
+      40f32.to_radians().cos(),
+    ));
+    assert!(luminance(towards) > 2.0 * luminance(away));
+    // clear sky away from the sun is blue
+    assert!(away.z() > away.x());
+  }
+
+  #[test]
+  fn sun_is_redder_near_horizon() {
+    let noon = sky_at(80.0).sun().unwrap();
+    let evening = sky_at(5.0).sun().unwrap();
+    assert!(evening.color.x() / evening.color.z() > noon.color.x() / noon.color.z());
+    assert!(noon.color.y() > evening.color.y());
+    let sample = noon.sample(Vec3::zero()).unwrap();
+    assert_approx_eq!(sample.wi.dot(sun_at(80.0)), 1.0, 1e-4);
+  }
+
+  #[test]
+  fn no_sun_after_sunset() {
+    assert!(sky_at(-5.0).sun().is_none());
+  }
+
+  #[test]
+  fn ground_uses_albedo() {
+    let dark = Sky::new(sun_at(45.0), 3.0, Color::uni(0.1), 1.0, 0.0);
+    let bright = Sky::new(sun_at(45.0), 3.0, Color::uni(0.4), 1.0, 0.0);
+    let down = Vec3::new(0.0, -1.0, 0.0);
+    assert_approx_eq!(
+      bright.radiance(down).y(),
+      4.0 * dark.radiance(down).y(),
+      1e-4
+    );
+  }
+}
@@ -4,6 +4,7 @@ use crate::aabb::AABB;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::traceable::{RayHit, Traceable};
+use crate::utils::random_in_cone;
 use crate::vec3::{Point3d, Vec3};
 
 #[derive(Clone, Debug)]
@@ -29,6 +30,39 @@ impl Sphere {
 
     (phi / (2.0 * pi), theta / pi)
   }
+
+  /** Derivatives of the point wrt. `u` and `v` of `get_sphere_uv`, for the outward `normal` */
+  pub fn get_sphere_tangents(&self, normal: &Vec3) -> (Vec3, Vec3) {
+    let pi = std::f32::consts::PI;
+    // radius of the circle of latitude, 0 at the poles
+    let ring = (normal.x() * normal.x() + normal.z() * normal.z()).sqrt();
+    if ring < 1e-6 {
+      return (
+        Vec3::right() * (2.0 * pi * self.radius),
+        Vec3::forward() * (pi * self.radius),
+      );
+    }
+    let along_ring = Vec3::new(normal.z(), 0.0, -normal.x());
+    let dpdu = along_ring * (2.0 * pi * self.radius);
+    let dpdv = normal.cross(along_ring) * (pi * self.radius / ring);
+    (dpdu, dpdv)
+  }
+
+  /**
+  Density of `sample_direction`. Uniform over the cone that contains the sphere,
+  or over all directions if `origin` is inside.
+  */
+  fn cone_pdf(&self, origin: Point3d) -> f32 {
+    let pi = std::f32::consts::PI;
+    let dist_sq = (self.center - origin).length_squared();
+    let radius_sq = self.radius * self.radius;
+    if dist_sq <= radius_sq {
+      1.0 / (4.0 * pi)
+    } else {
+      let cos_theta_max = (1.0 - radius_sq / dist_sq).sqrt();
+      1.0 / (2.0 * pi * (1.0 - cos_theta_max))
+    }
+  }
 }
 
 impl Traceable for Sphere {
@@ -79,14 +113,61 @@ impl Traceable for Sphere {
     let normal = (hit_point - self.center).unit_vector();
     let (front_face, outward_normal) = RayHit::check_is_front_face(r, normal);
     let (u, v) = Sphere::get_sphere_uv(&outward_normal);
+    let (tangent, bitangent) = self.get_sphere_tangents(&normal);
     Some(RayHit {
       p: hit_point,
       t: root,
       u,
       v,
       normal: outward_normal,
+      geometric_normal: outward_normal,
+      tangent,
+      bitangent,
       front_face,
+      vertex_color: None,
       material: self.material.clone(),
     })
   }
+
+  fn is_light(&self) -> bool {
+    self.material.is_emissive()
+  }
+
+  /**
+  Sample directions inside the cone that the sphere occupies when seen from `origin`.
+  Every sampled point is visible. If `origin` is inside, sample all directions.
+
+  https://raytracing.github.io/books/RayTracingTheRestOfYourLife.html#lightscattering/samplingasphereobject
+  */
+  fn sample_direction(&self, origin: Point3d) -> Option<(Vec3, f32)> {
+    let to_center = self.center - origin;
+    let dist_sq = to_center.length_squared();
+    let radius_sq = self.radius * self.radius;
+
+    let dir = if dist_sq <= radius_sq {
+      Vec3::rand_unit()
+    } else {
+      let cos_theta_max = (1.0 - radius_sq / dist_sq).sqrt();
+      random_in_cone(to_center, cos_theta_max)
+    };
+
+    // distance to the first intersection along `dir`. Clamped, as directions
+    // at the edge of the cone can numerically miss the sphere
+    let proj = to_center.dot(dir);
+    let closest_sq = (dist_sq - proj * proj).min(radius_sq);
+    let half_chord = (radius_sq - closest_sq).sqrt();
+    let t = if dist_sq <= radius_sq {
+      proj + half_chord
+    } else {
+      proj - half_chord
+    };
+    Some((dir * t, self.cone_pdf(origin)))
+  }
+
+  fn direction_pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    match self.check_intersection(r, t_min, t_max) {
+      Some(_) => self.cone_pdf(r.origin),
+      None => 0.0,
+    }
+  }
 }
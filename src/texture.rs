@@ -1,4 +1,5 @@
 use noise::{NoiseFn, Perlin};
+use std::sync::Arc;
 
 use crate::traceable::RayHit;
 use crate::vec3::Color;
@@ -47,6 +48,57 @@ impl Texture for UVDebugTex {
   }
 }
 
+///////////////////////
+// Vertex color
+#[derive(Clone, Debug)]
+/**
+Per-vertex colors of the mesh, interpolated over the triangle.
+Objects without vertex colors use `fallback`.
+*/
+pub struct VertexColorTex {
+  pub fallback: Color,
+}
+
+impl Texture for VertexColorTex {
+  fn sample(&self, hit: &RayHit) -> Color {
+    hit.vertex_color.unwrap_or(self.fallback)
+  }
+}
+
+///////////////////////
+// Scaled
+#[derive(Clone, Debug)]
+/** Other texture multiplied by a color, e.g. glTF's `factor * texture` */
+pub struct ScaledTex {
+  pub tex: Arc<dyn Texture>,
+  pub scale: Color,
+}
+
+impl Texture for ScaledTex {
+  fn sample(&self, hit: &RayHit) -> Color {
+    self.tex.sample(hit) * self.scale
+  }
+}
+
+///////////////////////
+// Channel
+#[derive(Clone, Debug)]
+/**
+Single channel of other texture as grayscale, e.g. roughness packed into green.
+Materials read scalar parameters from the red channel.
+*/
+pub struct ChannelTex {
+  pub tex: Arc<dyn Texture>,
+  /** 0 is red, 1 is green, 2 is blue */
+  pub channel: usize,
+}
+
+impl Texture for ChannelTex {
+  fn sample(&self, hit: &RayHit) -> Color {
+    Color::uni(self.tex.sample(hit)[self.channel])
+  }
+}
+
 ///////////////////////
 // Checker
 #[derive(Clone, Debug)]
@@ -106,10 +158,20 @@ pub struct ImageTex {
 
 impl ImageTex {
   pub fn new(path: &Path) -> ImageTex {
-    let image = ImageReader::open(path).unwrap();
-    ImageTex {
-      image: image.decode().unwrap().to_rgba8(),
-    }
+    ImageTex::load(path).unwrap()
+  }
+
+  /** Image that is already in memory e.g. embedded in glTF file */
+  pub fn from_image(image: image::RgbaImage) -> ImageTex {
+    ImageTex { image }
+  }
+
+  /** Same as `new`, but returns error instead of panicking e.g. if file does not exist */
+  pub fn load(path: &Path) -> Result<ImageTex, image::ImageError> {
+    let image = ImageReader::open(path)?.decode()?;
+    Ok(ImageTex {
+      image: image.to_rgba8(),
+    })
   }
 }
 
@@ -3,15 +3,29 @@ use std::sync::Arc;
 use crate::aabb::AABB;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::{Point3d, Vec3};
+use crate::utils::Onb;
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::WorldObjectsList;
+
+/** How far from the surface `RayHit::spawn_ray` starts the new ray */
+const RAY_OFFSET: f32 = 1e-4;
 
 #[derive(Clone, Debug)]
 /** Result of Ray hitting a Traceable */
 pub struct RayHit {
   /** Point of hit */
   pub p: Point3d,
-  /** Normal at the place of hit. Can point into shape */
+  /**
+  Shading normal at the place of hit, used by materials. Can point into shape. Faces
+  the ray, but normal/bump maps or interpolated mesh normals may tilt it.
+  */
   pub normal: Vec3,
+  /** Normal of the actual surface, on the same side as `normal`. See `spawn_ray` */
+  pub geometric_normal: Vec3,
+  /** Direction in which `u` grows (dp/du), not normalized. Does not flip with `normal` */
+  pub tangent: Vec3,
+  /** Direction in which `v` grows (dp/dv), not normalized. Does not flip with `normal` */
+  pub bitangent: Vec3,
   /** Ray distance from origin */
   pub t: f32,
   /** Texture coordinate, x-axis */
@@ -20,6 +34,8 @@ pub struct RayHit {
   pub v: f32,
   /** Is front face */
   pub front_face: bool,
+  /** Interpolated per-vertex color, only for meshes that have them */
+  pub vertex_color: Option<Color>,
   pub material: Arc<dyn Material>,
 }
 
@@ -29,6 +45,28 @@ impl RayHit {
     !self.t.is_nan()
   }
 
+  /**
+  Local space of BSDFs: `normal` is +z and +x follows `tangent`, so anisotropic
+  materials are stretched along the texture's u axis.
+  */
+  pub fn shading_frame(&self) -> Onb {
+    Onb::from_w_u(self.normal, self.tangent)
+  }
+
+  /**
+  Ray leaving the surface in direction `dir`. Starts a bit above (or below, for refraction)
+  the actual surface, so it does not hit it again. Shading normal can't be used for
+  that, it does not match the geometry.
+  */
+  pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+    let offset = if dir.dot(self.geometric_normal) >= 0.0 {
+      RAY_OFFSET
+    } else {
+      -RAY_OFFSET
+    };
+    Ray::new(self.p + self.geometric_normal * offset, dir)
+  }
+
   pub fn check_is_front_face(r: &Ray, outward_normal: Vec3) -> (bool, Vec3) {
     let is_front_face = r.dir.dot(outward_normal) < 0.0;
     if is_front_face {
@@ -43,4 +81,34 @@ impl RayHit {
 pub trait Traceable: Send + Sync {
   fn check_intersection(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<RayHit>;
   fn bounding_box(&self) -> Option<AABB>;
+
+  /** Emits light and supports `sample_direction`, so it can be used for direct light sampling */
+  fn is_light(&self) -> bool {
+    false
+  }
+
+  /**
+  For objects that contain other objects (BVH, transforms etc.). Returns all lights inside,
+  wrapped so that they are in the same space as this object.
+  */
+  fn lights(&self) -> WorldObjectsList {
+    Vec::new()
+  }
+
+  /**
+  Pick random point on the object, as seen from `origin`. Returns vector from `origin`
+  to that point (not normalized) and probability density of that direction wrt. solid angle.
+  Only needed if `is_light` returns true.
+  */
+  fn sample_direction(&self, _origin: Point3d) -> Option<(Vec3, f32)> {
+    None
+  }
+
+  /**
+  Probability density that `sample_direction(r.origin)` picks the first point where `r`
+  hits this object between `t_min` and `t_max`. 0 if there is no such point.
+  */
+  fn direction_pdf(&self, _r: &Ray, _t_min: f32, _t_max: f32) -> f32 {
+    0.0
+  }
 }
@@ -4,7 +4,8 @@ use std::sync::Arc;
 use crate::aabb::AABB;
 use crate::ray::Ray;
 use crate::traceable::{RayHit, Traceable};
-use crate::vec3::Point3d;
+use crate::vec3::{Point3d, Vec3};
+use crate::world::WorldObjectsList;
 
 // The book shows the math for rotation around Y axis with sines and cosines.
 // I'm not gonna pretend that I don't know the solution, so here
@@ -25,22 +26,19 @@ pub struct Transform {
 }
 
 impl Transform {
-  /*
-  // TODO finish this.
+  /**
+  `transform` moves the object from its local space into world space.
+  Unlike `from_transform_rot`, it can also contain scale.
+  */
   pub fn new(transform: Mat4, object: Arc<dyn Traceable>) -> Self {
-    let aabb = Transform::calc_bounding_box(transform.inverse(), object.clone());
-    // let aabb = Transform::calc_bounding_box(transform, object.clone());
+    let aabb = Transform::calc_bounding_box(transform, object.clone());
     Transform {
-      object: object.clone(),
-      // transform,
-      // transform_inverse: transform.inverse(),
-      // aabb: Transform::calc_bounding_box(transform.inverse(), object),
+      object,
       transform: transform.inverse(), // we manipulate ray, not the object. So moving object right 5u is same as movin ray -5u
       transform_inverse: transform,
       aabb,
     }
   }
-  */
 
   pub fn from_transform_rot(
     mat3: glam::f32::Mat3,
@@ -64,6 +62,11 @@ impl Transform {
     }
   }
 
+  /** Matrix that moves things from object's local space into world space */
+  pub fn object_to_world(&self) -> Mat4 {
+    self.transform_inverse
+  }
+
   fn calc_bounding_box(
     transform: Mat4, //
     object: Arc<dyn Traceable>,
@@ -91,13 +94,13 @@ impl Traceable for Transform {
 
   fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
     let mat = self.transform;
-    // Rotation matrix from our transform. Assumes no scale/shear. Our ray direction is unit vector
-    // expressing rotation. It should not be translated, only rotated.
+    // Linear part (rotation/scale) of our transform. Our ray direction is a vector,
+    // it should not be translated, only rotated/scaled.
     let rot = glam::f32::Mat3::from_mat4(mat);
     // Express ray from world space into object space (by using matrix)
     let offseted_ray = Ray {
       origin: r.origin.transform_mat4(mat),
-      dir: r.dir.transform_mat3(rot), // do not normalize! Tho do not matter that much if we only rotate
+      dir: r.dir.transform_mat3(rot), // do not normalize! Then `t` is the same in both spaces
     };
 
     let result = self.object.check_intersection(&offseted_ray, t_min, t_max);
@@ -108,16 +111,73 @@ impl Traceable for Transform {
         // TBH we could probably just do `hit.p = r.at(hit.t)`
         hit.p = hit.p.transform_mat4(self.transform_inverse);
 
-        // this is.. complicated. I've followed the book, but:
-        // 1. I think this may deform normals (inverse for normals was not just a usuall inverse)
-        // 2. Recalc if front face - I don't think it is needed.
-        let normal = hit.normal.transform_mat3(rot.inverse()).unit_vector();
-        // transform normal from object into world space. Again, only rotation is applied,
-        // since normal is unit-length vector
-        hit.normal = normal;
+        // Normals are transformed by inverse transpose of object->world matrix.
+        // `rot` is already inverse of that, so just transpose. For pure rotation
+        // it's the same as inverse. Front face does not change, since dot product
+        // between ray and normal keeps the sign.
+        let normal_mat = rot.transpose();
+        hit.normal = hit.normal.transform_mat3(normal_mat).unit_vector();
+        hit.geometric_normal = hit
+          .geometric_normal
+          .transform_mat3(normal_mat)
+          .unit_vector();
+
+        // tangents are just directions on the surface, they move with it
+        let to_world = glam::f32::Mat3::from_mat4(self.transform_inverse);
+        hit.tangent = hit.tangent.transform_mat3(to_world);
+        hit.bitangent = hit.bitangent.transform_mat3(to_world);
 
         Some(hit)
       }
     }
   }
+
+  fn is_light(&self) -> bool {
+    self.object.is_light()
+  }
+
+  fn lights(&self) -> WorldObjectsList {
+    self
+      .object
+      .lights()
+      .into_iter()
+      .map(|light| {
+        Arc::new(Transform {
+          transform: self.transform,
+          transform_inverse: self.transform_inverse,
+          aabb: Transform::calc_bounding_box(self.transform_inverse, light.clone()),
+          object: light,
+        }) as Arc<dyn Traceable>
+      })
+      .collect()
+  }
+
+  /**
+  Sample in object space and move the result into world space. Linear transform
+  stretches the directions, so the solid angle density has to be adjusted.
+  For `d = L * d_local / |L * d_local|` it's `pdf = pdf_local * |L * d_local|³ / det(L)`
+  (with `d_local` normalized).
+  */
+  fn sample_direction(&self, origin: Point3d) -> Option<(Vec3, f32)> {
+    let local_origin = origin.transform_mat4(self.transform);
+    let (local_dir, local_pdf) = self.object.sample_direction(local_origin)?;
+
+    let to_world = glam::f32::Mat3::from_mat4(self.transform_inverse);
+    let dir = local_dir.transform_mat3(to_world);
+    let stretch = dir.length() / local_dir.length();
+    let pdf = local_pdf * stretch.powi(3) / to_world.determinant().abs();
+    Some((dir, pdf))
+  }
+
+  /** Same density conversion as in `sample_direction`, `t` is the same in both spaces */
+  fn direction_pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    let rot = glam::f32::Mat3::from_mat4(self.transform);
+    let local_ray = Ray {
+      origin: r.origin.transform_mat4(self.transform),
+      dir: r.dir.transform_mat3(rot),
+    };
+    let local_pdf = self.object.direction_pdf(&local_ray, t_min, t_max);
+    let stretch = r.dir.length() / local_ray.dir.length();
+    local_pdf * stretch.powi(3) * rot.determinant().abs()
+  }
 }
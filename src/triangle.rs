@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use crate::aabb::AABB;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::traceable::{RayHit, Traceable};
+use crate::utils::area_pdf_to_solid_angle;
+use crate::vec3::{Point3d, Vec3};
+
+/** Triangles are flat, so we pad the AABB a bit. Same as `Rectangle` */
+const AABB_PADDING: f32 = 0.0001;
+
+/**
+Möller–Trumbore ray-triangle intersection. Returns `(t, b1, b2)`, where `b1`, `b2`
+are barycentric coordinates of the hit wrt. `v1` and `v2`. Weight of `v0` is `1 - b1 - b2`.
+
+https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+*/
+pub fn intersect_triangle(
+  r: &Ray,
+  v0: Point3d,
+  v1: Point3d,
+  v2: Point3d,
+  t_min: f32,
+  t_max: f32,
+) -> Option<(f32, f32, f32)> {
+  let edge1 = v1 - v0;
+  let edge2 = v2 - v0;
+  let pvec = r.dir.cross(edge2);
+  let det = edge1.dot(pvec);
+  // ray is parallel to the triangle's plane
+  if det.abs() < 1e-10 {
+    return None;
+  }
+  let inv_det = 1.0 / det;
+
+  let tvec = r.origin - v0;
+  let b1 = tvec.dot(pvec) * inv_det;
+  if !(0.0..=1.0).contains(&b1) {
+    return None;
+  }
+
+  let qvec = tvec.cross(edge1);
+  let b2 = r.dir.dot(qvec) * inv_det;
+  if b2 < 0.0 || b1 + b2 > 1.0 {
+    return None;
+  }
+
+  let t = edge2.dot(qvec) * inv_det;
+  if t < t_min || t > t_max {
+    return None;
+  }
+  Some((t, b1, b2))
+}
+
+/** AABB of 3 points, padded so that it is never flat */
+pub fn triangle_bounding_box(v0: Point3d, v1: Point3d, v2: Point3d) -> AABB {
+  let aabb = AABB::from_point_cloud(&[v0, v1, v2]);
+  AABB {
+    min: aabb.min - Vec3::uni(AABB_PADDING),
+    max: aabb.max + Vec3::uni(AABB_PADDING),
+  }
+}
+
+/** Uniformly distributed random point on the triangle */
+pub fn sample_triangle(v0: Point3d, v1: Point3d, v2: Point3d) -> Point3d {
+  let su = rand::random::<f32>().sqrt();
+  let b1 = 1.0 - su;
+  let b2 = rand::random::<f32>() * su;
+  v0 * (1.0 - b1 - b2) + v1 * b1 + v2 * b2
+}
+
+#[derive(Clone, Debug)]
+/** Single triangle. For many triangles that share vertices use `TriangleMesh` */
+pub struct Triangle {
+  pub v0: Point3d,
+  pub v1: Point3d,
+  pub v2: Point3d,
+  pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+  pub fn new(v0: Point3d, v1: Point3d, v2: Point3d, material: Arc<dyn Material>) -> Self {
+    Self {
+      v0,
+      v1,
+      v2,
+      material,
+    }
+  }
+}
+
+impl Traceable for Triangle {
+  fn bounding_box(&self) -> Option<AABB> {
+    Some(triangle_bounding_box(self.v0, self.v1, self.v2))
+  }
+
+  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    let (t, b1, b2) = intersect_triangle(r, self.v0, self.v1, self.v2, t_min, t_max)?;
+
+    // counter-clockwise winding is the front face
+    let normal = (self.v1 - self.v0).cross(self.v2 - self.v0).unit_vector();
+    let (front_face, outward_normal) = RayHit::check_is_front_face(r, normal);
+    Some(RayHit {
+      p: r.at(t),
+      t,
+      // no texture coordinates, so barycentrics will have to do
+      u: b1,
+      v: b2,
+      normal: outward_normal,
+      geometric_normal: outward_normal,
+      tangent: self.v1 - self.v0,
+      bitangent: self.v2 - self.v0,
+      front_face,
+      vertex_color: None,
+      material: self.material.clone(),
+    })
+  }
+
+  fn is_light(&self) -> bool {
+    self.material.is_emissive()
+  }
+
+  fn sample_direction(&self, origin: Point3d) -> Option<(Vec3, f32)> {
+    let cross = (self.v1 - self.v0).cross(self.v2 - self.v0);
+    let area = cross.length() * 0.5;
+    let to_point = sample_triangle(self.v0, self.v1, self.v2) - origin;
+    let pdf = area_pdf_to_solid_angle(1.0 / area, to_point, cross.unit_vector());
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some((to_point, pdf))
+  }
+
+  fn direction_pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    match self.check_intersection(r, t_min, t_max) {
+      Some(hit) => {
+        let cross = (self.v1 - self.v0).cross(self.v2 - self.v0);
+        let area = cross.length() * 0.5;
+        area_pdf_to_solid_angle(1.0 / area, hit.p - r.origin, cross.unit_vector())
+      }
+      None => 0.0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assert_approx_eq::assert_approx_eq;
+
+  fn tri() -> (Point3d, Point3d, Point3d) {
+    (
+      Point3d::new(0.0, 0.0, 0.0),
+      Point3d::new(1.0, 0.0, 0.0),
+      Point3d::new(0.0, 1.0, 0.0),
+    )
+  }
+
+  #[test]
+  fn hit() {
+    let (v0, v1, v2) = tri();
+    let r = Ray::new(Point3d::new(0.25, 0.5, 2.0), Vec3::new(0.0, 0.0, -1.0));
+    let (t, b1, b2) = intersect_triangle(&r, v0, v1, v2, 0.0, f32::INFINITY).unwrap();
+    assert_approx_eq!(t, 2.0);
+    assert_approx_eq!(b1, 0.25);
+    assert_approx_eq!(b2, 0.5);
+  }
+
+  #[test]
+  fn hit_from_behind() {
+    let (v0, v1, v2) = tri();
+    let r = Ray::new(Point3d::new(0.25, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0));
+    let (t, _, _) = intersect_triangle(&r, v0, v1, v2, 0.0, f32::INFINITY).unwrap();
+    assert_approx_eq!(t, 1.0);
+  }
+
+  #[test]
+  fn miss_outside() {
+    let (v0, v1, v2) = tri();
+    let r = Ray::new(Point3d::new(0.75, 0.75, 2.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(intersect_triangle(&r, v0, v1, v2, 0.0, f32::INFINITY).is_none());
+  }
+
+  #[test]
+  fn miss_parallel() {
+    let (v0, v1, v2) = tri();
+    let r = Ray::new(Point3d::new(-1.0, 0.25, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    assert!(intersect_triangle(&r, v0, v1, v2, 0.0, f32::INFINITY).is_none());
+  }
+
+  #[test]
+  fn miss_out_of_range() {
+    let (v0, v1, v2) = tri();
+    let r = Ray::new(Point3d::new(0.25, 0.25, 2.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(intersect_triangle(&r, v0, v1, v2, 0.0, 1.0).is_none());
+  }
+}
@@ -0,0 +1,391 @@
+use std::sync::Arc;
+
+use crate::aabb::AABB;
+use crate::bvh::BVH;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::traceable::{RayHit, Traceable};
+use crate::triangle::{intersect_triangle, sample_triangle, triangle_bounding_box};
+use crate::utils::area_pdf_to_solid_angle;
+use crate::vec3::{Color, Point3d, Vec3};
+use crate::world::WorldObjectsList;
+
+#[derive(Clone, Debug, Default)]
+/**
+Vertex buffers of the mesh. `normals`, `uvs` and `colors` are optional, but if provided
+they have to have the same length as `positions`.
+*/
+pub struct MeshData {
+  pub positions: Vec<Point3d>,
+  /** Per-vertex normals for smooth shading. Flat shading if empty */
+  pub normals: Vec<Vec3>,
+  /** Per-vertex texture coordinates. Barycentrics are used if empty */
+  pub uvs: Vec<(f32, f32)>,
+  /** Per-vertex colors. Available to materials through `VertexColorTex` */
+  pub colors: Vec<Color>,
+  /** 3 vertex indices per triangle. Counter-clockwise winding is the front face */
+  pub indices: Vec<[u32; 3]>,
+}
+
+impl MeshData {
+  /** Check if indices and optional buffers are consistent with positions */
+  pub fn validate(&self) -> Result<(), String> {
+    let vertex_count = self.positions.len();
+    if !self.normals.is_empty() && self.normals.len() != vertex_count {
+      return Err(format!(
+        "Mesh has {} normals, expected {}",
+        self.normals.len(),
+        vertex_count
+      ));
+    }
+    if !self.uvs.is_empty() && self.uvs.len() != vertex_count {
+      return Err(format!(
+        "Mesh has {} texture coordinates, expected {}",
+        self.uvs.len(),
+        vertex_count
+      ));
+    }
+    if !self.colors.is_empty() && self.colors.len() != vertex_count {
+      return Err(format!(
+        "Mesh has {} vertex colors, expected {}",
+        self.colors.len(),
+        vertex_count
+      ));
+    }
+    let out_of_range = self
+      .indices
+      .iter()
+      .flatten()
+      .find(|&&idx| idx as usize >= vertex_count);
+    if let Some(idx) = out_of_range {
+      return Err(format!(
+        "Mesh index {} out of range, there are only {} vertices",
+        idx, vertex_count
+      ));
+    }
+    Ok(())
+  }
+
+  fn vertices(&self, face_idx: usize) -> (Point3d, Point3d, Point3d) {
+    let [i0, i1, i2] = self.indices[face_idx];
+    (
+      self.positions[i0 as usize],
+      self.positions[i1 as usize],
+      self.positions[i2 as usize],
+    )
+  }
+}
+
+/** Single triangle of the mesh, so that we can put them into BVH */
+struct MeshTriangle {
+  mesh: Arc<MeshData>,
+  face_idx: usize,
+  material: Arc<dyn Material>,
+}
+
+impl Traceable for MeshTriangle {
+  fn bounding_box(&self) -> Option<AABB> {
+    let (v0, v1, v2) = self.mesh.vertices(self.face_idx);
+    Some(triangle_bounding_box(v0, v1, v2))
+  }
+
+  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    let mesh = &self.mesh;
+    let (v0, v1, v2) = mesh.vertices(self.face_idx);
+    let (t, b1, b2) = intersect_triangle(r, v0, v1, v2, t_min, t_max)?;
+    let b0 = 1.0 - b1 - b2;
+    let [i0, i1, i2] = self.vertex_indices();
+
+    let geometric_normal = (v1 - v0).cross(v2 - v0).unit_vector();
+    let (front_face, mut normal) = RayHit::check_is_front_face(r, geometric_normal);
+    if !mesh.normals.is_empty() {
+      let n = mesh.normals[i0] * b0 + mesh.normals[i1] * b1 + mesh.normals[i2] * b2;
+      let n = n.unit_vector();
+      normal = if front_face { n } else { !n };
+    }
+
+    let (u, v) = if mesh.uvs.is_empty() {
+      (b1, b2)
+    } else {
+      let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+      (
+        uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+        uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+      )
+    };
+    let (tangent, bitangent) = self.tangents(v0, v1, v2);
+
+    let vertex_color = if mesh.colors.is_empty() {
+      None
+    } else {
+      Some(mesh.colors[i0] * b0 + mesh.colors[i1] * b1 + mesh.colors[i2] * b2)
+    };
+
+    Some(RayHit {
+      p: r.at(t),
+      t,
+      u,
+      v,
+      normal,
+      geometric_normal: if front_face {
+        geometric_normal
+      } else {
+        !geometric_normal
+      },
+      tangent,
+      bitangent,
+      front_face,
+      vertex_color,
+      material: self.material.clone(),
+    })
+  }
+}
+
+impl MeshTriangle {
+  fn vertex_indices(&self) -> [usize; 3] {
+    let [i0, i1, i2] = self.mesh.indices[self.face_idx];
+    [i0 as usize, i1 as usize, i2 as usize]
+  }
+
+  /**
+  Derivatives of the point wrt. texture coordinates, constant over the triangle.
+  Without (or with degenerate) texture coordinates, these are for barycentrics.
+  */
+  fn tangents(&self, v0: Point3d, v1: Point3d, v2: Point3d) -> (Vec3, Vec3) {
+    let (e1, e2) = (v1 - v0, v2 - v0);
+    if self.mesh.uvs.is_empty() {
+      return (e1, e2);
+    }
+    let [i0, i1, i2] = self.vertex_indices();
+    let uvs = &self.mesh.uvs;
+    let (du1, dv1) = (uvs[i1].0 - uvs[i0].0, uvs[i1].1 - uvs[i0].1);
+    let (du2, dv2) = (uvs[i2].0 - uvs[i0].0, uvs[i2].1 - uvs[i0].1);
+    let det = du1 * dv2 - dv1 * du2;
+    if det.abs() < 1e-12 {
+      return (e1, e2);
+    }
+    // e1 = du1 * dpdu + dv1 * dpdv, same for e2
+    let dpdu = (e1 * dv2 - e2 * dv1) / det;
+    let dpdv = (e2 * du1 - e1 * du2) / det;
+    (dpdu, dpdv)
+  }
+}
+
+/** Indexed triangle mesh. Has its own BVH, so can be used as a single object in the World */
+pub struct TriangleMesh {
+  mesh: Arc<MeshData>,
+  bvh: BVH,
+  material: Arc<dyn Material>,
+  /** Running sum of triangle areas, used to pick triangle when sampling the mesh as a light */
+  area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+  /** Panics if mesh data is inconsistent, use `MeshData::validate` beforehand if unsure */
+  pub fn new(mesh: MeshData, material: Arc<dyn Material>) -> Self {
+    if let Err(e) = mesh.validate() {
+      panic!("Tried to create invalid TriangleMesh: {}", e);
+    }
+
+    let mesh = Arc::new(mesh);
+    let triangles: WorldObjectsList = (0..mesh.indices.len())
+      .map(|face_idx| {
+        Arc::new(MeshTriangle {
+          mesh: mesh.clone(),
+          face_idx,
+          material: material.clone(),
+        }) as Arc<dyn Traceable>
+      })
+      .collect();
+    let bvh = BVH::from_objects(&triangles);
+
+    let mut total_area = 0.0;
+    let area_cdf = (0..mesh.indices.len())
+      .map(|face_idx| {
+        let (v0, v1, v2) = mesh.vertices(face_idx);
+        total_area += (v1 - v0).cross(v2 - v0).length() * 0.5;
+        total_area
+      })
+      .collect();
+
+    Self {
+      mesh,
+      bvh,
+      material,
+      area_cdf,
+    }
+  }
+
+  fn total_area(&self) -> f32 {
+    self.area_cdf.last().copied().unwrap_or(0.0)
+  }
+
+  #[allow(dead_code)]
+  pub fn triangle_count(&self) -> usize {
+    self.mesh.indices.len()
+  }
+}
+
+impl Traceable for TriangleMesh {
+  fn bounding_box(&self) -> Option<AABB> {
+    self.bvh.bounding_box()
+  }
+
+  fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
+    self.bvh.check_intersection(r, t_min, t_max)
+  }
+
+  fn is_light(&self) -> bool {
+    self.material.is_emissive() && self.total_area() > 0.0
+  }
+
+  /** Pick triangle with probability proportional to its area, then uniform point on it */
+  fn sample_direction(&self, origin: Point3d) -> Option<(Vec3, f32)> {
+    let total_area = self.total_area();
+    let target = rand::random::<f32>() * total_area;
+    let face_idx = self
+      .area_cdf
+      .partition_point(|&a| a <= target)
+      .min(self.area_cdf.len() - 1);
+    let (v0, v1, v2) = self.mesh.vertices(face_idx);
+
+    let to_point = sample_triangle(v0, v1, v2) - origin;
+    let normal = (v1 - v0).cross(v2 - v0).unit_vector();
+    let pdf = area_pdf_to_solid_angle(1.0 / total_area, to_point, normal);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some((to_point, pdf))
+  }
+
+  fn direction_pdf(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    match self.check_intersection(r, t_min, t_max) {
+      Some(hit) => {
+        area_pdf_to_solid_angle(1.0 / self.total_area(), hit.p - r.origin, hit.geometric_normal)
+      }
+      None => 0.0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::SolidColor;
+  use crate::vec3::Color;
+  use assert_approx_eq::assert_approx_eq;
+
+  /** Unit quad in xy plane made from 2 triangles */
+  fn quad() -> MeshData {
+    MeshData {
+      positions: vec![
+        Point3d::new(0.0, 0.0, 0.0),
+        Point3d::new(1.0, 0.0, 0.0),
+        Point3d::new(1.0, 1.0, 0.0),
+        Point3d::new(0.0, 1.0, 0.0),
+      ],
+      normals: vec![Vec3::new(0.0, 0.0, 1.0); 4],
+      uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+      colors: Vec::new(),
+      indices: vec![[0, 1, 2], [0, 2, 3]],
+    }
+  }
+
+  fn material() -> Arc<dyn Material> {
+    Arc::new(SolidColor {
+      color: Color::one(),
+    })
+  }
+
+  #[test]
+  fn interpolates_uvs() {
+    let mesh = TriangleMesh::new(quad(), material());
+    for &(x, y) in &[(0.25, 0.75), (0.75, 0.25)] {
+      let r = Ray::new(Point3d::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+      let hit = mesh.check_intersection(&r, 0.0, f32::INFINITY).unwrap();
+      assert_approx_eq!(hit.t, 1.0);
+      assert_approx_eq!(hit.u, x);
+      assert_approx_eq!(hit.v, y);
+      assert!(hit.front_face);
+      assert_approx_eq!(hit.normal.z(), 1.0);
+    }
+  }
+
+  #[test]
+  fn interpolates_vertex_colors() {
+    let mut data = quad();
+    data.colors = vec![
+      Color::new(1.0, 0.0, 0.0),
+      Color::new(0.0, 1.0, 0.0),
+      Color::new(0.0, 0.0, 1.0),
+      Color::new(0.0, 0.0, 1.0),
+    ];
+    let mesh = TriangleMesh::new(data, material());
+    let r = Ray::new(Point3d::new(0.5, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let hit = mesh.check_intersection(&r, 0.0, f32::INFINITY).unwrap();
+    let c = hit.vertex_color.unwrap();
+    assert_approx_eq!(c.x(), 0.5);
+    assert_approx_eq!(c.y(), 0.5);
+    assert_approx_eq!(c.z(), 0.0);
+  }
+
+  #[test]
+  fn back_face_flips_normal() {
+    let mesh = TriangleMesh::new(quad(), material());
+    let r = Ray::new(Point3d::new(0.5, 0.25, -1.0), Vec3::new(0.0, 0.0, 1.0));
+    let hit = mesh.check_intersection(&r, 0.0, f32::INFINITY).unwrap();
+    assert!(!hit.front_face);
+    assert_approx_eq!(hit.normal.z(), -1.0);
+    assert_approx_eq!(hit.geometric_normal.z(), -1.0);
+  }
+
+  #[test]
+  fn tangents_follow_uvs() {
+    let mut data = quad();
+    // texture is stretched along x and mirrored
+    data.uvs = vec![(2.0, 0.0), (0.0, 0.0), (0.0, 1.0), (2.0, 1.0)];
+    let mesh = TriangleMesh::new(data, material());
+    for &(x, y) in &[(0.25, 0.75), (0.75, 0.25)] {
+      let r = Ray::new(Point3d::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+      let hit = mesh.check_intersection(&r, 0.0, f32::INFINITY).unwrap();
+      assert_approx_eq!(hit.tangent.x(), -0.5);
+      assert_approx_eq!(hit.tangent.y(), 0.0);
+      assert_approx_eq!(hit.bitangent.x(), 0.0);
+      assert_approx_eq!(hit.bitangent.y(), 1.0);
+    }
+  }
+
+  #[test]
+  fn direction_pdf_matches_sample() {
+    let mut data = quad();
+    // smooth shading, normals bent away from the face normal
+    data.normals = vec![
+      Vec3::new(-0.5, -0.5, 1.0).unit_vector(),
+      Vec3::new(0.5, -0.5, 1.0).unit_vector(),
+      Vec3::new(0.5, 0.5, 1.0).unit_vector(),
+      Vec3::new(-0.5, 0.5, 1.0).unit_vector(),
+    ];
+    let mesh = TriangleMesh::new(data, material());
+    let origin = Point3d::new(-0.5, 0.3, 0.7);
+    for _ in 0..50 {
+      let (dir, pdf) = mesh.sample_direction(origin).unwrap();
+      let r = Ray::new(origin, dir);
+      assert_approx_eq!(mesh.direction_pdf(&r, 0.001, f32::INFINITY), pdf, pdf * 1e-3);
+    }
+  }
+
+  #[test]
+  fn invalid_index() {
+    let mut data = quad();
+    data.indices.push([0, 1, 4]);
+    assert!(data.validate().is_err());
+  }
+
+  #[test]
+  fn empty_mesh() {
+    let mesh = TriangleMesh::new(MeshData::default(), material());
+    let r = Ray::new(Point3d::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(mesh.bounding_box().is_none());
+    assert!(mesh.check_intersection(&r, 0.0, f32::INFINITY).is_none());
+  }
+}
@@ -82,6 +82,26 @@ pub fn random_in_disk(r: f32) -> Point3d {
   Point3d::new(r_sqrt * theta.cos(), r_sqrt * theta.sin(), 0.0)
 }
 
+/**
+Random direction inside the cone around `axis`, uniform wrt. solid angle.
+Angle between the direction and `axis` has cosine at least `cos_theta_max`.
+*/
+pub fn random_in_cone(axis: Vec3, cos_theta_max: f32) -> Vec3 {
+  let mut rng = rand::thread_rng();
+  let z = 1.0 + rng.gen::<f32>() * (cos_theta_max - 1.0);
+  let phi = rng.gen_range(0.0..(2.0 * std::f32::consts::PI));
+  let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+  Onb::from_w(axis).local(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
+/** Wavelengths used for red, green and blue channel when light is treated as a wave */
+pub const RGB_WAVELENGTHS_NM: [f32; 3] = [680.0, 550.0, 440.0];
+
+/** Perceived brightness of linear RGB color (Rec. 709 weights) */
+pub fn luminance(col: Color) -> f32 {
+  0.2126 * col.x() + 0.7152 * col.y() + 0.0722 * col.z()
+}
+
 pub fn gamma_correct(col: Color, gamma: f32) -> Color {
   Color::new(
     col.x().powf(1.0 / gamma),
@@ -89,3 +109,83 @@ pub fn gamma_correct(col: Color, gamma: f32) -> Color {
     col.z().powf(1.0 / gamma),
   )
 }
+
+/**
+Convert probability density wrt. surface area into density wrt. solid angle,
+as seen from a point `to_point` away. Returns 0 for grazing angles.
+*/
+pub fn area_pdf_to_solid_angle(area_pdf: f32, to_point: Vec3, normal: Vec3) -> f32 {
+  let dist_sq = to_point.length_squared();
+  let cos = to_point.dot(normal).abs() / dist_sq.sqrt();
+  if cos < 1e-6 {
+    return 0.0;
+  }
+  area_pdf * dist_sq / cos
+}
+
+/**
+Multiple importance sampling weight for a sample taken with density `pdf_a`, when
+the same direction could also be taken by another technique with density `pdf_b`.
+
+https://pbr-book.org/3ed-2018/Monte_Carlo_Integration/Importance_Sampling#MultipleImportanceSampling
+*/
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+  let a = pdf_a * pdf_a;
+  let b = pdf_b * pdf_b;
+  if a + b <= 0.0 {
+    return 0.0;
+  }
+  a / (a + b)
+}
+
+/**
+Orthonormal basis. `w` is usually the normal, `u` and `v` are either picked
+arbitrarily or follow the surface tangent. Used to turn directions sampled
+around +z into world space.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+  pub u: Vec3,
+  pub v: Vec3,
+  pub w: Vec3,
+}
+
+impl Onb {
+  pub fn from_w(n: Vec3) -> Onb {
+    let w = n.unit_vector();
+    // any vector that is not parallel to `w`
+    let a = if w.x().abs() > 0.9 {
+      Vec3::up()
+    } else {
+      Vec3::right()
+    };
+    let v = w.cross(a).unit_vector();
+    let u = w.cross(v);
+    Onb { u, v, w }
+  }
+
+  /**
+  `u` is `tangent` made perpendicular to `w` (Gram-Schmidt). Falls back to
+  `from_w` if the tangent is missing or parallel to the normal.
+  */
+  pub fn from_w_u(n: Vec3, tangent: Vec3) -> Onb {
+    let w = n.unit_vector();
+    let u = tangent - w * w.dot(tangent);
+    if u.length_squared() < 1e-12 {
+      return Onb::from_w(n);
+    }
+    let u = u.unit_vector();
+    let v = w.cross(u);
+    Onb { u, v, w }
+  }
+
+  /** From this basis into world space */
+  pub fn local(&self, a: f32, b: f32, c: f32) -> Vec3 {
+    self.u * a + self.v * b + self.w * c
+  }
+
+  /** From world space into this basis */
+  pub fn world_to_local(&self, v: Vec3) -> Vec3 {
+    Vec3::new(v.dot(self.u), v.dot(self.v), v.dot(self.w))
+  }
+}
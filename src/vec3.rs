@@ -53,7 +53,7 @@ impl Vec3 {
     Vec3::new(v.x, v.y, v.z)
   }
 
-  pub fn to_glam(&self) -> glam::f32::Vec3 {
+  pub fn to_glam(self) -> glam::f32::Vec3 {
     glam::f32::Vec3::new(self.x(), self.y(), self.z())
   }
 
@@ -82,6 +82,10 @@ impl Vec3 {
     is_zero(self.x()) && is_zero(self.y()) && is_zero(self.z())
   }
 
+  pub fn max_component(&self) -> Num {
+    self.x().max(self.y()).max(self.z())
+  }
+
   pub fn length(&self) -> Num {
     self.length_squared().sqrt()
   }
@@ -13,12 +13,18 @@ use crate::vec3::{Color, Vec3};
 /** ATM it's just a plane in xy space */
 #[derive(Clone)]
 pub struct Volumetric {
-  /** Not physic based, just some value. No value range either */
+  /**
+  Chance of scattering per unit of distance. Light that travelled distance `d`
+  without scattering is `exp(-density * d)` (Beer-Lambert)
+  */
   pub density: f32,
   /** Material that 'affects' the ray when it traverses this volume */
   pub phase_function: Arc<dyn Material>,
-  /** Shape of the volumetric expressed as some other shape */
-  pub shape: Arc<dyn Traceable>,
+  /**
+  Shape of the volumetric expressed as some other shape. `None` fills the whole
+  scene e.g. global fog. It has no bounding box then, see `BVH::unbounded`.
+  */
+  pub shape: Option<Arc<dyn Traceable>>,
 }
 
 impl Volumetric {
@@ -26,7 +32,7 @@ impl Volumetric {
   pub fn color(shape: Arc<dyn Traceable>, density: f32, color: Color) -> Self {
     Self {
       density,
-      shape: shape.clone(),
+      shape: Some(shape),
       phase_function: Arc::new(IsotropicMat::color(color)),
     }
   }
@@ -35,77 +41,119 @@ impl Volumetric {
   pub fn texture(shape: Arc<dyn Traceable>, density: f32, t: Arc<dyn Texture>) -> Self {
     Self {
       density,
-      shape: shape.clone(),
+      shape: Some(shape),
       phase_function: Arc::new(IsotropicMat::texture(t)),
     }
   }
+
+  /** Volume without boundary, every ray is inside */
+  pub fn everywhere(density: f32, t: Arc<dyn Texture>) -> Self {
+    Self {
+      density,
+      shape: None,
+      phase_function: Arc::new(IsotropicMat::texture(t)),
+    }
+  }
+
+  /** Ray's entry and exit points. Entry is `None` if the volume has no boundary */
+  fn ray_segment(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, Option<RayHit>)> {
+    let shape = match &self.shape {
+      Some(shape) => shape,
+      None => return Some((t_min, t_max, None)),
+    };
+    // Check where in global space we instersect with the volume. We ignore t_min, t_max.
+    // We just want to know if ray is EVER inside volume.
+    // We assume shape is convex, so there is only one pair of entry-exit points
+    let hit0 = shape.check_intersection(r, -f32::INFINITY, f32::INFINITY)?; // maybe entry
+    let hit1 = shape.check_intersection(r, hit0.t + 0.0001, f32::INFINITY)?; // maybe exit
+    // careful about NaNs, do not propagate them!
+    Some((hit0.t.max(t_min), hit1.t.min(t_max), Some(hit0)))
+  }
 }
 
 impl Traceable for Volumetric {
   fn bounding_box(&self) -> Option<AABB> {
-    self.shape.bounding_box()
+    self.shape.as_ref().and_then(|shape| shape.bounding_box())
   }
 
   fn check_intersection(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<RayHit> {
-    // Check where in global space we instersect with the volume. We ignore t_min, t_max.
-    // We just want to know if ray is EVER inside volume.
-    // We assume shape is convex, so there is only one pair of entry-exit points
-    let maybe_hit1 = self // maybe entry
-      .shape
-      .check_intersection(r, -f32::INFINITY, f32::INFINITY);
-    let maybe_both_hits = maybe_hit1
-      .map(|hit0| {
-        let maybe_hit2 = self // maybe exit
-          .shape
-          .check_intersection(r, hit0.t + 0.0001, f32::INFINITY);
-        maybe_hit2.map(|hit1| (hit0, hit1))
-      })
-      .flatten();
+    let (t0, t1, entry) = self.ray_segment(r, t_min, t_max)?;
+    // Just the usuall sanity check. floating point errors etc.
+    // Exit is searched from the entry intersection, so we do not expect this to happen.
+    // We also took max/min just before.
+    if t0 >= t1 {
+      return None;
+    }
+
+    // do not go backward from ray origin
+    let t0 = t0.max(0.0);
+    let ray_length = r.dir.length(); // e.g. transform does not normalize direction. Tho usually 1.0
+    let distance_inside_boundary = (t1 - t0) * ray_length;
+    // Free flight distance is exponentially distributed, so the ray can go arbitrarily far.
+    // Smaller density == bigger hit_distance. Means more early bailing out.
+    let mut rng = rand::thread_rng();
+    let bounce_prob: f32 = rng.gen();
+    let hit_distance = -(1.0 - bounce_prob).ln() / self.density;
+    if hit_distance > distance_inside_boundary {
+      return None;
+    }
 
-    match maybe_both_hits {
-      Some((mut hit0, mut hit1)) => {
-        hit0.t = hit0.t.max(t_min); // careful about NaNs, do not propagate them!
-        hit1.t = hit1.t.min(t_max);
-        // Just the usuall sanity check. floating point errors etc.
-        // hit1 starts tracing from hit0 intersection, so we do not expect this to happen.
-        // We also took max/min just before.
-        if hit0.t >= hit1.t {
-          return None;
-        }
+    // we traveled from 'entrance' on 'surface' of the volume into it and intersected with something.
+    let t = t0 + hit_distance / ray_length;
+    let normal = Vec3::rand_unit(); // from book: arbitrary.
+    let (u, v, tangent, bitangent) = match entry {
+      Some(hit0) => (hit0.u, hit0.v, hit0.tangent, hit0.bitangent),
+      None => (0.0, 0.0, Vec3::zero(), Vec3::zero()),
+    };
+    Some(RayHit {
+      p: r.at(t),
+      t,
+      u,
+      v,
+      material: self.phase_function.clone(),
+      // Volume describes e.g. particles suspended in the air. The ray bounces
+      // will rarely be in the same direction. TBH there are a few probability functions
+      // that describe this behavour, like Rayleigh scattering. It all depends e.g. on
+      // wavelength of light ray etc.
+      //
+      // Instead, our IsotropicMat material will ignore normal and front_face
+      // and pick bounce randomly.
+      normal,
+      geometric_normal: normal,
+      tangent,
+      bitangent,
+      front_face: true, // from book: also arbitrary
+      vertex_color: None,
+    })
+  }
+}
 
-        // do not go backward from ray origin
-        hit0.t = hit0.t.max(0.0);
-        let ray_length = r.dir.length(); // e.g. transform does not normalize direction. Tho usually 1.0
-        let distance_inside_boundary = (hit1.t - hit0.t) * ray_length;
-        // we probabilistically bail out. This is not physics based, density is just 'some' value
-        // Smaller density == bigger hit_distance. Means more early bailing out.
-        let mut rng = rand::thread_rng();
-        let bounce_prob: f32 = rng.gen();
-        let hit_distance = bounce_prob / self.density;
-        if hit_distance > distance_inside_boundary {
-          return None;
-        }
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::texture::SolidColorTex;
+  use crate::vec3::Point3d;
+  use assert_approx_eq::assert_approx_eq;
+
+  /** Fraction of rays that pass without scattering */
+  fn transmittance(volume: &Volumetric, r: &Ray, t_max: f32) -> f32 {
+    let n = 20000;
+    let passed = (0..n)
+      .filter(|_| volume.check_intersection(r, 0.0, t_max).is_none())
+      .count();
+    passed as f32 / n as f32
+  }
 
-        // we traveled from 'entrance' on 'surface' of the volume into it and intersected with something.
-        let t = hit0.t + hit_distance / ray_length;
-        Some(RayHit {
-          p: r.at(t),
-          t,
-          u: hit0.u,
-          v: hit0.v,
-          material: self.phase_function.clone(),
-          // Volume describes e.g. particles suspended in the air. The ray bounces
-          // will rarely be in the same direction. TBH there are a few probability functions
-          // that describe this behavour, like Rayleigh scattering. It all depends e.g. on
-          // wavelength of light ray etc.
-          //
-          // Instead, our IsotropicMat material will ignore normal and front_face
-          // and pick bounce randomly.
-          normal: Vec3::rand_unit(), // from book: arbitrary.
-          front_face: true,          // from book: also arbitrary
-        })
-      }
-      _ => None,
+  #[test]
+  fn fog_transmittance() {
+    let density = 0.1;
+    let fog = Volumetric::everywhere(density, Arc::new(SolidColorTex::new(1.0, 1.0, 1.0)));
+    let r = Ray::new(Point3d::zero(), Vec3::new(1.0, 2.0, 3.0));
+    for d in [5.0, 20.0, 40.0] {
+      assert_approx_eq!(transmittance(&fog, &r, d), (-density * d).exp(), 0.015);
     }
+    // nothing scatters behind the ray origin
+    let hit = fog.check_intersection(&r, 0.0, f32::INFINITY).unwrap();
+    assert!(hit.t > 0.0);
   }
 }
@@ -9,6 +9,19 @@ use crate::traceable::{RayHit, Traceable};
 /** Used to store all traceables in the world */
 pub type WorldObjectsList = Vec<Arc<dyn Traceable>>;
 
+/** All lights in the objects, including the ones nested inside BVHs, transforms etc. */
+pub fn collect_lights(objects: &[Arc<dyn Traceable>]) -> WorldObjectsList {
+  let mut result: WorldObjectsList = Vec::new();
+  for object in objects {
+    if object.is_light() {
+      result.push(object.clone());
+    } else {
+      result.extend(object.lights());
+    }
+  }
+  result
+}
+
 /** Collection of Traceable objects */
 pub struct World {
   pub objects: WorldObjectsList,
@@ -63,6 +76,10 @@ impl Traceable for World {
 
     result
   }
+
+  fn lights(&self) -> WorldObjectsList {
+    collect_lights(&self.objects)
+  }
 }
 
 impl fmt::Debug for World {